@@ -0,0 +1,486 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use ed25519_dalek::{Keypair, PublicKey};
+use rand::rngs::OsRng;
+use base64::{encode, decode};
+
+use raiap_test::structs::*;
+use raiap_test::structs::identity::*;
+use raiap_test::structs::anchor::*;
+use raiap_test::structs::stream::*;
+use raiap_test::structs::derive::Root;
+
+pub type CliResult<T> = std::result::Result<T, String>;
+
+pub const USAGE: &str = "Usage:
+  keygen <name>                                  writes <name>.key and <name>.pub
+  derive <name> --key <master key> --index <n> [--generation <card>]  writes the profile <name>.key and <name>.pub
+  card <card> --key <key> [--genesis] [--info <text>] [--master <group>]... [--slave <group>]...
+  identity new <id> --card <card>
+  identity cancel <id> --key <key> [--cosign <key>]... [--close]
+  identity renew <id> --key <key> --next <pub> [--cosign <key>]...
+  identity evolve <id> --card <card>
+  identity verify <id>
+  identity report <id>
+  identity show <id>
+  registry <id> --key <key> --rid <registry-id> --type <type> --info <text> [--del]
+  anchor new <anchor> --id <id> --key <key> --profile <key> --r <random> [--sn <n>]
+  anchor revoke <anchor> --id <id> --key <key>
+  anchor check <stream> --anchor <anchor> --profile <key> --udi <udi>
+  stream new <stream> --key <key> --udi <udi> --r <random> [--info <text>] [--master <group>]...
+  stream append <stream> --key <key> --info <text> [--del]
+  stream verify <stream> --pub <pub>
+  chain new <chain> --stream <stream>
+  chain renew <chain> --master <key> [--cosign <key>]... --prev <pub> --key <key> --udi <udi> --r <random> [--info <text>] [--group <group>]...
+  chain check <chain> --pub <pub>
+  chain report <chain> --pub <pub>
+
+A <group> is a public key file, or <m>:<pub>,<pub>,... for a group of m signers out of the listed keys.
+Key files are created with mode 0600 and never overwritten.";
+
+const FLAGS: &[&str] = &["genesis", "close", "del"];
+
+//-----------------------------------------------------------------------------------------------------------
+// Arguments
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Debug, Default)]
+pub struct Args {
+  pub positional: Vec<String>,
+  options: HashMap<String, Vec<String>>,
+  flags: HashSet<String>
+}
+
+impl Args {
+  pub fn parse(args: &[String]) -> CliResult<Self> {
+    let mut parsed = Self::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+      match arg.strip_prefix("--") {
+        None => parsed.positional.push(arg.clone()),
+        Some(name) if FLAGS.contains(&name) => { parsed.flags.insert(name.into()); },
+        Some(name) => {
+          let value = iter.next().ok_or(format!("Missing value for --{}!", name))?;
+          parsed.options.entry(name.into()).or_default().push(value.clone());
+        }
+      }
+    }
+
+    Ok(parsed)
+  }
+
+  pub fn flag(&self, name: &str) -> bool {
+    self.flags.contains(name)
+  }
+
+  pub fn opt(&self, name: &str) -> Option<&str> {
+    self.options.get(name).and_then(|values| values.last()).map(|value| value.as_str())
+  }
+
+  pub fn get(&self, name: &str) -> CliResult<&str> {
+    self.opt(name).ok_or(format!("Missing option --{}!", name))
+  }
+
+  pub fn all(&self, name: &str) -> &[String] {
+    self.options.get(name).map(|values| values.as_slice()).unwrap_or(&[])
+  }
+
+  pub fn pos(&self, index: usize) -> CliResult<&str> {
+    self.positional.get(index).map(|value| value.as_str()).ok_or_else(|| USAGE.to_string())
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Files
+//-----------------------------------------------------------------------------------------------------------
+fn read_keypair(path: &str) -> CliResult<Keypair> {
+  let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+  let bytes = decode(text.trim()).map_err(|_| format!("{}: invalid key encoding", path))?;
+  Keypair::from_bytes(&bytes).map_err(|_| format!("{}: invalid key", path))
+}
+
+// only readable by the owner, an existing key is never overwritten
+fn write_keypair(name: &str, keypair: &Keypair) -> CliResult<()> {
+  create(&format!("{}.key", name), 0o600, encode(&keypair.to_bytes()[..]).as_bytes())?;
+  create(&format!("{}.pub", name), 0o644, encode(keypair.public.as_bytes()).as_bytes())
+}
+
+fn create(path: &str, mode: u32, data: &[u8]) -> CliResult<()> {
+  let mut file = OpenOptions::new().write(true).create_new(true).mode(mode).open(path)
+    .map_err(|e| format!("{}: {}", path, e))?;
+  file.write_all(data).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn read_public(path: &str) -> CliResult<PublicKey> {
+  let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+  let bytes = decode(text.trim()).map_err(|_| format!("{}: invalid key encoding", path))?;
+  PublicKey::from_bytes(&bytes).map_err(|_| format!("{}: invalid public key", path))
+}
+
+fn read<T: DeserializeOwned>(path: &str) -> CliResult<T> {
+  let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+  bincode::deserialize(&bytes).map_err(|_| format!("{}: invalid file content", path))
+}
+
+fn write<T: Serialize>(path: &str, value: &T) -> CliResult<()> {
+  // This unwrap() should never fail, or it's a serious code bug!
+  let bytes = bincode::serialize(value).unwrap();
+  fs::write(path, bytes).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn groups(args: &Args, master: &str, slave: Option<&str>) -> CliResult<Vec<TLGroup>> {
+  let mut groups = Vec::<TLGroup>::new();
+  for value in args.all(master).iter() {
+    groups.push(group(TLType::MASTER, value)?);
+  }
+
+  if let Some(slave) = slave {
+    for value in args.all(slave).iter() {
+      groups.push(group(TLType::SLAVE, value)?);
+    }
+  }
+
+  Ok(groups)
+}
+
+// <pub> or <m>:<pub>,<pub>,...
+fn group(typ: TLType, value: &str) -> CliResult<TLGroup> {
+  let (threshold, paths) = match value.split_once(':') {
+    None => return Ok(TLGroup::new(typ, &read_public(value)?)),
+    Some((threshold, paths)) => (threshold.parse::<usize>().map_err(|_| format!("{}: invalid threshold", value))?, paths)
+  };
+
+  let keys = paths.split(',').map(read_public).collect::<CliResult<Vec<PublicKey>>>()?;
+  TLGroup::multi(typ, threshold, &keys).map_err(fail)
+}
+
+fn oper(args: &Args) -> OType {
+  if args.flag("del") { OType::DEL } else { OType::SET }
+}
+
+fn fail<E: ToString>(err: E) -> String {
+  err.to_string()
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Commands
+//-----------------------------------------------------------------------------------------------------------
+pub fn run(args: &[String]) -> CliResult<String> {
+  let args = Args::parse(args)?;
+  match (args.pos(0)?, args.positional.get(1).map(|cmd| cmd.as_str())) {
+    ("keygen", _) => keygen(&args),
+    ("derive", _) => derive(&args),
+    ("card", _) => card(&args),
+    ("identity", Some(cmd)) => identity(cmd, &args),
+    ("registry", _) => registry(&args),
+    ("anchor", Some(cmd)) => anchor(cmd, &args),
+    ("stream", Some(cmd)) => stream(cmd, &args),
+    ("chain", Some(cmd)) => chain(cmd, &args),
+    _ => Err(USAGE.into())
+  }
+}
+
+fn keygen(args: &Args) -> CliResult<String> {
+  let name = args.pos(1)?;
+  let mut csprng = OsRng{};
+  let keypair = Keypair::generate(&mut csprng);
+
+  write_keypair(name, &keypair)?;
+  Ok(format!("KEY: {}", commit(&keypair.public)))
+}
+
+fn derive(args: &Args) -> CliResult<String> {
+  let name = args.pos(1)?;
+  let keypair = read_keypair(args.get("key")?)?;
+  let index = args.get("index")?.parse::<u32>().map_err(fail)?;
+  let generation = args.opt("generation").unwrap_or("0").parse::<u32>().map_err(fail)?;
+
+  let profile = Root::new(&keypair).map_err(fail)?.profile_at(generation, index);
+  write_keypair(name, &profile.keypair)?;
+  Ok(format!("R: {}", profile.r))
+}
+
+fn card(args: &Args) -> CliResult<String> {
+  let path = args.pos(1)?;
+  let keypair = read_keypair(args.get("key")?)?;
+  let info = args.opt("info").unwrap_or("");
+
+  let card = Card::new(args.flag("genesis"), &keypair, info.as_bytes(), &groups(args, "master", Some("slave"))?).map_err(fail)?;
+  write(path, &card)?;
+  Ok(format!("CARD: {}", path))
+}
+
+fn identity(cmd: &str, args: &Args) -> CliResult<String> {
+  let path = args.pos(2)?;
+  if cmd == "new" {
+    let identity = Identity::new(read(args.get("card")?)?).map_err(fail)?;
+    write(path, &identity)?;
+    return Ok(format!("UDI: {}", identity.udi))
+  }
+
+  let mut identity: Identity = read(path)?;
+  match cmd {
+    "cancel" => {
+      let keypair = read_keypair(args.get("key")?)?;
+      let mut cancel = Cancel::new(args.flag("close"), &keypair, identity.prev().map_err(fail)?).map_err(fail)?;
+      for path in args.all("cosign").iter() {
+        cancel.cosign(&read_keypair(path)?).map_err(fail)?;
+      }
+
+      identity.cancel(cancel).map_err(fail)?;
+    },
+
+    "renew" => {
+      let keypair = read_keypair(args.get("key")?)?;
+      let next = read_public(args.get("next")?)?;
+      let mut renew = Renew::new(&keypair, &next, identity.prev().map_err(fail)?, identity.is_enabled()).map_err(fail)?;
+      for path in args.all("cosign").iter() {
+        renew.cosign(&read_keypair(path)?).map_err(fail)?;
+      }
+
+      identity.renew(renew).map_err(fail)?;
+    },
+
+    "evolve" => identity.evolve(read(args.get("card")?)?).map_err(fail)?,
+
+    "verify" => {
+      identity.verify_full().map_err(fail)?;
+      return Ok(format!("VALID: {}", identity.udi))
+    },
+
+    "report" => return Ok(identity.report().to_json()),
+
+    "show" => return Ok(format!("{:#?}", identity)),
+    _ => return Err(USAGE.into())
+  }
+
+  write(path, &identity)?;
+  Ok(format!("UDI: {} (enabled: {})", identity.udi, identity.is_enabled()))
+}
+
+fn registry(args: &Args) -> CliResult<String> {
+  let path = args.pos(1)?;
+  let mut identity: Identity = read(path)?;
+  let keypair = read_keypair(args.get("key")?)?;
+  let id = args.get("rid")?;
+
+  identity.append(&keypair, id, args.get("type")?, oper(args), args.get("info")?.as_bytes()).map_err(fail)?;
+  write(path, &identity)?;
+  Ok(format!("REGISTRY: {} ({} entries)", id, identity.registry(id).unwrap().len()))
+}
+
+fn anchor(cmd: &str, args: &Args) -> CliResult<String> {
+  let path = args.pos(2)?;
+  match cmd {
+    "new" => {
+      let id_path = args.get("id")?;
+      let mut identity: Identity = read(id_path)?;
+      let keypair = read_keypair(args.get("key")?)?;
+      let profile = read_keypair(args.get("profile")?)?;
+      let sn = match args.opt("sn") {
+        None => Anchors::of(&identity).map_err(fail)?.next_sn(),
+        Some(sn) => sn.parse::<usize>().map_err(fail)?
+      };
+
+      let anchor = Anchor::new(&profile, &identity.udi, args.get("r")?, sn).map_err(fail)?;
+      identity.append(&keypair, DOMAIN, TYPE, OType::SET, &anchor.to_bytes()).map_err(fail)?;
+      Anchors::of(&identity).map_err(fail)?;
+      write(id_path, &identity)?;
+      write(path, &anchor)?;
+      Ok(format!("AL: {} (sn: {})", anchor.al, anchor.sn))
+    },
+
+    "revoke" => {
+      let id_path = args.get("id")?;
+      let mut identity: Identity = read(id_path)?;
+      let keypair = read_keypair(args.get("key")?)?;
+      let anchor: Anchor = read(path)?;
+
+      identity.append(&keypair, DOMAIN, TYPE, OType::DEL, &anchor.to_bytes()).map_err(fail)?;
+      Anchors::of(&identity).map_err(fail)?;
+      write(id_path, &identity)?;
+      Ok(format!("REVOKED: {}", anchor.al))
+    },
+
+    "check" => {
+      let stream: Stream = read(path)?;
+      let anchor: Anchor = read(args.get("anchor")?)?;
+      let profile = read_keypair(args.get("profile")?)?;
+      let udi = args.get("udi")?;
+
+      let asi_sig = Stream::asi_signature(&profile, udi, &anchor.r).map_err(fail)?;
+      match stream.check_asi(udi, &anchor.r, &profile.public, &asi_sig) {
+        true => Ok(format!("CONNECTED: {}", stream.asi)),
+        false => Err(format!("NOT-CONNECTED: {}", stream.asi))
+      }
+    },
+
+    _ => Err(USAGE.into())
+  }
+}
+
+fn stream(cmd: &str, args: &Args) -> CliResult<String> {
+  let path = args.pos(2)?;
+  match cmd {
+    "new" => {
+      let keypair = read_keypair(args.get("key")?)?;
+      let genesis = Record { oper: OType::SET, info: args.opt("info").unwrap_or("").as_bytes().to_vec() };
+      let stream = Stream::new(&keypair, args.get("udi")?, args.get("r")?, &groups(args, "master", None)?, genesis, None).map_err(fail)?;
+      write(path, &stream)?;
+      Ok(format!("ASI: {}", stream.asi))
+    },
+
+    "append" => {
+      let mut stream: Stream = read(path)?;
+      let keypair = read_keypair(args.get("key")?)?;
+      let record = Record { oper: oper(args), info: args.get("info")?.as_bytes().to_vec() };
+      let block = StreamBlock::new(&keypair, record, stream.prev()).map_err(fail)?;
+      stream.save(block).map_err(fail)?;
+      write(path, &stream)?;
+      Ok(format!("ASI: {} ({} blocks)", stream.asi, stream.blocks.len()))
+    },
+
+    "verify" => {
+      let stream: Stream = read(path)?;
+      stream.verify_stream(&read_public(args.get("pub")?)?).map_err(fail)?;
+      Ok(format!("VALID: {}", stream.asi))
+    },
+
+    _ => Err(USAGE.into())
+  }
+}
+
+fn chain(cmd: &str, args: &Args) -> CliResult<String> {
+  let path = args.pos(2)?;
+  match cmd {
+    "new" => {
+      let chain = Chain::new(read(args.get("stream")?)?);
+      write(path, &chain)?;
+      Ok(format!("ASI: {}", chain.current().asi))
+    },
+
+    "renew" => {
+      let mut chain: Chain = read(path)?;
+      let master = read_keypair(args.get("master")?)?;
+      let prev = read_public(args.get("prev")?)?;
+      let keypair = read_keypair(args.get("key")?)?;
+
+      let mut renew = Renew::new(&master, &keypair.public, chain.current().prev(), true).map_err(fail)?;
+      for path in args.all("cosign").iter() {
+        renew.cosign(&read_keypair(path)?).map_err(fail)?;
+      }
+
+      let ext_renew = ExtRenew::new(renew, &prev);
+      let genesis = Record { oper: OType::SET, info: args.opt("info").unwrap_or("").as_bytes().to_vec() };
+      let stream = Stream::new(&keypair, args.get("udi")?, args.get("r")?, &groups(args, "group", None)?, genesis, Some(ext_renew)).map_err(fail)?;
+      chain.save(stream).map_err(fail)?;
+      write(path, &chain)?;
+      Ok(format!("ASI: {} ({} streams)", chain.current().asi, chain.streams().len()))
+    },
+
+    "check" => {
+      let chain: Chain = read(path)?;
+      chain.check(&read_public(args.get("pub")?)?).map_err(fail)?;
+      Ok(format!("VALID: {}", chain.current().asi))
+    },
+
+    "report" => {
+      let chain: Chain = read(path)?;
+      Ok(chain.report(&read_public(args.get("pub")?)?).to_json())
+    },
+
+    _ => Err(USAGE.into())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::os::unix::fs::PermissionsExt;
+  use rand::RngCore;
+
+  fn run_in(dir: &str, cmd: &str) -> CliResult<String> {
+    let args: Vec<String> = cmd.split_whitespace()
+      .map(|arg| arg.replace("@", dir))
+      .collect();
+
+    run(&args)
+  }
+
+  #[test]
+  fn parse_args() {
+    let args: Vec<String> = "card c.bin --genesis --master a.pub --master b.pub --info x".split_whitespace().map(String::from).collect();
+    let args = Args::parse(&args).unwrap();
+
+    assert!(args.positional == vec!["card", "c.bin"]);
+    assert!(args.flag("genesis"));
+    assert!(args.all("master") == ["a.pub", "b.pub"]);
+    assert!(args.opt("info") == Some("x"));
+    assert!(args.get("key") == Err("Missing option --key!".into()));
+  }
+
+  #[test]
+  fn identity_lifecycle() {
+    let mut csprng = OsRng{};
+    let dir = std::env::temp_dir().join(format!("raiap-cli-{}", csprng.next_u64()));
+    fs::create_dir_all(&dir).unwrap();
+    let dir = dir.to_str().unwrap();
+
+    for name in ["master", "master2", "master3", "id1", "id2", "profile1", "profile2"].iter() {
+      run_in(dir, &format!("keygen @/{}", name)).unwrap();
+    }
+
+    // keys are private and never overwritten
+    let mode = fs::metadata(format!("{}/id1.key", dir)).unwrap().permissions().mode();
+    assert!(mode & 0o777 == 0o600);
+    let key = fs::read(format!("{}/id1.key", dir)).unwrap();
+    assert!(run_in(dir, "keygen @/id1").is_err());
+    assert!(fs::read(format!("{}/id1.key", dir)).unwrap() == key);
+
+    // identity with a 2 of 3 master group
+    run_in(dir, "card @/card1.bin --key @/id1.key --genesis --info first --master 2:@/master.pub,@/master2.pub,@/master3.pub").unwrap();
+    let udi = run_in(dir, "identity new @/id.bin --card @/card1.bin").unwrap().replace("UDI: ", "");
+    run_in(dir, "registry @/id.bin --key @/id1.key --rid idp.io/test --type test --info one").unwrap();
+
+    assert!(run_in(dir, "identity cancel @/id.bin --key @/master.key").is_err());
+    run_in(dir, "identity cancel @/id.bin --key @/master.key --cosign @/master3.key").unwrap();
+    run_in(dir, "identity renew @/id.bin --key @/master.key --cosign @/master2.key --next @/id2.pub").unwrap();
+    run_in(dir, "card @/card2.bin --key @/id2.key --info second --master @/master.pub").unwrap();
+    run_in(dir, "identity evolve @/id.bin --card @/card2.bin").unwrap();
+    run_in(dir, "registry @/id.bin --key @/id2.key --rid idp.io/test --type test --info two").unwrap();
+    assert!(run_in(dir, "identity verify @/id.bin").unwrap() == format!("VALID: {}", udi));
+    assert!(run_in(dir, "identity report @/id.bin").unwrap().contains("\"failure\": null"));
+
+    // anchored profile stream
+    run_in(dir, "anchor new @/anchor.bin --id @/id.bin --key @/id2.key --profile @/profile1.key --r r1").unwrap();
+    run_in(dir, &format!("stream new @/stream1.bin --key @/profile1.key --udi {} --r r1 --master @/master.pub", udi)).unwrap();
+    run_in(dir, "stream append @/stream1.bin --key @/profile1.key --info block").unwrap();
+    run_in(dir, "stream verify @/stream1.bin --pub @/profile1.pub").unwrap();
+    run_in(dir, &format!("anchor check @/stream1.bin --anchor @/anchor.bin --profile @/profile1.key --udi {}", udi)).unwrap();
+    assert!(run_in(dir, "anchor new @/anchor0.bin --id @/id.bin --key @/id2.key --profile @/profile2.key --r r0 --sn 0").is_err());
+    assert!(run_in(dir, "stream verify @/stream1.bin --pub @/profile2.pub").is_err());
+
+    // chain of streams
+    run_in(dir, "chain new @/chain.bin --stream @/stream1.bin").unwrap();
+    run_in(dir, &format!("chain renew @/chain.bin --master @/master.key --prev @/profile1.pub --key @/profile2.key --udi {} --r r2", udi)).unwrap();
+    run_in(dir, "chain check @/chain.bin --pub @/profile2.pub").unwrap();
+    assert!(run_in(dir, "chain report @/chain.bin --pub @/profile1.pub").unwrap().contains("\"failure\": {"));
+    let out = run_in(dir, "anchor new @/anchor2.bin --id @/id.bin --key @/id2.key --profile @/profile2.key --r r2").unwrap();
+    assert!(out.ends_with("(sn: 1)"));
+    run_in(dir, "anchor revoke @/anchor.bin --id @/id.bin --key @/id2.key").unwrap();
+
+    // derived profile
+    let r = run_in(dir, "derive @/profile3 --key @/master.key --index 0 --generation 1").unwrap().replace("R: ", "");
+    assert!(run_in(dir, "derive @/again --key @/master.key --index 0 --generation 1").unwrap() == format!("R: {}", r));
+    assert!(run_in(dir, "derive @/other --key @/master.key --index 0").unwrap() != format!("R: {}", r));
+    assert!(fs::read(format!("{}/profile3.key", dir)).unwrap() == fs::read(format!("{}/again.key", dir)).unwrap());
+    run_in(dir, &format!("anchor new @/anchor3.bin --id @/id.bin --key @/id2.key --profile @/profile3.key --r {}", r)).unwrap();
+    assert!(run_in(dir, "anchor revoke @/anchor.bin --id @/id.bin --key @/id2.key").is_err());
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
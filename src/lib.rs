@@ -0,0 +1,2 @@
+pub mod structs;
+pub mod store;
@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Sha256, Digest};
+
+use crate::structs::{Result, Error};
+use crate::store::Store;
+
+pub const SEGMENT_SIZE: u64 = 16 * 1024 * 1024;
+
+const HEADER: usize = 8;    // key length (u32) + value length (u32)
+const CHECKSUM: usize = 8;  // first bytes of sha256(key || value)
+
+fn io(err: std::io::Error) -> Error {
+  Error::Storage { reason: err.to_string() }
+}
+
+fn checksum(key: &[u8], value: &[u8]) -> Vec<u8> {
+  let mut hasher = Sha256::new();
+  hasher.input(key);
+  hasher.input(value);
+  hasher.result()[..CHECKSUM].to_vec()
+}
+
+fn segment_path(dir: &Path, segment: u32) -> PathBuf {
+  dir.join(format!("segment-{:06}.log", segment))
+}
+
+fn index_path(dir: &Path, segment: u32) -> PathBuf {
+  dir.join(format!("segment-{:06}.idx", segment))
+}
+
+fn invalid_index(path: &Path) -> Error {
+  Error::Storage { reason: format!("Invalid index {}", path.display()) }
+}
+
+#[derive(Debug, Clone)]
+struct Location {
+  segment: u32,
+  offset: u64,
+  len: usize
+}
+
+type Record = (String, Location);
+
+//-----------------------------------------------------------------------------------------------------------
+// FileStore (append-only segment files, each full segment has a persisted index of its records)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Debug)]
+pub struct FileStore {
+  dir: PathBuf,
+  max_size: u64,
+
+  segment: u32,
+  size: u64,
+  writer: File,
+  poisoned: bool,   // a failed write couldn't be rolled back, the store must be reopened
+
+  index: HashMap<String, Vec<Location>>,
+  active: Vec<Record>   // records of the last segment, indexed when it's full
+}
+
+impl FileStore {
+  pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+    Self::with_segment_size(dir, SEGMENT_SIZE)
+  }
+
+  pub fn with_segment_size<P: AsRef<Path>>(dir: P, max_size: u64) -> Result<Self> {
+    let dir = dir.as_ref().to_path_buf();
+    fs::create_dir_all(&dir).map_err(io)?;
+
+    let mut segments = Vec::<u32>::new();
+    for entry in fs::read_dir(&dir).map_err(io)? {
+      let name = entry.map_err(io)?.file_name().to_string_lossy().to_string();
+      if let Some(num) = name.strip_prefix("segment-").and_then(|n| n.strip_suffix(".log")) {
+        segments.push(num.parse().map_err(|_| Error::Storage { reason: format!("Invalid segment name {}", name) })?);
+      }
+    }
+    segments.sort_unstable();
+
+    let mut index = HashMap::<String, Vec<Location>>::new();
+    let last = segments.last().cloned().unwrap_or(0);
+    for segment in segments.iter().filter(|segment| **segment != last) {
+      // full segments are only scanned when the index wasn't written (a crash after the rotation)
+      let records = match Self::load_index(&dir, *segment) {
+        Ok(records) => records,
+        Err(_) => {
+          let path = segment_path(&dir, *segment);
+          let (records, valid, total) = Self::scan(&path, *segment)?;
+          if valid != total {
+            return Err(Error::Storage { reason: format!("Corrupted segment {}", path.display()) })
+          }
+
+          Self::save_index(&dir, *segment, &records)?;
+          records
+        }
+      };
+
+      for (key, loc) in records {
+        index.entry(key).or_default().push(loc);
+      }
+    }
+
+    // only the last segment can have a torn write from a crash
+    let path = segment_path(&dir, last);
+    let active = match path.exists() {
+      false => Vec::new(),
+      true => {
+        let (records, valid, total) = Self::scan(&path, last)?;
+        if valid != total {
+          let file = OpenOptions::new().write(true).open(&path).map_err(io)?;
+          file.set_len(valid).map_err(io)?;
+          file.sync_all().map_err(io)?;
+        }
+
+        records
+      }
+    };
+
+    for (key, loc) in active.iter() {
+      index.entry(key.clone()).or_default().push(loc.clone());
+    }
+
+    let writer = OpenOptions::new().create(true).append(true).open(&path).map_err(io)?;
+    let size = writer.metadata().map_err(io)?.len();
+
+    Ok(Self { dir, max_size, segment: last, size, writer, poisoned: false, index, active })
+  }
+
+  // all valid records of the segment, with the (valid, total) length of the segment
+  fn scan(path: &Path, segment: u32) -> Result<(Vec<Record>, u64, u64)> {
+    let mut data = Vec::<u8>::new();
+    File::open(path).map_err(io)?.read_to_end(&mut data).map_err(io)?;
+
+    let mut records = Vec::<Record>::new();
+    let mut pos = 0usize;
+    while pos + HEADER <= data.len() {
+      // These unwrap() should never fail, or it's a serious code bug!
+      let key_len = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+      let value_len = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+
+      let end = pos + HEADER + key_len + value_len + CHECKSUM;
+      if end > data.len() {
+        break
+      }
+
+      let key = &data[pos + HEADER..pos + HEADER + key_len];
+      let value = &data[pos + HEADER + key_len..end - CHECKSUM];
+      if checksum(key, value)[..] != data[end - CHECKSUM..end] {
+        break
+      }
+
+      let key = String::from_utf8(key.to_vec()).map_err(|_| Error::Storage { reason: "Invalid record key".into() })?;
+      let offset = (pos + HEADER + key_len) as u64;
+      records.push((key, Location { segment, offset, len: value_len }));
+      pos = end;
+    }
+
+    Ok((records, pos as u64, data.len() as u64))
+  }
+
+  // the index is a list of (key length (u32), key, offset (u64), value length (u32)), followed by the checksum
+  fn save_index(dir: &Path, segment: u32, records: &[Record]) -> Result<()> {
+    let mut data = Vec::<u8>::new();
+    for (key, loc) in records.iter() {
+      data.extend(&(key.len() as u32).to_le_bytes());
+      data.extend(key.as_bytes());
+      data.extend(&loc.offset.to_le_bytes());
+      data.extend(&(loc.len as u32).to_le_bytes());
+    }
+
+    let sum = checksum(&data, &[]);
+    data.extend(sum);
+
+    // the index is complete or missing, never torn
+    let tmp = dir.join(format!("segment-{:06}.idx.tmp", segment));
+    let mut file = File::create(&tmp).map_err(io)?;
+    file.write_all(&data).and_then(|_| file.sync_all()).map_err(io)?;
+    fs::rename(&tmp, index_path(dir, segment)).map_err(io)?;
+
+    if let Ok(dir) = File::open(dir) {
+      let _ = dir.sync_all();
+    }
+
+    Ok(())
+  }
+
+  fn load_index(dir: &Path, segment: u32) -> Result<Vec<Record>> {
+    let path = index_path(dir, segment);
+    let mut data = Vec::<u8>::new();
+    File::open(&path).map_err(io)?.read_to_end(&mut data).map_err(io)?;
+
+    if data.len() < CHECKSUM || checksum(&data[..data.len() - CHECKSUM], &[])[..] != data[data.len() - CHECKSUM..] {
+      return Err(invalid_index(&path))
+    }
+
+    let data = &data[..data.len() - CHECKSUM];
+    let mut records = Vec::<Record>::new();
+    let mut pos = 0usize;
+    while pos < data.len() {
+      let field = |from: usize, len: usize| data.get(from..from + len).ok_or_else(|| invalid_index(&path));
+
+      let key_len = u32::from_le_bytes(field(pos, 4)?.try_into().unwrap()) as usize;
+      let key = String::from_utf8(field(pos + 4, key_len)?.to_vec()).map_err(|_| invalid_index(&path))?;
+      let offset = u64::from_le_bytes(field(pos + 4 + key_len, 8)?.try_into().unwrap());
+      let len = u32::from_le_bytes(field(pos + 12 + key_len, 4)?.try_into().unwrap()) as usize;
+
+      records.push((key, Location { segment, offset, len }));
+      pos += 16 + key_len;
+    }
+
+    Ok(records)
+  }
+
+  fn rotate(&mut self) -> Result<()> {
+    Self::save_index(&self.dir, self.segment, &self.active)?;
+    self.active.clear();
+
+    self.segment += 1;
+    self.writer = OpenOptions::new().create(true).append(true).open(segment_path(&self.dir, self.segment)).map_err(io)?;
+    self.size = 0;
+
+    // persist the new directory entry
+    if let Ok(dir) = File::open(&self.dir) {
+      let _ = dir.sync_all();
+    }
+
+    Ok(())
+  }
+
+  // read the value of a record by its location and check it against the record checksum
+  fn read(&self, key: &str, loc: &Location) -> Result<Vec<u8>> {
+    let mut file = File::open(segment_path(&self.dir, loc.segment)).map_err(io)?;
+    file.seek(SeekFrom::Start(loc.offset)).map_err(io)?;
+
+    let mut value = vec![0u8; loc.len + CHECKSUM];
+    file.read_exact(&mut value).map_err(io)?;
+
+    let sum = value.split_off(loc.len);
+    if checksum(key.as_bytes(), &value) != sum {
+      return Err(Error::Storage { reason: format!("Corrupted record {} in segment {}", key, loc.segment) })
+    }
+
+    Ok(value)
+  }
+}
+
+impl Store for FileStore {
+  fn append(&mut self, key: &str, value: &[u8]) -> Result<()> {
+    let mut record = Vec::<u8>::with_capacity(HEADER + key.len() + value.len() + CHECKSUM);
+    record.extend(&(key.len() as u32).to_le_bytes());
+    record.extend(&(value.len() as u32).to_le_bytes());
+    record.extend(key.as_bytes());
+    record.extend(value);
+    record.extend(checksum(key.as_bytes(), value));
+
+    if self.poisoned {
+      return Err(Error::Storage { reason: "Failed write, the store must be reopened".into() })
+    }
+
+    if self.size > 0 && self.size + record.len() as u64 > self.max_size {
+      self.rotate()?;
+    }
+
+    // a partial write is truncated, otherwise the next records would be indexed at wrong offsets
+    if let Err(err) = self.writer.write_all(&record).and_then(|_| self.writer.sync_data()) {
+      if self.writer.set_len(self.size).is_err() {
+        self.poisoned = true;
+      }
+
+      return Err(io(err))
+    }
+
+    let offset = self.size + (HEADER + key.len()) as u64;
+    let loc = Location { segment: self.segment, offset, len: value.len() };
+    self.index.entry(key.into()).or_default().push(loc.clone());
+    self.active.push((key.into(), loc));
+    self.size += record.len() as u64;
+
+    Ok(())
+  }
+
+  fn values(&self, key: &str) -> Result<Vec<Vec<u8>>> {
+    match self.index.get(key) {
+      None => Ok(Vec::new()),
+      Some(locations) => locations.iter().map(|loc| self.read(key, loc)).collect()
+    }
+  }
+
+  fn last(&self, key: &str) -> Result<Option<Vec<u8>>> {
+    match self.index.get(key).and_then(|locations| locations.last()) {
+      None => Ok(None),
+      Some(loc) => Ok(Some(self.read(key, loc)?))
+    }
+  }
+
+  fn count(&self, key: &str) -> Result<usize> {
+    Ok(self.index.get(key).map(Vec::len).unwrap_or(0))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::store::tests::check_store;
+
+  use rand::rngs::OsRng;
+  use rand::RngCore;
+
+  fn temp_dir() -> PathBuf {
+    let mut csprng = OsRng{};
+    std::env::temp_dir().join(format!("raiap-store-{}", csprng.next_u64()))
+  }
+
+  #[test]
+  fn file_store() {
+    let dir = temp_dir();
+    {
+      let mut store = FileStore::with_segment_size(&dir, 1024).unwrap();
+      check_store(&mut store);
+
+      // small segments must rotate
+      assert!(store.segment > 0);
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn reopen_and_recover_torn_write() {
+    let dir = temp_dir();
+    {
+      let mut store = FileStore::with_segment_size(&dir, 64).unwrap();
+      store.append("key-1", b"value-1").unwrap();
+      store.append("key-2", b"value-2").unwrap();
+      store.append("key-1", b"value-3").unwrap();
+    }
+
+    // simulate a crash in the middle of a write
+    {
+      let store = FileStore::open(&dir).unwrap();
+      let mut file = OpenOptions::new().append(true).open(segment_path(&dir, store.segment)).unwrap();
+      file.write_all(&[5, 0, 0, 0, 100, 0, 0, 0, b'k']).unwrap();
+    }
+
+    {
+      let mut store = FileStore::open(&dir).unwrap();
+      assert!(store.values("key-1").unwrap() == vec![b"value-1".to_vec(), b"value-3".to_vec()]);
+      assert!(store.last("key-2").unwrap() == Some(b"value-2".to_vec()));
+      assert!(store.last("key-3").unwrap().is_none());
+
+      store.append("key-3", b"value-4").unwrap();
+    }
+
+    let store = FileStore::open(&dir).unwrap();
+    assert!(store.last("key-3").unwrap() == Some(b"value-4".to_vec()));
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn persisted_index() {
+    let dir = temp_dir();
+    {
+      let mut store = FileStore::with_segment_size(&dir, 32).unwrap();
+      store.append("key-1", b"value-1").unwrap();
+      store.append("key-2", b"value-2").unwrap();
+      store.append("key-1", b"value-3").unwrap();
+      store.append("key-3", b"value-4").unwrap();
+      assert!(store.segment == 3);
+    }
+
+    // full segments are indexed, the last one is scanned
+    for segment in 0..3 {
+      assert!(index_path(&dir, segment).exists());
+    }
+    assert!(!index_path(&dir, 3).exists());
+
+    // the records of an indexed segment are only read (and checked) on demand
+    let mut data = fs::read(segment_path(&dir, 1)).unwrap();
+    let last = data.len() - CHECKSUM - 1;
+    data[last] ^= 1;
+    fs::write(segment_path(&dir, 1), data).unwrap();
+
+    {
+      let store = FileStore::open(&dir).unwrap();
+      assert!(store.count("key-1").unwrap() == 2 && store.count("key-2").unwrap() == 1);
+      assert!(store.last("key-1").unwrap() == Some(b"value-3".to_vec()));
+      assert!(store.last("key-2").is_err());
+    }
+
+    // a missing index is rebuilt from the segment
+    fs::remove_file(index_path(&dir, 0)).unwrap();
+    let store = FileStore::open(&dir).unwrap();
+    assert!(index_path(&dir, 0).exists());
+    assert!(store.values("key-1").unwrap() == vec![b"value-1".to_vec(), b"value-3".to_vec()]);
+    assert!(store.last("key-3").unwrap() == Some(b"value-4".to_vec()));
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn failed_write() {
+    let dir = temp_dir();
+    {
+      let mut store = FileStore::open(&dir).unwrap();
+      store.append("key-1", b"value-1").unwrap();
+
+      // a read-only writer can't write nor truncate
+      store.writer = File::open(segment_path(&dir, store.segment)).unwrap();
+      assert!(store.append("key-2", b"value-2").is_err());
+      assert!(store.poisoned && store.append("key-2", b"value-2").is_err());
+      assert!(store.last("key-2").unwrap().is_none());
+    }
+
+    let mut store = FileStore::open(&dir).unwrap();
+    store.append("key-2", b"value-2").unwrap();
+    assert!(store.last("key-1").unwrap() == Some(b"value-1".to_vec()));
+    assert!(store.last("key-2").unwrap() == Some(b"value-2".to_vec()));
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,460 @@
+use std::fmt;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+use ed25519_dalek::{PublicKey, Signature};
+
+use crate::structs::{Result, Error, Hash};
+use crate::structs::signer::Signer;
+use crate::structs::payload::{self, Payload, Format};
+use crate::structs::identity::Identity;
+use crate::structs::log::{self, Event};
+use crate::structs::stream::{self, Stream};
+use crate::store::{Store, FileStore, encode, decode};
+
+// global order of the commitments (subject key of each sequence number)
+const LOG: &str = "ledger/log";
+
+//-----------------------------------------------------------------------------------------------------------
+// Commitment (signed head of an identity or stream)
+//   The prev is the hash of the previous commitment of the same subject. Two different commitments with the
+//   same subject and prev are an equivocation, even if the owner signed them with different keys.
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub enum Subject {
+  Identity(String),   // udi
+  Stream(String)      // asi
+}
+
+impl fmt::Display for Subject {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Subject::Identity(udi) => write!(f, "identity/{}", udi),
+      Subject::Stream(asi) => write!(f, "stream/{}", asi)
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Commitment {
+  pub subject: Subject,
+  pub prev: Option<Hash>,
+  pub head: Hash,
+  #[serde(with = "crate::structs::json::b64")]
+  pub key: PublicKey,
+  #[serde(with = "crate::structs::json::b64")]
+  pub sig: Signature
+}
+
+impl Commitment {
+  pub fn new(signer: &dyn Signer, subject: Subject, head: Hash, prev: Option<&Commitment>) -> Result<Self> {
+    let prev = prev.map(Commitment::hash);
+    let key = signer.public();
+    let sig = signer.sign(&Self::data(&subject, &prev, &head, &key))?;
+
+    Ok(Self { subject, prev, head, key, sig })
+  }
+
+  // signed by the key of the current card
+  pub fn identity(signer: &dyn Signer, identity: &Identity, prev: Option<&Commitment>) -> Result<Self> {
+    Self::new(signer, Subject::Identity(identity.udi.clone()), identity.digest(), prev)
+  }
+
+  // signed by the stream key, the last block hash covers all blocks
+  pub fn stream(signer: &dyn Signer, stream: &Stream, prev: Option<&Commitment>) -> Result<Self> {
+    Self::new(signer, Subject::Stream(stream.asi.clone()), stream.prev(), prev)
+  }
+
+  pub fn hash(&self) -> Hash {
+    Hash::of(&Self::data(&self.subject, &self.prev, &self.head, &self.key))
+  }
+
+  // only the signature, the ledger also checks that the key owns the subject (see Owner)
+  pub fn verify(&self) -> bool {
+    let sig_data = Self::data(&self.subject, &self.prev, &self.head, &self.key);
+    self.key.verify(&sig_data, &self.sig).is_ok()
+  }
+
+  pub(crate) fn data(subject: &Subject, prev: &Option<Hash>, head: &Hash, key: &PublicKey) -> Vec<u8> {
+    Payload::new(payload::LEDGER_COMMITMENT, Format::CURRENT)
+      .field(subject)
+      .field(prev)
+      .field(head)
+      .field(key)
+      .into_bytes()
+  }
+}
+
+// two successors of the same prev, each with the proof that its key owns the subject (can be published as a
+// fraud proof)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Equivocation {
+  pub first: Commitment,
+  pub first_owner: Ownership,
+  pub second: Commitment,
+  pub second_owner: Ownership
+}
+
+impl Equivocation {
+  pub fn verify(&self) -> bool {
+    let (first, second) = (&self.first, &self.second);
+    first.subject == second.subject && first.prev == second.prev && first.hash() != second.hash()
+      && first.verify() && second.verify() && self.first_owner.check(first) && self.second_owner.check(second)
+  }
+}
+
+// proof kept by the ledger that the commitment key belongs to the subject
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Ownership {
+  Identity(Vec<Event>),   // evolution history of the identity, the key is the one of one of its cards
+  #[serde(with = "crate::structs::json::b64")]
+  Stream(Signature)       // ASI signature of the stream key
+}
+
+impl Ownership {
+  pub fn check(&self, commitment: &Commitment) -> bool {
+    match (self, &commitment.subject) {
+      (Ownership::Identity(events), Subject::Identity(udi)) => match log::restore(events) {
+        Ok(identity) => identity.verify_full().is_ok() && identity.udi == *udi
+          && identity.cards.iter().any(|card| *card.key() == commitment.key),
+        Err(_) => false
+      },
+      (Ownership::Stream(sig), Subject::Stream(asi)) => stream::asi(&commitment.key, sig) == *asi,
+      _ => false
+    }
+  }
+}
+
+// proof that the commitment key belongs to the subject
+pub enum Owner<'a> {
+  Identity(&'a Identity),  // verified identity with the committed head, signed by the key of its current card
+  Stream(&'a Signature)    // ASI signature of the stream key (see Stream::asi_signature)
+}
+
+impl Owner<'_> {
+  fn check(&self, commitment: &Commitment) -> Result<()> {
+    let authorised = match (self, &commitment.subject) {
+      (Owner::Identity(identity), Subject::Identity(udi)) => {
+        identity.verify_full()?;
+        identity.udi == *udi && *identity.card().key() == commitment.key && identity.digest() == commitment.head
+      },
+      (Owner::Stream(sig), Subject::Stream(asi)) => stream::asi(&commitment.key, sig) == *asi,
+      _ => false
+    };
+
+    match authorised {
+      true => Ok(()),
+      false => Err(Error::UnauthorisedCommitment { subject: commitment.subject.to_string() })
+    }
+  }
+
+  fn ownership(&self) -> Ownership {
+    match self {
+      Owner::Identity(identity) => Ownership::Identity(log::events(identity)),
+      Owner::Stream(sig) => Ownership::Stream(**sig)
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LedgerEntry {
+  pub seq: usize,
+  pub commitment: Commitment,
+  pub owner: Ownership
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Ledger (single node append-only ledger of head commitments)
+//   Accepts commitments with a valid signature of a key that owns the subject and that extend a known commitment
+//   of the subject, and orders them with a sequence number. Conflicting commitments are kept as evidence.
+//-----------------------------------------------------------------------------------------------------------
+pub struct Ledger<S: Store> {
+  store: S,
+  size: usize
+}
+
+impl Ledger<FileStore> {
+  pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+    Self::new(FileStore::open(dir)?)
+  }
+}
+
+impl<S: Store> Ledger<S> {
+  pub fn new(store: S) -> Result<Self> {
+    let size = store.values(LOG)?.len();
+    Ok(Self { store, size })
+  }
+
+  // number of commitments
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  // returns the sequence number (the same one if the commitment was already submitted)
+  pub fn submit(&mut self, commitment: Commitment, owner: Owner) -> Result<usize> {
+    if !commitment.verify() {
+      return Err(Error::InvalidCommitment)
+    }
+
+    owner.check(&commitment)?;
+    let ownership = owner.ownership();
+
+    let entries = self.entries(&commitment.subject)?;
+    let hash = commitment.hash();
+    if let Some(found) = entries.iter().find(|en| en.commitment.hash() == hash) {
+      return Ok(found.seq)
+    }
+
+    if let Some(prev) = commitment.prev {
+      if !entries.iter().any(|en| en.commitment.hash() == prev) {
+        return Err(Error::UnknownCommitment { subject: commitment.subject.to_string() })
+      }
+    }
+
+    // the log goes first, a crash in between only leaves a gap in the sequence numbers
+    let key = Self::key(&commitment.subject);
+    let seq = self.size;
+    self.store.append(LOG, key.as_bytes())?;
+    self.store.append(&key, &encode(&LedgerEntry { seq, commitment, owner: ownership }))?;
+    self.size += 1;
+
+    Ok(seq)
+  }
+
+  // all commitments of the subject in ledger order (only keys that own the subject are accepted)
+  pub fn entries(&self, subject: &Subject) -> Result<Vec<LedgerEntry>> {
+    self.store.values(&Self::key(subject))?.iter()
+      .map(|bytes| decode(bytes, "ledger entry"))
+      .collect()
+  }
+
+  // last commitment of the subject
+  pub fn head(&self, subject: &Subject) -> Result<Option<Commitment>> {
+    Ok(self.entries(subject)?.pop().map(|en| en.commitment))
+  }
+
+  pub fn equivocations(&self, subject: &Subject) -> Result<Vec<Equivocation>> {
+    let entries = self.entries(subject)?;
+
+    // the entries were checked when submitted and are all different
+    let mut found = Vec::<Equivocation>::new();
+    for (index, first) in entries.iter().enumerate() {
+      for second in entries.iter().skip(index + 1).filter(|en| en.commitment.prev == first.commitment.prev) {
+        found.push(Equivocation {
+          first: first.commitment.clone(),
+          first_owner: first.owner.clone(),
+          second: second.commitment.clone(),
+          second_owner: second.owner.clone()
+        });
+      }
+    }
+
+    Ok(found)
+  }
+
+  // the subject has a single history in the ledger
+  pub fn check(&self, subject: &Subject) -> Result<()> {
+    match self.equivocations(subject)?.is_empty() {
+      true => Ok(()),
+      false => Err(Error::Equivocation { subject: subject.to_string() })
+    }
+  }
+
+  fn key(subject: &Subject) -> String {
+    format!("ledger/{}", subject)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::{OType, json};
+  use crate::structs::identity::*;
+  use crate::structs::stream::*;
+  use crate::store::MemoryStore;
+
+  use std::fs;
+  use std::path::PathBuf;
+
+  use rand::rngs::OsRng;
+  use rand::RngCore;
+  use ed25519_dalek::Keypair;
+
+  fn temp_dir() -> PathBuf {
+    let mut csprng = OsRng{};
+    std::env::temp_dir().join(format!("raiap-ledger-{}", csprng.next_u64()))
+  }
+
+  fn create() -> (Identity, Keypair) {
+    let mut csprng = OsRng{};
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", &[master]).unwrap();
+
+    (Identity::new(genesis).unwrap(), id_keypair)
+  }
+
+  #[test]
+  fn ledger_order() {
+    let mut csprng = OsRng{};
+    let dir = temp_dir();
+    let (mut identity, id_keypair) = create();
+    let subject = Subject::Identity(identity.udi.clone());
+
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let stream = Stream::new(&profile_keypair, &identity.udi, "r-random", &[], genesis, None).unwrap();
+    let asi_sig = Stream::asi_signature(&profile_keypair, &identity.udi, "r-random").unwrap();
+
+    let first = identity.clone();
+    let c0 = Commitment::identity(&id_keypair, &identity, None).unwrap();
+    identity.append(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!").unwrap();
+    let c1 = Commitment::identity(&id_keypair, &identity, Some(&c0)).unwrap();
+    {
+      let mut ledger = Ledger::open(&dir).unwrap();
+      assert!(ledger.submit(c0.clone(), Owner::Identity(&first)).unwrap() == 0);
+      assert!(ledger.submit(Commitment::stream(&profile_keypair, &stream, None).unwrap(), Owner::Stream(&asi_sig)).unwrap() == 1);
+      assert!(ledger.submit(c1.clone(), Owner::Identity(&identity)).unwrap() == 2);
+
+      // already submitted
+      assert!(ledger.submit(c0.clone(), Owner::Identity(&first)).unwrap() == 0);
+    }
+
+    // the order survives a restart
+    let mut ledger = Ledger::open(&dir).unwrap();
+    assert!(ledger.size() == 3);
+    let seqs: Vec<usize> = ledger.entries(&subject).unwrap().iter().map(|en| en.seq).collect();
+    assert!(seqs == vec![0, 2]);
+    assert!(ledger.head(&subject).unwrap() == Some(c1.clone()));
+    assert!(ledger.head(&subject).unwrap().unwrap().head == identity.digest());
+
+    // unknown previous head or invalid signature
+    let other = Commitment::identity(&id_keypair, &identity, Some(&Commitment::identity(&id_keypair, &identity, None).unwrap())).unwrap();
+    assert!(ledger.submit(other, Owner::Identity(&identity)).err() == Some(Error::UnknownCommitment { subject: subject.to_string() }));
+
+    let mut forged = Commitment::identity(&id_keypair, &identity, Some(&c1)).unwrap();
+    forged.head = c0.head;
+    assert!(ledger.submit(forged, Owner::Identity(&identity)).err() == Some(Error::InvalidCommitment));
+    ledger.check(&subject).unwrap();
+
+    fs::remove_dir_all(&dir).unwrap();
+  }
+
+  #[test]
+  fn detect_equivocation() {
+    let (identity, id_keypair) = create();
+    let subject = Subject::Identity(identity.udi.clone());
+
+    let mut ledger = Ledger::new(MemoryStore::new()).unwrap();
+    let c0 = Commitment::identity(&id_keypair, &identity, None).unwrap();
+    ledger.submit(c0.clone(), Owner::Identity(&identity)).unwrap();
+
+    // the owner forks the identity with different registries
+    let mut copy1 = identity.clone();
+    copy1.append(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!").unwrap();
+    let mut copy2 = identity.clone();
+    copy2.append(&id_keypair, "idp.io/test", "test", OType::SET, b"Other info!").unwrap();
+
+    let c1 = Commitment::identity(&id_keypair, &copy1, Some(&c0)).unwrap();
+    let c2 = Commitment::identity(&id_keypair, &copy2, Some(&c0)).unwrap();
+    ledger.submit(c1.clone(), Owner::Identity(&copy1)).unwrap();
+    ledger.submit(c2.clone(), Owner::Identity(&copy2)).unwrap();
+
+    let found = ledger.equivocations(&subject).unwrap();
+    assert!(found.len() == 1 && found[0].first == c1 && found[0].second == c2);
+    assert!(found[0].verify());
+    assert!(ledger.check(&subject) == Err(Error::Equivocation { subject: subject.to_string() }));
+
+    // a commitment with itself is not a proof
+    let owner = Owner::Identity(&identity).ownership();
+    let proof = Equivocation { first: c0.clone(), first_owner: owner.clone(), second: c0, second_owner: owner };
+    assert!(!proof.verify());
+  }
+
+  #[test]
+  fn equivocation_across_key_rotation() {
+    let mut csprng = OsRng{};
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", std::slice::from_ref(&master)).unwrap();
+    let identity = Identity::new(genesis).unwrap();
+    let subject = Subject::Identity(identity.udi.clone());
+
+    let mut ledger = Ledger::new(MemoryStore::new()).unwrap();
+    let c0 = Commitment::identity(&id_keypair, &identity, None).unwrap();
+    ledger.submit(c0.clone(), Owner::Identity(&identity)).unwrap();
+
+    let mut copy1 = identity.clone();
+    copy1.append(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!").unwrap();
+    let c1 = Commitment::identity(&id_keypair, &copy1, Some(&c0)).unwrap();
+    ledger.submit(c1.clone(), Owner::Identity(&copy1)).unwrap();
+
+    // the owner rotates the card key and commits another successor of c0 with the new key
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let mut copy2 = identity.clone();
+    copy2.renew(Renew::new(&m_keypair, &id_keypair2.public, copy2.prev().unwrap(), true).unwrap()).unwrap();
+    copy2.evolve(Card::new(false, &id_keypair2, b"No info!", &[master]).unwrap()).unwrap();
+    let c2 = Commitment::identity(&id_keypair2, &copy2, Some(&c0)).unwrap();
+    ledger.submit(c2.clone(), Owner::Identity(&copy2)).unwrap();
+
+    let found = ledger.equivocations(&subject).unwrap();
+    assert!(found.len() == 1 && found[0].first == c1 && found[0].second == c2);
+    assert!(found[0].verify());
+    assert!(ledger.check(&subject) == Err(Error::Equivocation { subject: subject.to_string() }));
+
+    // the proof survives the export
+    let proof: Equivocation = json::from_str(&json::to_string(&found[0]), "equivocation").unwrap();
+    assert!(proof.verify());
+
+    // a stranger can't frame the owner with the history of the identity
+    let other: Keypair = Keypair::generate(&mut csprng);
+    let mut forged = found[0].clone();
+    forged.second = Commitment::new(&other, subject.clone(), copy2.digest(), Some(&c0)).unwrap();
+    assert!(!forged.verify());
+
+    // nor with the history of another identity
+    let (stranger, _) = create();
+    let mut forged = found[0].clone();
+    forged.second_owner = Owner::Identity(&stranger).ownership();
+    assert!(!forged.verify());
+  }
+
+  #[test]
+  fn unauthorised_keys() {
+    let mut csprng = OsRng{};
+    let (mut identity, id_keypair) = create();
+    let subject = Subject::Identity(identity.udi.clone());
+    let err = Err(Error::UnauthorisedCommitment { subject: subject.to_string() });
+
+    let mut ledger = Ledger::new(MemoryStore::new()).unwrap();
+    let c0 = Commitment::identity(&id_keypair, &identity, None).unwrap();
+    ledger.submit(c0.clone(), Owner::Identity(&identity)).unwrap();
+
+    // a stranger can't frame the owner, even with the identity
+    let other: Keypair = Keypair::generate(&mut csprng);
+    let stranger = Commitment::new(&other, subject.clone(), identity.digest(), Some(&c0)).unwrap();
+    assert!(ledger.submit(stranger, Owner::Identity(&identity)) == err);
+
+    // the head must be the one of the submitted identity
+    let stale = Commitment::identity(&id_keypair, &identity, Some(&c0)).unwrap();
+    identity.append(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!").unwrap();
+    assert!(ledger.submit(stale, Owner::Identity(&identity)) == err);
+    assert!(ledger.entries(&subject).unwrap().len() == 1 && ledger.head(&subject).unwrap() == Some(c0));
+
+    // only the stream key that owns the asi
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let stream = Stream::new(&profile_keypair, &identity.udi, "r-random", &[], genesis, None).unwrap();
+    let asi_sig = Stream::asi_signature(&profile_keypair, &identity.udi, "r-random").unwrap();
+    let other_sig = Stream::asi_signature(&other, &identity.udi, "r-random").unwrap();
+
+    let forged = Commitment::new(&other, Subject::Stream(stream.asi.clone()), stream.prev(), None).unwrap();
+    let subject = Subject::Stream(stream.asi.clone());
+    assert!(ledger.submit(forged.clone(), Owner::Stream(&asi_sig)) == Err(Error::UnauthorisedCommitment { subject: subject.to_string() }));
+    assert!(ledger.submit(forged, Owner::Stream(&other_sig)) == Err(Error::UnauthorisedCommitment { subject: subject.to_string() }));
+    assert!(ledger.submit(Commitment::stream(&profile_keypair, &stream, None).unwrap(), Owner::Identity(&identity)).is_err());
+    ledger.submit(Commitment::stream(&profile_keypair, &stream, None).unwrap(), Owner::Stream(&asi_sig)).unwrap();
+  }
+}
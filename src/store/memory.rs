@@ -0,0 +1,30 @@
+use std::collections::HashMap;
+
+use crate::structs::Result;
+use crate::store::Store;
+
+#[derive(Default, Debug, Clone)]
+pub struct MemoryStore {
+  data: HashMap<String, Vec<Vec<u8>>>
+}
+
+impl MemoryStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+
+impl Store for MemoryStore {
+  fn append(&mut self, key: &str, value: &[u8]) -> Result<()> {
+    self.data.entry(key.into()).or_default().push(value.into());
+    Ok(())
+  }
+
+  fn values(&self, key: &str) -> Result<Vec<Vec<u8>>> {
+    Ok(self.data.get(key).cloned().unwrap_or_default())
+  }
+
+  fn count(&self, key: &str) -> Result<usize> {
+    Ok(self.data.get(key).map(Vec::len).unwrap_or(0))
+  }
+}
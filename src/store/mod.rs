@@ -0,0 +1,237 @@
+pub mod memory;
+pub mod file;
+pub mod ledger;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::structs::{Result, Error};
+use crate::structs::identity::*;
+use crate::structs::stream::*;
+use crate::structs::log::{self, Event};
+
+pub use memory::MemoryStore;
+pub use file::FileStore;
+pub use ledger::Ledger;
+
+fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+  // This unwrap() should never fail, or it's a serious code bug!
+  bincode::serialize(value).unwrap()
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8], what: &'static str) -> Result<T> {
+  bincode::deserialize(bytes).map_err(|_| Error::Deserialize { what })
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Store (append-only key/value backend with typed access to identities, registries and chains)
+//-----------------------------------------------------------------------------------------------------------
+pub trait Store {
+  fn append(&mut self, key: &str, value: &[u8]) -> Result<()>;
+  fn values(&self, key: &str) -> Result<Vec<Vec<u8>>>;
+
+  fn last(&self, key: &str) -> Result<Option<Vec<u8>>> {
+    Ok(self.values(key)?.pop())
+  }
+
+  fn count(&self, key: &str) -> Result<usize> {
+    Ok(self.values(key)?.len())
+  }
+
+  // append the evolution events and the registry entries not yet stored
+  fn save_identity(&mut self, identity: &Identity) -> Result<()> {
+    let key = format!("identity/{}", identity.udi);
+    let stored = self.count(&key)?;
+    for event in log::events(identity).iter().skip(stored) {
+      self.append(&key, &encode(event))?;
+    }
+
+    let mut ids: Vec<&String> = identity.db.keys().collect();
+    ids.sort();
+    for id in ids {
+      let stored = self.count(&format!("registry/{}/{}", identity.udi, id))?;
+      for reg in identity.db[id].iter().skip(stored) {
+        self.save_registry(&identity.udi, reg)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  fn load_identity(&self, udi: &str) -> Result<Option<Identity>> {
+    let events = self.values(&format!("identity/{}", udi))?.iter()
+      .map(|bytes| decode(bytes, "identity event"))
+      .collect::<Result<Vec<Event>>>()?;
+
+    if events.is_empty() {
+      return Ok(None)
+    }
+
+    let mut identity = log::restore(&events)?;
+
+    for id in self.values(&format!("registries/{}", udi))?.iter() {
+      let id = String::from_utf8(id.clone()).map_err(|_| Error::Deserialize { what: "registry id" })?;
+      let chain = self.load_registry(udi, &id)?;
+      identity.db.insert(id, chain);
+    }
+
+    Ok(Some(identity))
+  }
+
+  fn save_registry(&mut self, udi: &str, registry: &Registry) -> Result<()> {
+    let key = format!("registry/{}/{}", udi, registry.id);
+    if self.count(&key)? == 0 {
+      self.append(&format!("registries/{}", udi), registry.id.as_bytes())?;
+    }
+
+    self.append(&key, &encode(registry))
+  }
+
+  fn load_registry(&self, udi: &str, id: &str) -> Result<Vec<Registry>> {
+    self.values(&format!("registry/{}/{}", udi, id))?.iter()
+      .map(|bytes| decode(bytes, "registry"))
+      .collect()
+  }
+
+  // chains are stored by the genesis asi and indexed by every stream asi, only the streams and blocks not yet
+  // stored are appended
+  fn save_chain(&mut self, chain: &Chain) -> Result<()> {
+    let first = chain.streams()[0].asi.clone();
+    let key = format!("chain/{}", first);
+    let stored = self.count(&key)?;
+    for st in chain.streams().iter().skip(stored) {
+      self.append(&key, &encode(&st.without_blocks()))?;
+      self.append(&format!("asi/{}", st.asi), first.as_bytes())?;
+    }
+
+    // the streams before the last stored one can't get new blocks (the renew of the next one links the last)
+    for st in chain.streams().iter().skip(stored.saturating_sub(1)) {
+      let key = format!("blocks/{}", st.asi);
+      let stored = self.count(&key)?;
+      for bl in st.blocks.iter().skip(stored) {
+        self.append(&key, &encode(bl))?;
+      }
+    }
+
+    Ok(())
+  }
+
+  fn load_chain(&self, asi: &str) -> Result<Option<Chain>> {
+    let first = match self.last(&format!("asi/{}", asi))? {
+      None => return Ok(None),
+      Some(first) => String::from_utf8(first).map_err(|_| Error::Deserialize { what: "asi" })?
+    };
+
+    let mut chain: Option<Chain> = None;
+    for bytes in self.values(&format!("chain/{}", first))?.iter() {
+      let mut stream: Stream = decode(bytes, "stream")?;
+      for bytes in self.values(&format!("blocks/{}", stream.asi))?.iter() {
+        stream.save(decode(bytes, "stream block")?)?;
+      }
+
+      match chain.as_mut() {
+        None => chain = Some(Chain::new(stream)),
+        Some(chain) => chain.save(stream)?
+      }
+    }
+
+    Ok(chain)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::OType;
+
+  use rand::rngs::OsRng;
+  use ed25519_dalek::Keypair;
+
+  pub fn check_store<S: Store>(store: &mut S) {
+    let mut csprng = OsRng{};
+
+    // identity with a registry
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", std::slice::from_ref(&master)).unwrap();
+    let mut identity = Identity::new(genesis).unwrap();
+
+    let reg1 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", identity.prev().unwrap(), 0).unwrap();
+    identity.save(reg1.clone()).unwrap();
+    store.save_identity(&identity).unwrap();
+
+    // incremental registry
+    let reg2 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"More info!", reg1.hash(), 0).unwrap();
+    identity.save(reg2.clone()).unwrap();
+    store.save_registry(&identity.udi, &reg2).unwrap();
+
+    // evolution is saved without duplicating the genesis or registries
+    let cancel = Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap();
+    identity.cancel(cancel).unwrap();
+    store.save_identity(&identity).unwrap();
+    store.save_identity(&identity).unwrap();
+    assert!(store.count(&format!("identity/{}", identity.udi)).unwrap() == 2);
+    assert!(store.count(&format!("registry/{}/idp.io/test", identity.udi)).unwrap() == 2);
+
+    let loaded = store.load_identity(&identity.udi).unwrap().unwrap();
+    assert!(!loaded.is_enabled());
+    assert!(loaded.registry("idp.io/test").unwrap().len() == 2);
+    loaded.verify_full().unwrap();
+
+    // the renew completes the stored cancel
+    let next: Keypair = Keypair::generate(&mut csprng);
+    identity.renew(Renew::new(&m_keypair, &next.public, identity.prev().unwrap(), false).unwrap()).unwrap();
+    store.save_identity(&identity).unwrap();
+    let card = Card::new(false, &next, b"No important info!", &identity.card().groups.values().cloned().collect::<Vec<_>>()).unwrap();
+    identity.evolve(card).unwrap();
+    store.save_identity(&identity).unwrap();
+    assert!(store.count(&format!("identity/{}", identity.udi)).unwrap() == 4);
+
+    let loaded = store.load_identity(&identity.udi).unwrap().unwrap();
+    assert!(loaded.is_enabled() && loaded.evols.len() == 1 && loaded.digest() == identity.digest());
+    loaded.verify_full().unwrap();
+    assert!(store.load_identity("no-udi").unwrap().is_none());
+
+    // chain of streams
+    let keypair1: Keypair = Keypair::generate(&mut csprng);
+    let keypair2: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let stream1 = Stream::new(&keypair1, &identity.udi, "r1-random", &[master], genesis, None).unwrap();
+    let mut chain = Chain::new(stream1);
+    store.save_chain(&chain).unwrap();
+
+    let block = StreamBlock::new(&keypair1, Record { oper: OType::SET, info: b"New info!".to_vec() }, chain.current().prev()).unwrap();
+    let mut stream1 = chain.current().clone();
+    stream1.save(block).unwrap();
+    chain = Chain::new(stream1);
+    store.save_chain(&chain).unwrap();
+
+    let ext_renew = ExtRenew::new(Renew::new(&m_keypair, &keypair2.public, chain.current().prev(), true).unwrap(), &keypair1.public);
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let stream2 = Stream::new(&keypair2, &identity.udi, "r2-random", &[], genesis, Some(ext_renew)).unwrap();
+    chain.save(stream2).unwrap();
+    store.save_chain(&chain).unwrap();
+    store.save_chain(&chain).unwrap();
+
+    // each stream and block is only stored once
+    let first = chain.streams()[0].asi.clone();
+    assert!(store.values(&format!("chain/{}", first)).unwrap().len() == 2);
+    assert!(store.values(&format!("blocks/{}", first)).unwrap().len() == 1);
+
+    // lookup by any asi of the chain
+    for st in chain.streams().iter() {
+      let loaded = store.load_chain(&st.asi).unwrap().unwrap();
+      assert!(loaded.streams().len() == 2 && loaded.blocks() == 1);
+      loaded.check(&keypair2.public).unwrap();
+    }
+
+    assert!(store.load_chain("no-asi").unwrap().is_none());
+  }
+
+  #[test]
+  fn memory_store() {
+    let mut store = MemoryStore::new();
+    check_store(&mut store);
+  }
+}
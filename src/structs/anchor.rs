@@ -1,14 +1,20 @@
-
-pub const DOMAIN: &'static str = "raiap.io";
-pub const TYPE: &'static str = "anchor";
+use std::collections::BTreeSet;
 
 use serde::{Serialize, Deserialize};
-use ed25519_dalek::{Keypair, PublicKey, Signature};
+use ed25519_dalek::{PublicKey, Signature};
 
 use sha2::{Sha256, Digest};
 use base64::encode;
 
-use crate::structs::Result;
+use crate::structs::{Result, Error, OType};
+use crate::structs::cbor;
+use crate::structs::signer::Signer;
+use crate::structs::payload::{self, Payload, Format};
+use crate::structs::identity::Identity;
+use crate::structs::stream::{Stream, Chain};
+
+pub const DOMAIN: &str = "raiap.io";
+pub const TYPE: &str = "anchor";
 
 pub fn al(sig: &Signature) -> String {
   let mut hasher = Sha256::new();
@@ -26,34 +32,204 @@ pub struct Anchor {
 }
 
 impl Anchor {
-  pub fn new(keypair: &Keypair, udi: &str, r: &str, sn: usize) -> Self {
+  pub fn new(signer: &dyn Signer, udi: &str, r: &str, sn: usize) -> Result<Self> {
     let sig_data = Self::al_data(udi, r);
-    let sig = keypair.sign(&sig_data);
+    let sig = signer.sign(&sig_data)?;
 
-    Self { r: r.into(), sn, al: al(&sig) }
+    Ok(Self { r: r.into(), sn, al: al(&sig) })
   }
 
-  pub fn al_signature(&self, keypair: &Keypair, udi: &str) -> Signature {
+  pub fn al_signature(&self, signer: &dyn Signer, udi: &str) -> Result<Signature> {
     let sig_data = Self::al_data(udi, &self.r);
-    keypair.sign(&sig_data)
+    signer.sign(&sig_data)
+  }
+
+  // append to the anchor registry, the sn must be higher than the one of the last anchor
+  pub fn register(&self, signer: &dyn Signer, identity: &mut Identity) -> Result<()> {
+    let anchors = Anchors::of(identity)?;
+    if let Some(last) = anchors.last() {
+      if self.sn <= last.sn {
+        return Err(Error::InvalidAnchorSn { last: last.sn, found: self.sn })
+      }
+    }
+
+    identity.append(signer, DOMAIN, TYPE, OType::SET, &self.to_bytes())
+  }
+
+  // revoke a registered anchor with a DEL entry (info is the revoked anchor)
+  pub fn revoke(&self, signer: &dyn Signer, identity: &mut Identity) -> Result<()> {
+    let anchors = Anchors::of(identity)?;
+    match anchors.get(&self.al) {
+      None => return Err(Error::UnknownAnchor { r: self.r.clone() }),
+      Some(_) if anchors.is_revoked(&self.al) => return Err(Error::AnchorRevoked { al: self.al.clone() }),
+      Some(_) => ()
+    }
+
+    identity.append(signer, DOMAIN, TYPE, OType::DEL, &self.to_bytes())
   }
 
+  // canonical CBOR, the signed info of the anchor registry entries
   pub fn to_bytes(&self) -> Vec<u8> {
-    bincode::serialize(self).unwrap()
+    cbor::to_vec(self)
   }
 
   pub fn from_bytes(bytes: &[u8]) -> Result<Anchor> {
-    bincode::deserialize(bytes).map_err(|_|{ "Unable to deserialize anchor!".into() })
+    cbor::from_slice(bytes, "anchor")
   }
 
-  fn al_data(udi: &str, r: &str) -> Vec<u8> {
-    let mut data = Vec::<u8>::new();
+  pub(crate) fn al_data(udi: &str, r: &str) -> Vec<u8> {
+    Payload::new(payload::ANCHOR_AL, Format::CURRENT)
+      .field(udi)
+      .field(r)
+      .into_bytes()
+  }
+}
 
-    // These unwrap() should never fail, or it's a serious code bug!
-    data.extend(bincode::serialize(udi).unwrap());
-    data.extend(bincode::serialize(r).unwrap());
-    
-    data
+//-----------------------------------------------------------------------------------------------------------
+// Link (proof kept by the holder that a stream is the one of an anchor)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Link {
+  #[serde(with = "crate::structs::json::b64")]
+  pub key: PublicKey,
+  #[serde(with = "crate::structs::json::b64")]
+  pub al_sig: Signature,
+  #[serde(with = "crate::structs::json::b64")]
+  pub asi_sig: Signature
+}
+
+impl Link {
+  pub fn new(signer: &dyn Signer, udi: &str, r: &str) -> Result<Self> {
+    let al_sig = signer.sign(&Anchor::al_data(udi, r))?;
+    let asi_sig = Stream::asi_signature(signer, udi, r)?;
+
+    Ok(Self { key: signer.public(), al_sig, asi_sig })
+  }
+
+  pub fn al(&self) -> String {
+    al(&self.al_sig)
+  }
+
+  pub fn check(&self, udi: &str, anchor: &Anchor, stream: &Stream) -> bool {
+    self.al() == anchor.al
+      && self.key.verify(&Anchor::al_data(udi, &anchor.r), &self.al_sig).is_ok()
+      && stream.check_asi(udi, &anchor.r, &self.key, &self.asi_sig)
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Anchors (validated view of the anchor registry of an identity)
+//   * SET entries register anchors with increasing sn
+//   * DEL entries revoke a registered anchor (the info is the revoked anchor)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, Default)]
+pub struct Anchors {
+  anchors: Vec<Anchor>,
+  revoked: BTreeSet<String>
+}
+
+impl Anchors {
+  pub fn of(identity: &Identity) -> Result<Self> {
+    let mut anchors = Self::default();
+    let chain = match identity.registry(DOMAIN) {
+      None => return Ok(anchors),
+      Some(chain) => chain
+    };
+
+    for reg in chain.iter() {
+      if reg.oper == OType::REANCHOR {
+        continue
+      }
+
+      if reg.typ != TYPE {
+        return Err(Error::InvalidRegistryType { id: DOMAIN.into(), expected: TYPE.into(), found: reg.typ.clone() })
+      }
+
+      let anchor = Anchor::from_bytes(&reg.info)?;
+      match reg.oper {
+        OType::SET => {
+          if let Some(last) = anchors.last() {
+            if anchor.sn <= last.sn {
+              return Err(Error::InvalidAnchorSn { last: last.sn, found: anchor.sn })
+            }
+          }
+
+          anchors.anchors.push(anchor);
+        },
+
+        _ => {
+          if anchors.get(&anchor.al) != Some(&anchor) {
+            return Err(Error::UnknownAnchor { r: anchor.r })
+          }
+
+          if !anchors.revoked.insert(anchor.al.clone()) {
+            return Err(Error::AnchorRevoked { al: anchor.al })
+          }
+        }
+      }
+    }
+
+    Ok(anchors)
+  }
+
+  pub fn last(&self) -> Option<&Anchor> {
+    self.anchors.last()
+  }
+
+  // sn for the next anchor
+  pub fn next_sn(&self) -> usize {
+    self.last().map(|an| an.sn + 1).unwrap_or(0)
+  }
+
+  pub fn get(&self, al: &str) -> Option<&Anchor> {
+    self.anchors.iter().find(|an| an.al == al)
+  }
+
+  pub fn is_revoked(&self, al: &str) -> bool {
+    self.revoked.contains(al)
+  }
+
+  // anchors that are not revoked (by sn)
+  pub fn live(&self) -> Vec<&Anchor> {
+    self.anchors.iter().filter(|an| !self.is_revoked(&an.al)).collect()
+  }
+
+  // each stream of the chain is tied to a registered anchor (links[i] for stream i), with increasing sn
+  pub fn check_chain(&self, udi: &str, chain: &Chain, links: &[Link]) -> Result<()> {
+    let mut last: Option<&Anchor> = None;
+    for (index, stream) in chain.streams().iter().enumerate() {
+      let link = links.get(index).ok_or(Error::InvalidAnchorLink { index })?;
+      let anchor = self.get(&link.al()).ok_or(Error::InvalidAnchorLink { index })?;
+      if !link.check(udi, anchor, stream) {
+        return Err(Error::InvalidAnchorLink { index })
+      }
+
+      if let Some(last) = last {
+        if anchor.sn <= last.sn {
+          return Err(Error::InvalidAnchorSn { last: last.sn, found: anchor.sn })
+        }
+      }
+
+      last = Some(anchor);
+    }
+
+    Ok(())
+  }
+
+  // current stream of the chain that contains the anchor, if the anchor of that stream is not revoked
+  pub fn live_stream<'a>(&self, udi: &str, al: &str, chain: &'a Chain, links: &[Link]) -> Result<&'a Stream> {
+    self.check_chain(udi, chain, links)?;
+    if !links.iter().any(|link| link.al() == al) {
+      return Err(Error::NoAnchorStream { al: al.into() })
+    }
+
+    // check_chain requires a link for every stream
+    let current = links[chain.streams().len() - 1].al();
+    if self.is_revoked(&current) {
+      return Err(Error::AnchorRevoked { al: current })
+    }
+
+    Ok(chain.current())
   }
 }
 
@@ -63,11 +239,17 @@ mod tests {
   
   use crate::structs::*;
   use crate::structs::identity::*;
-  
+  use crate::structs::stream::*;
+
   use rand::rngs::OsRng;
   use ed25519_dalek::Keypair;
 
   fn create() -> (Identity, Keypair) {
+    let (identity, _, id_keypair) = create_with_master();
+    (identity, id_keypair)
+  }
+
+  fn create_with_master() -> (Identity, Keypair, Keypair) {
     let mut csprng = OsRng{};
 
     // create master group
@@ -76,10 +258,10 @@ mod tests {
 
     // create genesis card and identity
     let id_keypair: Keypair = Keypair::generate(&mut csprng);
-    let genesis = Card::new(true, &id_keypair, b"No important info!", &vec![master.clone()]);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", std::slice::from_ref(&master)).unwrap();
     let identity = Identity::new(genesis).unwrap();
     
-    (identity, id_keypair)
+    (identity, m_keypair, id_keypair)
   }
 
   #[test]
@@ -89,8 +271,8 @@ mod tests {
     
     // write anchor
     let profile_keypair: Keypair = Keypair::generate(&mut csprng);
-    let anchor1 = Anchor::new(&profile_keypair, &identity.udi, "some-random", 0);
-    let anchor_reg = Registry::new(&id_keypair, "raiap.io/test", "anchor", OType::SET, &anchor1.to_bytes(), identity.prev().unwrap(), 0);
+    let anchor1 = Anchor::new(&profile_keypair, &identity.udi, "some-random", 0).unwrap();
+    let anchor_reg = Registry::new(&id_keypair, "raiap.io/test", "anchor", OType::SET, &anchor1.to_bytes(), identity.prev().unwrap(), 0).unwrap();
     identity.save(anchor_reg).unwrap();
 
     // read anchor
@@ -100,4 +282,117 @@ mod tests {
 
     assert!(anchor1 == anchor2);
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn anchor_sequence() {
+    let mut csprng = OsRng{};
+    let (mut identity, id_keypair) = create();
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+
+    let anchor0 = Anchor::new(&profile_keypair, &identity.udi, "r0", 0).unwrap();
+    anchor0.register(&id_keypair, &mut identity).unwrap();
+    let anchor1 = Anchor::new(&profile_keypair, &identity.udi, "r1", 1).unwrap();
+    anchor1.register(&id_keypair, &mut identity).unwrap();
+
+    // sn must increase
+    let replay = Anchor::new(&profile_keypair, &identity.udi, "r2", 1).unwrap();
+    assert!(replay.register(&id_keypair, &mut identity) == Err(Error::InvalidAnchorSn { last: 1, found: 1 }));
+
+    // revocation
+    anchor0.revoke(&id_keypair, &mut identity).unwrap();
+    assert!(anchor0.revoke(&id_keypair, &mut identity) == Err(Error::AnchorRevoked { al: anchor0.al.clone() }));
+    assert!(replay.revoke(&id_keypair, &mut identity) == Err(Error::UnknownAnchor { r: "r2".into() }));
+
+    let anchors = Anchors::of(&identity).unwrap();
+    assert!(anchors.is_revoked(&anchor0.al) && !anchors.is_revoked(&anchor1.al));
+    assert!(anchors.live() == vec![&anchor1]);
+    assert!(anchors.next_sn() == 2);
+    identity.verify_full().unwrap();
+
+    // a registry written without the checks is rejected by the validation
+    let head = identity.head(DOMAIN);
+    let reg = Registry::new(&id_keypair, DOMAIN, TYPE, OType::SET, &replay.to_bytes(), head, 0).unwrap();
+    identity.save(reg).unwrap();
+    assert!(Anchors::of(&identity).err() == Some(Error::InvalidAnchorSn { last: 1, found: 1 }));
+  }
+
+  #[test]
+  fn anchor_after_evolution() {
+    let mut csprng = OsRng{};
+    let (mut identity, m_keypair, id_keypair) = create_with_master();
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+
+    let anchor0 = Anchor::new(&profile_keypair, &identity.udi, "r0", 0).unwrap();
+    anchor0.register(&id_keypair, &mut identity).unwrap();
+
+    // evolution to a new identity key
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    identity.cancel(Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap()).unwrap();
+    identity.renew(Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), true).unwrap()).unwrap();
+    identity.evolve(Card::new(false, &id_keypair2, b"No important info!", &[master]).unwrap()).unwrap();
+
+    // the registry is re-anchored to the new card before the next entries
+    let anchor1 = Anchor::new(&profile_keypair, &identity.udi, "r1", 1).unwrap();
+    anchor1.register(&id_keypair2, &mut identity).unwrap();
+    anchor0.revoke(&id_keypair2, &mut identity).unwrap();
+    identity.verify_full().unwrap();
+
+    let anchors = Anchors::of(&identity).unwrap();
+    assert!(anchors.live() == vec![&anchor1]);
+    assert!(anchors.next_sn() == 2);
+
+    // the old key can't write after the evolution
+    let anchor2 = Anchor::new(&profile_keypair, &identity.udi, "r2", 2).unwrap();
+    let err = Error::InvalidRegistry { id: DOMAIN.into() };
+    assert!(anchor2.register(&id_keypair, &mut identity) == Err(err.clone()));
+    assert!(anchor1.revoke(&id_keypair, &mut identity) == Err(err));
+    identity.verify_full().unwrap();
+  }
+
+  #[test]
+  fn anchor_live_stream() {
+    let mut csprng = OsRng{};
+    let (mut identity, id_keypair) = create();
+    let udi = identity.udi.clone();
+
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let keypair1: Keypair = Keypair::generate(&mut csprng);
+    let keypair2: Keypair = Keypair::generate(&mut csprng);
+
+    // stream-1 with anchor sn 0
+    let anchor1 = Anchor::new(&keypair1, &udi, "r1", 0).unwrap();
+    anchor1.register(&id_keypair, &mut identity).unwrap();
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let stream1 = Stream::new(&keypair1, &udi, "r1", &[master], genesis, None).unwrap();
+
+    // stream-2 created after the chain renew, with anchor sn 1
+    let ext_renew = ExtRenew::new(Renew::new(&m_keypair, &keypair2.public, stream1.prev(), true).unwrap(), &keypair1.public);
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let stream2 = Stream::new(&keypair2, &udi, "r2", &[], genesis, Some(ext_renew)).unwrap();
+    let mut chain = Chain::new(stream1);
+    chain.save(stream2).unwrap();
+    let anchor2 = Anchor::new(&keypair2, &udi, "r2", 1).unwrap();
+    anchor2.register(&id_keypair, &mut identity).unwrap();
+
+    let links = vec![Link::new(&keypair1, &udi, "r1").unwrap(), Link::new(&keypair2, &udi, "r2").unwrap()];
+    let anchors = Anchors::of(&identity).unwrap();
+    anchors.check_chain(&udi, &chain, &links).unwrap();
+
+    // the old AL resolves to the current stream
+    let live = anchors.live_stream(&udi, &anchor1.al, &chain, &links).unwrap();
+    assert!(live.asi == chain.current().asi);
+    assert!(anchors.live_stream(&udi, "unknown", &chain, &links).err() == Some(Error::NoAnchorStream { al: "unknown".into() }));
+
+    // links in the wrong order or missing
+    let swapped = vec![links[1].clone(), links[0].clone()];
+    assert!(anchors.check_chain(&udi, &chain, &swapped) == Err(Error::InvalidAnchorLink { index: 0 }));
+    assert!(anchors.check_chain(&udi, &chain, &links[..1]) == Err(Error::InvalidAnchorLink { index: 1 }));
+
+    // revoking the current anchor ends the profile
+    anchor2.revoke(&id_keypair, &mut identity).unwrap();
+    let anchors = Anchors::of(&identity).unwrap();
+    assert!(anchors.live_stream(&udi, &anchor1.al, &chain, &links).err() == Some(Error::AnchorRevoked { al: anchor2.al.clone() }));
+  }
+}
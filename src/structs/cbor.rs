@@ -0,0 +1,205 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use ciborium::value::Value;
+
+use crate::structs::{Result, Error};
+
+//-----------------------------------------------------------------------------------------------------------
+// Canonical CBOR (RFC 8949 deterministic encoding)
+//   * integers and lengths in the shortest form, no indefinite lengths
+//   * map keys sorted by the bytewise order of their encoding, no duplicates
+//   * structs are maps keyed by the field name, unit variants are text, other variants a single entry map
+//   * byte fields (info, keys, signatures) are byte strings, options are null or the value
+//-----------------------------------------------------------------------------------------------------------
+pub fn to_vec<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+  // This unwrap() should never fail, or it's a serious code bug!
+  let value = Value::serialized(value).unwrap();
+  write(&canonical(value))
+}
+
+pub fn from_slice<T: DeserializeOwned>(bytes: &[u8], what: &'static str) -> Result<T> {
+  let value: Value = ciborium::de::from_reader(bytes).map_err(|_| Error::Deserialize { what })?;
+
+  // reject any other encoding of the same value (including trailing bytes)
+  let value = canonical(value);
+  if has_duplicates(&value) || write(&value) != bytes {
+    return Err(Error::NonCanonical { what })
+  }
+
+  value.deserialized().map_err(|_| Error::Deserialize { what })
+}
+
+pub(crate) fn value<T: Serialize + ?Sized>(value: &T) -> Value {
+  // This unwrap() should never fail, or it's a serious code bug!
+  canonical(Value::serialized(value).unwrap())
+}
+
+pub(crate) fn write(value: &Value) -> Vec<u8> {
+  let mut data = Vec::<u8>::new();
+
+  // This unwrap() should never fail, or it's a serious code bug!
+  ciborium::ser::into_writer(value, &mut data).unwrap();
+  data
+}
+
+fn canonical(value: Value) -> Value {
+  match value {
+    Value::Array(items) => Value::Array(items.into_iter().map(canonical).collect()),
+    Value::Tag(tag, inner) => Value::Tag(tag, Box::new(canonical(*inner))),
+    Value::Map(entries) => {
+      let mut entries: Vec<(Vec<u8>, Value, Value)> = entries.into_iter()
+        .map(|(key, value)| {
+          let key = canonical(key);
+          (write(&key), key, canonical(value))
+        })
+        .collect();
+
+      entries.sort_by(|a, b| a.0.cmp(&b.0));
+      Value::Map(entries.into_iter().map(|(_, key, value)| (key, value)).collect())
+    },
+    other => other
+  }
+}
+
+// expects a canonical value (duplicated keys are adjacent)
+fn has_duplicates(value: &Value) -> bool {
+  match value {
+    Value::Array(items) => items.iter().any(has_duplicates),
+    Value::Tag(_, inner) => has_duplicates(inner),
+    Value::Map(entries) => {
+      entries.windows(2).any(|pair| pair[0].0 == pair[1].0)
+        || entries.iter().any(|(key, value)| has_duplicates(key) || has_duplicates(value))
+    },
+    _ => false
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::OType;
+  use crate::structs::identity::*;
+  use crate::structs::anchor::*;
+  use crate::structs::stream::*;
+  use crate::structs::payload::Format;
+
+  use ed25519_dalek::{Keypair, SecretKey, PublicKey};
+
+  const VECTORS: &str = include_str!("../../vectors/cbor.txt");
+
+  fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+  }
+
+  // fixed keys, ed25519 signatures are deterministic
+  fn keypair(seed: u8) -> Keypair {
+    let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+  }
+
+  fn vectors() -> Vec<(String, Vec<u8>)> {
+    let m_keypair = keypair(1);
+    let id_keypair = keypair(2);
+    let id_keypair2 = keypair(3);
+    let profile_keypair = keypair(4);
+
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let card = Card::new(true, &id_keypair, b"No important info!", std::slice::from_ref(&master)).unwrap();
+    let registry = Registry::new(&id_keypair, "raiap.io/test", "test", OType::SET, b"Not important!", card.hash(), 0).unwrap();
+
+    let mut renew = Renew::new(&m_keypair, &id_keypair2.public, card.hash(), true).unwrap();
+    renew.cosign(&id_keypair).unwrap();
+    let cancel = Cancel::new(true, &m_keypair, card.hash()).unwrap();
+    let evolve = Evolve { cancel: None, renew: Some(renew.clone()) };
+
+    let anchor = Anchor::new(&profile_keypair, "udi", "r", 1).unwrap();
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let stream = Stream::new(&profile_keypair, "udi", "r", std::slice::from_ref(&master), genesis, None).unwrap();
+    let record = Record { oper: OType::DEL, info: b"".to_vec() };
+    let block = StreamBlock::new(&profile_keypair, record, stream.hash()).unwrap();
+
+    vec![
+      ("card".into(), to_vec(&card)),
+      ("evolve".into(), to_vec(&evolve)),
+      ("registry".into(), to_vec(&registry)),
+      ("anchor".into(), to_vec(&anchor)),
+      ("stream".into(), to_vec(&stream)),
+      ("stream-block".into(), to_vec(&block)),
+
+      // signed messages
+      ("card-payload".into(), Card::data(Format::CURRENT, card.is_genesis, &card.info, &card.groups)),
+      ("cancel-payload".into(), Cancel::data(Format::CURRENT, cancel.is_close, &cancel.prev)),
+      ("renew-payload".into(), Renew::data(Format::CURRENT, &renew.commit, &renew.prev)),
+      ("registry-payload".into(), Registry::data(Format::CURRENT, &registry.id, &registry.typ, &registry.oper, &registry.info, &registry.prev)),
+      ("anchor-al-payload".into(), Anchor::al_data("udi", "r")),
+      ("stream-asi-payload".into(), Stream::asi_data(Format::CURRENT, "udi", "r")),
+      ("stream-payload".into(), Stream::data(Format::CURRENT, &stream.asi, &stream.groups, &stream.genesis, &stream.renew)),
+      ("stream-block-payload".into(), StreamBlock::data(Format::CURRENT, &block.record, &block.prev)),
+
+      // signed anchor registry info and group commit
+      ("anchor-info".into(), anchor.to_bytes()),
+      ("group-commit".into(), master.commit.as_bytes().to_vec()),
+
+      // content hashes (links)
+      ("card-hash".into(), card.hash().as_bytes().to_vec()),
+      ("registry-hash".into(), registry.hash().as_bytes().to_vec()),
+      ("renew-hash".into(), evolve.renew.as_ref().unwrap().hash().as_bytes().to_vec()),
+      ("stream-hash".into(), stream.hash().as_bytes().to_vec())
+    ]
+  }
+
+  #[test]
+  fn canonical_encoding() {
+    // shortest integers and sorted keys
+    assert!(to_vec(&23u64) == vec![0x17]);
+    assert!(to_vec(&24u64) == vec![0x18, 0x18]);
+    assert!(to_vec(&500usize) == vec![0x19, 0x01, 0xf4]);
+
+    let mut map = std::collections::HashMap::<String, u8>::new();
+    map.insert("bb".into(), 1);
+    map.insert("a".into(), 2);
+    map.insert("c".into(), 3);
+    assert!(hex(&to_vec(&map)) == "a3616102616303626262 01".replace(' ', ""));
+
+    // same value with a different encoding is rejected
+    assert!(from_slice::<u64>(&[0x18, 0x17], "number") == Err(Error::NonCanonical { what: "number" }));
+    assert!(from_slice::<u64>(&[0x17, 0x00], "number") == Err(Error::NonCanonical { what: "number" }));
+    assert!(from_slice::<Vec<u8>>(&[0x9f, 0x01, 0xff], "array") == Err(Error::NonCanonical { what: "array" }));
+    assert!(from_slice::<u64>(&[0x17], "number") == Ok(23));
+  }
+
+  #[test]
+  fn structures_roundtrip() {
+    for (name, bytes) in vectors().iter() {
+      let decoded = match name.as_str() {
+        "card" => to_vec(&from_slice::<Card>(bytes, "card").unwrap()),
+        "evolve" => to_vec(&from_slice::<Evolve>(bytes, "evolve").unwrap()),
+        "registry" => to_vec(&from_slice::<Registry>(bytes, "registry").unwrap()),
+        "anchor" => to_vec(&from_slice::<Anchor>(bytes, "anchor").unwrap()),
+        "stream" => to_vec(&from_slice::<Stream>(bytes, "stream").unwrap()),
+        "stream-block" => to_vec(&from_slice::<StreamBlock>(bytes, "stream block").unwrap()),
+        _ => continue
+      };
+
+      assert!(decoded == *bytes);
+    }
+
+    let card: Card = from_slice(&vectors()[0].1, "card").unwrap();
+    assert!(card.verify());
+  }
+
+  #[test]
+  fn test_vectors() {
+    let expected: Vec<(String, String)> = VECTORS.lines()
+      .filter(|line| !line.is_empty() && !line.starts_with('#'))
+      .map(|line| {
+        let mut parts = line.splitn(2, ':');
+        (parts.next().unwrap().trim().into(), parts.next().unwrap().trim().into())
+      })
+      .collect();
+
+    let found: Vec<(String, String)> = vectors().into_iter().map(|(name, bytes)| (name, hex(&bytes))).collect();
+    assert!(expected == found);
+  }
+}
@@ -0,0 +1,178 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+use serde_bytes::{Bytes, ByteBuf};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use ed25519_dalek::PublicKey;
+
+use sha2::{Sha256, Digest};
+
+use crate::structs::{Result, Error, cbor};
+use crate::structs::identity::{Card, Registry};
+
+// info with claims: prefix + canonical CBOR array of the sorted attribute digests
+pub const CLAIMS: &[u8] = b"raiap.io/claims:";
+
+// the digests of a claims info
+pub fn digests(info: &[u8]) -> Result<Vec<Vec<u8>>> {
+  let data = info.strip_prefix(CLAIMS).ok_or(Error::Deserialize { what: "claims" })?;
+  let digests: Vec<ByteBuf> = cbor::from_slice(data, "claims")?;
+  Ok(digests.into_iter().map(ByteBuf::into_vec).collect())
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Attribute (salted, only the digest is signed)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Attribute {
+  pub name: String,
+  #[serde(with = "crate::structs::json::b64")]
+  pub value: Vec<u8>,
+  #[serde(with = "crate::structs::json::b64")]
+  salt: Vec<u8>
+}
+
+impl Attribute {
+  // sha256 of the canonical CBOR array [salt, name, value]
+  pub fn digest(&self) -> Vec<u8> {
+    let data = cbor::to_vec(&(Bytes::new(&self.salt), &self.name, Bytes::new(&self.value)));
+
+    let mut hasher = Sha256::new();
+    hasher.input(&data);
+    hasher.result().to_vec()
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Claims (all attributes, kept by the holder)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Claims {
+  attributes: BTreeMap<String, Attribute>
+}
+
+impl Claims {
+  pub fn new(attributes: &[(&str, &[u8])]) -> Self {
+    let mut csprng = OsRng{};
+    let attributes = attributes.iter().map(|(name, value)| {
+      let mut salt = vec![0u8; 16];
+      csprng.fill_bytes(&mut salt);
+      (name.to_string(), Attribute { name: name.to_string(), value: value.to_vec(), salt })
+    }).collect();
+
+    Self { attributes }
+  }
+
+  pub fn get(&self, name: &str) -> Option<&Attribute> {
+    self.attributes.get(name)
+  }
+
+  // info to sign in a card or registry
+  pub fn info(&self) -> Vec<u8> {
+    let mut digests: Vec<ByteBuf> = self.attributes.values().map(|attr| ByteBuf::from(attr.digest())).collect();
+    digests.sort();
+
+    let mut info = CLAIMS.to_vec();
+    info.extend(cbor::to_vec(&digests));
+    info
+  }
+
+  // the chosen subset of attributes to reveal
+  pub fn disclose(&self, names: &[&str]) -> Result<Vec<Attribute>> {
+    names.iter()
+      .map(|name| self.attributes.get(*name).cloned().ok_or_else(|| Error::UnknownAttribute { name: name.to_string() }))
+      .collect()
+  }
+}
+
+// check the revealed attributes against the signed info
+pub fn verify(info: &[u8], disclosed: &[Attribute]) -> Result<()> {
+  let digests = digests(info)?;
+  for attr in disclosed.iter() {
+    if !digests.contains(&attr.digest()) {
+      return Err(Error::InvalidDisclosure { name: attr.name.clone() })
+    }
+  }
+
+  Ok(())
+}
+
+pub fn verify_card(card: &Card, disclosed: &[Attribute]) -> Result<()> {
+  if !card.verify() {
+    return Err(Error::InvalidCard)
+  }
+
+  verify(&card.info, disclosed)
+}
+
+pub fn verify_registry(registry: &Registry, key: &PublicKey, disclosed: &[Attribute]) -> Result<()> {
+  if !registry.verify(key) {
+    return Err(Error::InvalidRegistry { id: registry.id.clone() })
+  }
+
+  verify(&registry.info, disclosed)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::OType;
+  use crate::structs::identity::*;
+
+  use ed25519_dalek::Keypair;
+
+  fn create() -> Claims {
+    Claims::new(&[("name", b"Alice"), ("birth", b"1990-01-01"), ("country", b"PT")])
+  }
+
+  #[test]
+  fn disclose_card_attributes() {
+    let mut csprng = OsRng{};
+    let claims = create();
+
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let card = Card::new(true, &id_keypair, &claims.info(), &[master]).unwrap();
+
+    // only the country is revealed
+    let disclosed = claims.disclose(&["country"]).unwrap();
+    verify_card(&card, &disclosed).unwrap();
+    assert!(digests(&card.info).unwrap().len() == 3);
+    assert!(!card.info.windows(5).any(|w| w == b"Alice"));
+
+    // a changed value or salt doesn't match the digest
+    let mut forged = disclosed.clone();
+    forged[0].value = b"ES".to_vec();
+    assert!(verify_card(&card, &forged) == Err(Error::InvalidDisclosure { name: "country".into() }));
+
+    let other = create().disclose(&["country"]).unwrap();
+    assert!(verify_card(&card, &other) == Err(Error::InvalidDisclosure { name: "country".into() }));
+
+    assert!(claims.disclose(&["email"]) == Err(Error::UnknownAttribute { name: "email".into() }));
+    assert!(verify(b"No important info!", &disclosed) == Err(Error::Deserialize { what: "claims" }));
+  }
+
+  #[test]
+  fn disclose_registry_claims() {
+    let mut csprng = OsRng{};
+    let claims = create();
+
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", &[master]).unwrap();
+    let mut identity = Identity::new(genesis).unwrap();
+
+    let reg = Registry::new(&id_keypair, "idp.io/kyc", "claims", OType::SET, &claims.info(), identity.prev().unwrap(), 0).unwrap();
+    identity.save(reg.clone()).unwrap();
+
+    // different views of the same registry
+    verify_registry(&reg, &id_keypair.public, &claims.disclose(&["name"]).unwrap()).unwrap();
+    verify_registry(&reg, &id_keypair.public, &claims.disclose(&["birth", "country"]).unwrap()).unwrap();
+    verify_registry(&reg, &id_keypair.public, &[]).unwrap();
+
+    assert!(verify_registry(&reg, &m_keypair.public, &[]) == Err(Error::InvalidRegistry { id: "idp.io/kyc".into() }));
+  }
+}
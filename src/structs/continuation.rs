@@ -0,0 +1,200 @@
+use ed25519_dalek::PublicKey;
+
+use crate::structs::{Result, Error, OType};
+use crate::structs::signer::Signer;
+use crate::structs::identity::{Identity, Renew, TLGroup};
+use crate::structs::stream::{Stream, Chain, Record, ExtRenew};
+use crate::structs::anchor::{Anchor, Anchors, Link};
+use crate::structs::derive::{Root, Profile};
+
+//-----------------------------------------------------------------------------------------------------------
+// Profile chain (a chain of the holder with the derivation of its current profile, the key is re-derived from
+// the root when needed)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct ProfileChain {
+  pub chain: Chain,
+  pub generation: u32,
+  pub index: u32,
+  pub key: PublicKey
+}
+
+impl ProfileChain {
+  pub fn new(chain: Chain, profile: &Profile) -> Self {
+    Self { chain, generation: profile.generation, index: profile.index, key: profile.keypair.public }
+  }
+
+  // successor stream signed by the new profile and authorised by the master group of the current stream
+  fn successor(&self, udi: &str, masters: &[&dyn Signer], next: &Profile) -> Result<Stream> {
+    let (first, others) = masters.split_first().ok_or(Error::NoGroup)?;
+
+    let current = self.chain.current();
+    let mut renew = Renew::new(*first, &next.keypair.public, current.prev(), true)?;
+    for signer in others.iter() {
+      renew.cosign(*signer)?;
+    }
+
+    // the value of the current stream continues as the genesis of the successor
+    let genesis = match current.state().value() {
+      Some(entry) => entry.record.clone(),
+      None => Record { oper: OType::DEL, info: Vec::new() }
+    };
+
+    let groups: Vec<TLGroup> = current.groups.values().cloned().collect();
+    let ext_renew = ExtRenew::new(renew, &self.key);
+    Stream::new(&next.keypair, udi, &next.r, &groups, genesis, Some(ext_renew))
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Continuation of all profile chains after an identity evolution
+//   Each chain gets a successor stream with the profile of the same index derived for the evolved card at (the
+//   generation is the card index), and a new anchor. Chains already continued to that card are rejected.
+//   Nothing changes if any chain, anchor or link fails.
+//-----------------------------------------------------------------------------------------------------------
+pub fn continue_chains(identity: &mut Identity, signer: &dyn Signer, masters: &[&dyn Signer], root: &Root, at: usize, chains: &mut [ProfileChain]) -> Result<Vec<Anchor>> {
+  if !identity.is_enabled() {
+    return Err(Error::Disabled)
+  }
+
+  if at == 0 || at != identity.cards.len() - 1 || signer.public() != *identity.card().key() {
+    return Err(Error::InvalidCardKey)
+  }
+
+  let generation = at as u32;
+  if let Some(index) = chains.iter().position(|pc| pc.generation >= generation) {
+    return Err(Error::ChainContinued { index })
+  }
+
+  let mut next_identity = identity.clone();
+  let mut next_chains = Vec::<(Chain, Profile)>::with_capacity(chains.len());
+  let mut anchors = Vec::<Anchor>::with_capacity(chains.len());
+
+  for pc in chains.iter() {
+    let next = root.profile_at(generation, pc.index);
+    let stream = pc.successor(&identity.udi, masters, &next)?;
+
+    let mut chain = pc.chain.clone();
+    chain.save(stream)?;
+
+    let sn = Anchors::of(&next_identity)?.next_sn();
+    let anchor = next.anchor(&identity.udi, sn)?;
+    anchor.register(signer, &mut next_identity)?;
+
+    next_chains.push((chain, next));
+    anchors.push(anchor);
+  }
+
+  // validate the whole bundle before replacing anything
+  let registered = Anchors::of(&next_identity)?;
+  for (index, ((chain, next), anchor)) in next_chains.iter().zip(anchors.iter()).enumerate() {
+    chain.check(&next.keypair.public)?;
+
+    let link = Link::new(&next.keypair, &identity.udi, &anchor.r)?;
+    if registered.get(&anchor.al) != Some(anchor) || !link.check(&identity.udi, anchor, chain.current()) {
+      return Err(Error::InvalidAnchorLink { index })
+    }
+  }
+
+  *identity = next_identity;
+  for (pc, (chain, next)) in chains.iter_mut().zip(next_chains) {
+    *pc = ProfileChain::new(chain, &next);
+  }
+
+  Ok(anchors)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::identity::*;
+  use crate::structs::stream::*;
+  use crate::structs::anchor::DOMAIN;
+
+  use rand::rngs::OsRng;
+  use ed25519_dalek::Keypair;
+
+  fn create() -> (Identity, Keypair, Keypair, Root, Vec<ProfileChain>) {
+    let mut csprng = OsRng{};
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", std::slice::from_ref(&master)).unwrap();
+    let mut identity = Identity::new(genesis).unwrap();
+
+    // two anchored profiles with derived keys
+    let root = Root::new(&m_keypair).unwrap();
+    let mut chains = Vec::<ProfileChain>::new();
+    for index in 0..2 {
+      let profile = root.profile(index);
+      profile.anchor(&identity.udi, index as usize).unwrap().register(&id_keypair, &mut identity).unwrap();
+
+      let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+      let mut stream = Stream::new(&profile.keypair, &identity.udi, &profile.r, std::slice::from_ref(&master), genesis, None).unwrap();
+      let record = Record { oper: OType::SET, info: format!("Profile {}!", index).into_bytes() };
+      let block = StreamBlock::new(&profile.keypair, record, stream.hash()).unwrap();
+      stream.save(block).unwrap();
+
+      chains.push(ProfileChain::new(Chain::new(stream), &profile));
+    }
+
+    // identity evolution to a new key
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let cancel = Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap();
+    identity.cancel(cancel).unwrap();
+    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), true).unwrap();
+    identity.renew(renew).unwrap();
+    identity.evolve(Card::new(false, &id_keypair2, b"No important info!", &[master]).unwrap()).unwrap();
+
+    (identity, m_keypair, id_keypair2, root, chains)
+  }
+
+  #[test]
+  fn continue_profile_chains() {
+    let (mut identity, m_keypair, id_keypair2, root, mut chains) = create();
+    let old_keys: Vec<_> = chains.iter().map(|pc| pc.key).collect();
+
+    let anchors = continue_chains(&mut identity, &id_keypair2, &[&m_keypair], &root, 1, &mut chains).unwrap();
+    assert!(anchors.iter().map(|an| an.sn).collect::<Vec<_>>() == vec![2, 3]);
+    identity.verify_full().unwrap();
+
+    for (index, pc) in chains.iter().enumerate() {
+      assert!(pc.chain.streams().len() == 2);
+      assert!(pc.generation == 1 && pc.index == index as u32);
+      assert!(pc.key != old_keys[index] && pc.key == root.profile_at(1, pc.index).keypair.public);
+      pc.chain.check(&pc.key).unwrap();
+
+      // the value continues in the successor
+      let value = format!("Profile {}!", index).into_bytes();
+      assert!(pc.chain.current().state().value().unwrap().record.info == value);
+    }
+
+    // the old and new profiles are recovered with the master key
+    let profiles = Root::new(&m_keypair).unwrap().recover(&identity).unwrap();
+    assert!(profiles.len() == 4 && profiles[0].keypair.public == old_keys[0]);
+    assert!(profiles[3].generation == 1 && profiles[3].keypair.public == chains[1].key);
+
+    // the chains are only continued once per card
+    let err = continue_chains(&mut identity, &id_keypair2, &[&m_keypair], &root, 1, &mut chains).unwrap_err();
+    assert!(err == Error::ChainContinued { index: 0 });
+  }
+
+  #[test]
+  fn continue_is_atomic() {
+    let mut csprng = OsRng{};
+    let (mut identity, m_keypair, id_keypair2, root, mut chains) = create();
+    let other: Keypair = Keypair::generate(&mut csprng);
+
+    // not authorised by the master group, nothing changes
+    let before = identity.registry(DOMAIN).unwrap().len();
+    let err = continue_chains(&mut identity, &id_keypair2, &[&other], &root, 1, &mut chains).unwrap_err();
+    assert!(err == Error::NoStreamGroup { index: 0 });
+    assert!(identity.registry(DOMAIN).unwrap().len() == before);
+    assert!(chains.iter().all(|pc| pc.chain.streams().len() == 1));
+
+    // only the key of the evolved card
+    assert!(continue_chains(&mut identity, &m_keypair, &[&m_keypair], &root, 1, &mut chains).err() == Some(Error::InvalidCardKey));
+    assert!(continue_chains(&mut identity, &id_keypair2, &[&m_keypair], &root, 0, &mut chains).err() == Some(Error::InvalidCardKey));
+    continue_chains(&mut identity, &id_keypair2, &[&m_keypair], &root, 1, &mut chains).unwrap();
+  }
+}
@@ -0,0 +1,169 @@
+use hkdf::Hkdf;
+use sha2::Sha256;
+use base64::encode;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+
+use crate::structs::{Result, Error};
+use crate::structs::signer::Signer;
+use crate::structs::payload::{self, Payload, Format};
+use crate::structs::identity::Identity;
+use crate::structs::anchor::{Anchor, Anchors};
+
+// consecutive unused indexes before the recovery stops
+pub const GAP: u32 = 20;
+
+//-----------------------------------------------------------------------------------------------------------
+// Profile (keypair and anchor r of an anonymous profile)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Debug)]
+pub struct Profile {
+  pub generation: u32,  // card index the profile was derived for
+  pub index: u32,
+  pub keypair: Keypair,
+  pub r: String
+}
+
+impl Profile {
+  pub fn anchor(&self, udi: &str, sn: usize) -> Result<Anchor> {
+    Anchor::new(&self.keypair, udi, &self.r, sn)
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Root (derivation secret of a master key)
+//   The root is the master key signature over a fixed derivation message, ed25519 signatures are deterministic
+//   so any signer of the same key (including a smart card) derives the same profiles. The master key survives
+//   the identity evolutions and is recoverable with its shares, so the profiles of every card (generation) can
+//   be derived again. Never export it.
+//-----------------------------------------------------------------------------------------------------------
+pub struct Root {
+  hkdf: Hkdf<Sha256>
+}
+
+impl Root {
+  pub fn new(signer: &dyn Signer) -> Result<Self> {
+    let data = Payload::new(payload::DERIVE_ROOT, Format::CURRENT)
+      .field(&signer.public())
+      .into_bytes();
+
+    let sig = signer.sign(&data)?;
+    if signer.sign(&data)? != sig {
+      return Err(Error::Signer { reason: "non deterministic signatures".into() })
+    }
+
+    Ok(Self { hkdf: Hkdf::<Sha256>::new(Some(payload::DERIVE_ROOT.as_bytes()), &sig.to_bytes()) })
+  }
+
+  // profile of the genesis card
+  pub fn profile(&self, index: u32) -> Profile {
+    self.profile_at(0, index)
+  }
+
+  pub fn profile_at(&self, generation: u32, index: u32) -> Profile {
+    // This unwrap() should never fail, or it's a serious code bug!
+    let secret = SecretKey::from_bytes(&self.expand(payload::DERIVE_PROFILE_KEY, generation, index)).unwrap();
+    let public = PublicKey::from(&secret);
+    let r = encode(&self.expand(payload::DERIVE_PROFILE_R, generation, index));
+
+    Profile { generation, index, keypair: Keypair { secret, public }, r }
+  }
+
+  // re-derive the profiles of all registered anchors for every card (stops after GAP unused indexes)
+  pub fn recover(&self, identity: &Identity) -> Result<Vec<Profile>> {
+    let anchors = Anchors::of(identity)?;
+
+    let mut profiles = Vec::<Profile>::new();
+    for generation in 0..identity.cards.len() as u32 {
+      let mut unused = 0;
+      let mut index = 0;
+      while unused < GAP {
+        let profile = self.profile_at(generation, index);
+
+        // the AL doesn't depend on the sn
+        match anchors.get(&profile.anchor(&identity.udi, 0)?.al) {
+          None => unused += 1,
+          Some(_) => {
+            profiles.push(profile);
+            unused = 0;
+          }
+        }
+
+        index += 1;
+      }
+    }
+
+    Ok(profiles)
+  }
+
+  fn expand(&self, tag: &str, generation: u32, index: u32) -> [u8; 32] {
+    let info = Payload::new(tag, Format::CURRENT).field(&generation).field(&index).into_bytes();
+    let mut okm = [0u8; 32];
+
+    // This unwrap() should never fail, or it's a serious code bug!
+    self.hkdf.expand(&info, &mut okm).unwrap();
+    okm
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::identity::*;
+  use crate::structs::recovery;
+
+  use rand::rngs::OsRng;
+
+  #[test]
+  fn derive_profiles() {
+    let mut csprng = OsRng{};
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let root = Root::new(&m_keypair).unwrap();
+
+    // same key, same profiles
+    let profile = root.profile(3);
+    let again = Root::new(&Keypair::from_bytes(&m_keypair.to_bytes()).unwrap()).unwrap().profile(3);
+    assert!(profile.keypair.public == again.keypair.public && profile.r == again.r);
+
+    // different indexes, generations and keys are unrelated
+    assert!(root.profile(4).keypair.public != profile.keypair.public);
+    assert!(root.profile(4).r != profile.r);
+    assert!(root.profile_at(1, 3).keypair.public != profile.keypair.public);
+    assert!(root.profile_at(1, 3).r != profile.r);
+    let other: Keypair = Keypair::generate(&mut csprng);
+    assert!(Root::new(&other).unwrap().profile(3).keypair.public != profile.keypair.public);
+    assert!(profile.keypair.public != m_keypair.public);
+  }
+
+  #[test]
+  fn recover_profiles() {
+    let mut csprng = OsRng{};
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", &[master]).unwrap();
+    let mut identity = Identity::new(genesis).unwrap();
+
+    // profiles 0, 2 and 5 are anchored
+    let root = Root::new(&m_keypair).unwrap();
+    for (sn, index) in [0, 2, 5].iter().enumerate() {
+      let anchor = root.profile(*index).anchor(&identity.udi, sn).unwrap();
+      anchor.register(&id_keypair, &mut identity).unwrap();
+    }
+
+    // the identity key is replaced, profile 1 is anchored for the new card
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    identity.cancel(Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap()).unwrap();
+    identity.renew(Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), true).unwrap()).unwrap();
+    identity.evolve(Card::new(false, &id_keypair2, b"No important info!", &[master]).unwrap()).unwrap();
+    root.profile_at(1, 1).anchor(&identity.udi, 3).unwrap().register(&id_keypair2, &mut identity).unwrap();
+
+    // only the master key is needed, the lost identity key isn't
+    let restored = recovery::combine(&recovery::split(&m_keypair, 2, 3).unwrap()[1..]).unwrap();
+    let profiles = Root::new(&restored).unwrap().recover(&identity).unwrap();
+    let indexes: Vec<(u32, u32)> = profiles.iter().map(|pr| (pr.generation, pr.index)).collect();
+    assert!(indexes == vec![(0, 0), (0, 2), (0, 5), (1, 1)]);
+    assert!(profiles[1].keypair.public == root.profile(2).keypair.public);
+    assert!(profiles[3].keypair.public == root.profile_at(1, 1).keypair.public);
+  }
+}
@@ -0,0 +1,228 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Serialize, Deserialize};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use ed25519_dalek::PublicKey;
+
+use sha2::{Sha256, Digest};
+use base64::encode;
+
+use crate::structs::{Result, Error};
+use crate::structs::identity::Identity;
+use crate::structs::stream::Stream;
+
+// info of a sealed entry: marker + sha256(salt || data), the NUL bytes keep text from looking sealed
+pub const SEALED: &[u8] = b"\0raiap.io/sealed\0";
+const COMMIT: usize = 32;
+
+fn commitment(salt: &[u8], data: &[u8]) -> Vec<u8> {
+  let mut hasher = Sha256::new();
+  hasher.input(salt);
+  hasher.input(data);
+  hasher.result().to_vec()
+}
+
+// the commitment of a sealed info
+fn commit_of(info: &[u8]) -> Option<&[u8]> {
+  match info.strip_prefix(SEALED) {
+    Some(commit) if commit.len() == COMMIT => Some(commit),
+    _ => None
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Opening & Content
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Opening {
+  #[serde(with = "crate::structs::json::b64")]
+  salt: Vec<u8>,
+  #[serde(with = "crate::structs::json::b64")]
+  data: Vec<u8>
+}
+
+// content of an entry info after the vault lookup
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Content<'a> {
+  Plain(&'a [u8]),   // not sealed by this vault, can't be erased
+  Sealed(&'a [u8]),  // opened plaintext
+  Erased             // sealed by this vault and erased
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Vault (mutable storage of the openings, the chains only have the commitments)
+//   An info is only sealed if this vault holds its opening or erased it (the tombstones), anything else is plain,
+//   even with the marker, since it can't match the commitment of a secret salt.
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct Vault {
+  openings: HashMap<String, Opening>,
+  #[serde(default)]
+  erased: HashSet<String>
+}
+
+impl Vault {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  // returns the info to sign in a registry or stream record
+  pub fn seal(&mut self, data: &[u8]) -> Vec<u8> {
+    let mut csprng = OsRng{};
+    let mut salt = vec![0u8; 32];
+    csprng.fill_bytes(&mut salt);
+
+    let commit = commitment(&salt, data);
+    self.openings.insert(encode(&commit), Opening { salt, data: data.into() });
+
+    let mut info = SEALED.to_vec();
+    info.extend(commit);
+    info
+  }
+
+  pub fn is_sealed(&self, info: &[u8]) -> bool {
+    match commit_of(info).map(encode) {
+      None => false,
+      Some(key) => self.openings.contains_key(&key) || self.erased.contains(&key)
+    }
+  }
+
+  pub fn open<'a>(&'a self, info: &'a [u8]) -> Result<Content<'a>> {
+    let commit = match commit_of(info) {
+      None => return Ok(Content::Plain(info)),
+      Some(commit) => commit
+    };
+
+    let key = encode(commit);
+    match self.openings.get(&key) {
+      Some(op) if commitment(&op.salt, &op.data) == commit => Ok(Content::Sealed(&op.data)),
+      Some(_) => Err(Error::InvalidOpening),
+      None if self.erased.contains(&key) => Ok(Content::Erased),
+      None => Ok(Content::Plain(info))
+    }
+  }
+
+  // destroy the plaintext (and salt) of a sealed info and keep a tombstone, returns false if there is nothing to erase
+  pub fn erase(&mut self, info: &[u8]) -> bool {
+    let key = match commit_of(info) {
+      None => return false,
+      Some(commit) => encode(commit)
+    };
+
+    match self.openings.remove(&key) {
+      None => false,
+      Some(_) => self.erased.insert(key)
+    }
+  }
+
+  // verify the stream signatures and open every record (genesis first)
+  pub fn verify_stream<'a>(&'a self, stream: &'a Stream, key: &PublicKey) -> Result<Vec<Content<'a>>> {
+    stream.verify_stream(key)?;
+
+    let mut records = vec![&stream.genesis];
+    records.extend(stream.blocks.iter().map(|bl| &bl.record));
+    records.into_iter().map(|rec| self.open(&rec.info)).collect()
+  }
+
+  // verify the identity and open every entry of a registry
+  pub fn verify_registry<'a>(&'a self, identity: &'a Identity, id: &str) -> Result<Vec<Content<'a>>> {
+    identity.verify_full()?;
+
+    match identity.registry(id) {
+      None => Ok(Vec::new()),
+      Some(chain) => chain.iter().map(|reg| self.open(&reg.info)).collect()
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::OType;
+  use crate::structs::identity::*;
+  use crate::structs::stream::*;
+
+  use ed25519_dalek::Keypair;
+
+  #[test]
+  fn erase_registry() {
+    let mut csprng = OsRng{};
+    let mut vault = Vault::new();
+
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", &[master]).unwrap();
+    let mut identity = Identity::new(genesis).unwrap();
+
+    let info = vault.seal(b"Personal data!");
+    assert!(vault.is_sealed(&info) && !vault.is_sealed(b"Personal data!"));
+    let reg1 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, &info, identity.prev().unwrap(), 0).unwrap();
+    identity.save(reg1.clone()).unwrap();
+
+    let reg2 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Public data!", reg1.hash(), 0).unwrap();
+    identity.save(reg2).unwrap();
+
+    let contents = vault.verify_registry(&identity, "idp.io/test").unwrap();
+    assert!(contents == vec![Content::Sealed(b"Personal data!"), Content::Plain(b"Public data!")]);
+
+    // the chain is still valid after the erasure
+    assert!(vault.erase(&reg1.info));
+    assert!(!vault.erase(&reg1.info));
+    let contents = vault.verify_registry(&identity, "idp.io/test").unwrap();
+    assert!(contents == vec![Content::Erased, Content::Plain(b"Public data!")]);
+    assert!(vault.is_sealed(&reg1.info));
+
+    // never held by this vault, the info is shown as signed
+    let other = Vault::new();
+    assert!(!other.is_sealed(&reg1.info));
+    assert!(other.open(&reg1.info) == Ok(Content::Plain(&reg1.info)));
+  }
+
+  #[test]
+  fn erase_stream() {
+    let mut csprng = OsRng{};
+    let mut vault = Vault::new();
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let mut stream = Stream::new(&profile_keypair, "udi-random", "r-random", &[], genesis, None).unwrap();
+    let record = Record { oper: OType::SET, info: vault.seal(b"Personal data!") };
+    let block = StreamBlock::new(&profile_keypair, record, stream.hash()).unwrap();
+    stream.save(block).unwrap();
+
+    let contents = vault.verify_stream(&stream, &profile_keypair.public).unwrap();
+    assert!(contents[1] == Content::Sealed(b"Personal data!"));
+
+    assert!(vault.erase(&stream.blocks[0].record.info));
+    let contents = vault.verify_stream(&stream, &profile_keypair.public).unwrap();
+    assert!(contents == vec![Content::Plain(b"Not important!"), Content::Erased]);
+
+    // a vault with a different plaintext for the commitment
+    let mut tampered = Vault::new();
+    let info = tampered.seal(b"Personal data!");
+    let opening = tampered.openings.values_mut().next().unwrap();
+    opening.data = b"Other data!".to_vec();
+    assert!(tampered.open(&info) == Err(Error::InvalidOpening));
+  }
+
+  #[test]
+  fn plain_with_marker() {
+    let mut vault = Vault::new();
+    vault.seal(b"Personal data!");
+
+    // bytes with the marker are only sealed if the vault made them
+    let mut info = SEALED.to_vec();
+    info.extend(vec![7u8; COMMIT]);
+    assert!(!vault.is_sealed(&info));
+    assert!(vault.open(&info) == Ok(Content::Plain(&info)));
+    assert!(!vault.erase(&info));
+
+    // the erasure survives the vault storage
+    let info = vault.seal(b"Other data!");
+    assert!(vault.erase(&info));
+    let stored: Vault = serde_json::from_str(&serde_json::to_string(&vault).unwrap()).unwrap();
+    assert!(stored.open(&info) == Ok(Content::Erased));
+  }
+}
@@ -0,0 +1,239 @@
+use std::fmt;
+
+use serde::{Serialize, Deserialize};
+use crate::structs::hash::Hash;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ErrorKind {
+  Signature,      // a signature or commit doesn't verify
+  Chain,          // a structure doesn't link to the expected previous one
+  State,          // the operation is not allowed in the current state
+  Authorization,  // no group (or not enough members) authorised the operation
+  Format,         // malformed or inconsistent input
+  Storage         // failure of the storage backend or signing token
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Error {
+  // identity and evolutions
+  InvalidGenesis,
+  InvalidCard,
+  InvalidCardKey,
+  InvalidCancel,
+  InvalidRenew,
+  InvalidChain { expected: Hash, found: Hash },
+  InvalidUdi,
+  InvalidEvolutions,
+  InvalidEnabled,
+  Disabled,
+  NoEvolutions,
+  MissingCancel,
+  MissingRenew,
+  MissingRenewKey,
+  EvolveInProgress,
+  InvalidRenewState,
+  Closed,
+  EvolveEnabled,
+  EvolveToGenesis,
+
+  // event logs
+  InvalidSequence { expected: usize, found: usize },
+  UnexpectedGenesis,
+
+  // groups
+  NoGroup,
+  ThresholdNotReached,
+  InvalidThreshold,
+  OnlyMasterClose,
+
+  // registries
+  InvalidKeyIndex { id: String, expected: usize, found: usize },
+  InvalidRegistry { id: String },
+  InvalidRegistryChain { id: String, expected: Hash, found: Hash },
+  InvalidRegistryType { id: String, expected: String, found: String },
+  InvalidLegacyChain,
+  MissingReanchor { id: String },
+  InvalidReanchor { id: String },
+
+  // streams and chains
+  InvalidStreamGenesis,
+  InvalidBlock { index: usize },
+  InvalidBlockChain { index: usize, expected: Hash, found: Hash },
+  InvalidStreamChain { index: usize, expected: Hash, found: Hash },
+  InvalidAccumulator,
+  MissingStreamRenew,
+  NoStreamGroup { index: usize },
+  ChainWithoutKey { index: usize },
+  ChainInvalidEnd,
+  InvalidPage { offset: usize },
+  InvalidStreamOper { index: usize },
+
+  // merkle proofs
+  InvalidCheckpoint,
+  InvalidInclusionProof { index: usize },
+  InvalidConsistencyProof { first: usize, second: usize },
+
+  // ledger
+  InvalidCommitment,
+  UnauthorisedCommitment { subject: String },
+  UnknownCommitment { subject: String },
+  Equivocation { subject: String },
+  UnrelatedCopies,
+
+  // secret sharing
+  InvalidSharesThreshold,
+  NoShares,
+  InconsistentShares,
+  NotEnoughShares { required: usize, found: usize },
+  InvalidRecoveredKey,
+
+  // erasure and disclosure
+  InvalidOpening,
+  InvalidDisclosure { name: String },
+  UnknownAttribute { name: String },
+  InvalidChallenge,
+  InvalidReceipt,
+  UnknownAnchor { r: String },
+
+  // anchors
+  InvalidAnchorSn { last: usize, found: usize },
+  InvalidAnchorLink { index: usize },
+  ChainContinued { index: usize },
+  AnchorRevoked { al: String },
+  NoAnchorStream { al: String },
+
+  // encodings and storage
+  Deserialize { what: &'static str },
+  NonCanonical { what: &'static str },
+  Storage { reason: String },
+  Signer { reason: String }
+}
+
+impl Error {
+  pub fn kind(&self) -> ErrorKind {
+    use Error::*;
+    match self {
+      InvalidGenesis | InvalidCard | InvalidCardKey | InvalidCancel | InvalidRenew | InvalidRegistry { .. }
+        | InvalidStreamGenesis | InvalidBlock { .. } | InvalidRecoveredKey | InvalidOpening
+        | InvalidDisclosure { .. } | InvalidChallenge | InvalidReceipt | InvalidCheckpoint
+        | InvalidCommitment => ErrorKind::Signature,
+
+      InvalidChain { .. } | InvalidUdi | InvalidEvolutions | InvalidEnabled | InvalidRegistryChain { .. } | InvalidRegistryType { .. } | InvalidKeyIndex { .. }
+        | InvalidLegacyChain
+        | MissingReanchor { .. } | InvalidReanchor { .. }
+        | InvalidBlockChain { .. } | InvalidStreamChain { .. } | InvalidAccumulator | ChainWithoutKey { .. } | ChainInvalidEnd => ErrorKind::Chain,
+
+      InvalidAnchorSn { .. } | InvalidAnchorLink { .. } => ErrorKind::Chain,
+
+      InvalidInclusionProof { .. } | InvalidConsistencyProof { .. } => ErrorKind::Chain,
+
+      UnknownCommitment { .. } | Equivocation { .. } => ErrorKind::Chain,
+
+      InvalidSequence { .. } | UnexpectedGenesis => ErrorKind::Chain,
+
+      Disabled | NoEvolutions | MissingCancel | MissingRenew | EvolveInProgress | InvalidRenewState
+        | Closed | EvolveEnabled | EvolveToGenesis | UnknownAnchor { .. }
+        | AnchorRevoked { .. } | NoAnchorStream { .. } | ChainContinued { .. } => ErrorKind::State,
+
+      NoGroup | ThresholdNotReached | OnlyMasterClose | NoStreamGroup { .. } | UnauthorisedCommitment { .. } => ErrorKind::Authorization,
+
+      MissingRenewKey | InvalidThreshold | MissingStreamRenew | InvalidSharesThreshold | NoShares
+        | InconsistentShares | NotEnoughShares { .. } | Deserialize { .. }
+        | NonCanonical { .. } | UnknownAttribute { .. } | InvalidPage { .. } | InvalidStreamOper { .. }
+        | UnrelatedCopies => ErrorKind::Format,
+
+      Storage { .. } | Signer { .. } => ErrorKind::Storage
+    }
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    use Error::*;
+    match self {
+      InvalidGenesis => write!(f, "Invalid genesis card!"),
+      InvalidCard => write!(f, "Invalid card!"),
+      InvalidCardKey => write!(f, "The card key is not valid!"),
+      InvalidCancel => write!(f, "Invalid cancel!"),
+      InvalidRenew => write!(f, "Invalid renew!"),
+      InvalidChain { .. } => write!(f, "Invalid chain!"),
+      InvalidUdi => write!(f, "The udi doesn't match the genesis card!"),
+      InvalidEvolutions => write!(f, "Number of cards doesn't match the evolutions!"),
+      InvalidEnabled => write!(f, "Enabled flag doesn't match the evolutions!"),
+      Disabled => write!(f, "Identity is disabled!"),
+      NoEvolutions => write!(f, "Identity is disabled, must have evolutions!"),
+      MissingCancel => write!(f, "Expected to find cancel!"),
+      MissingRenew => write!(f, "A renew must exist to evolve!"),
+      MissingRenewKey => write!(f, "Renew must have a key!"),
+      EvolveInProgress => write!(f, "Evolve is already in progress!"),
+      InvalidRenewState => write!(f, "Identity in invalid state to perform a renew!"),
+      Closed => write!(f, "Identity closed permanently!"),
+      EvolveEnabled => write!(f, "Cannot evolve an enabled identity!"),
+      EvolveToGenesis => write!(f, "Cannot evolve to a genesis card!"),
+
+      InvalidSequence { expected, found } => write!(f, "Invalid log sequence (expected {}, found {})!", expected, found),
+      UnexpectedGenesis => write!(f, "Genesis must be the first log event!"),
+
+      NoGroup => write!(f, "No group found to evolve!"),
+      ThresholdNotReached => write!(f, "Group threshold not reached!"),
+      InvalidThreshold => write!(f, "Invalid group threshold!"),
+      OnlyMasterClose => write!(f, "Only master groups can close permanently!"),
+
+      InvalidKeyIndex { id, expected, found } => write!(f, "Invalid key index on {} (expected {}, found {})!", id, expected, found),
+      InvalidRegistry { id } => write!(f, "Invalid registry on {}!", id),
+      InvalidRegistryChain { id, .. } => write!(f, "Invalid chain on {}!", id),
+      InvalidRegistryType { id, expected, found } => write!(f, "Invalid chain (dif type) on {} (expected {}, found {})!", id, expected, found),
+      InvalidLegacyChain => write!(f, "Invalid chain of legacy signatures!"),
+      MissingReanchor { id } => write!(f, "Registry {} continues with a new key without a re-anchor!", id),
+      InvalidReanchor { id } => write!(f, "Invalid re-anchor on {}!", id),
+
+      InvalidStreamGenesis => write!(f, "Invalid genesis signature!"),
+      InvalidBlock { index } => write!(f, "Invalid block signature at {}!", index),
+      InvalidBlockChain { index, .. } => write!(f, "Invalid stream chain at block {}!", index),
+      InvalidStreamChain { index, .. } => write!(f, "Invalid stream chain at stream {}!", index),
+      InvalidAccumulator => write!(f, "Stream accumulator doesn't match the blocks!"),
+      MissingStreamRenew => write!(f, "Stream requires a renew block!"),
+      NoStreamGroup { index } => write!(f, "No group found on previous stream {}!", index),
+      ChainWithoutKey { index } => write!(f, "Chain contains more streams without a stream key at stream {}!", index),
+      ChainInvalidEnd => write!(f, "Chain with invalid end!"),
+      InvalidPage { offset } => write!(f, "Missing or invalid chain page at stream {}!", offset),
+      InvalidStreamOper { index } => write!(f, "Re-anchor not allowed in streams at record {}!", index),
+
+      InvalidCheckpoint => write!(f, "Invalid stream checkpoint!"),
+      InvalidInclusionProof { index } => write!(f, "Block {} is not included in the checkpoint!", index),
+      InvalidConsistencyProof { first, second } => write!(f, "Stream of {} blocks is not a prefix of {} blocks!", first, second),
+
+      InvalidCommitment => write!(f, "Invalid head commitment!"),
+      UnauthorisedCommitment { subject } => write!(f, "Commitment key doesn't own {}!", subject),
+      UnknownCommitment { subject } => write!(f, "Head commitment of {} references an unknown previous head!", subject),
+      Equivocation { subject } => write!(f, "Equivocation found for {}!", subject),
+      UnrelatedCopies => write!(f, "Copies of different identities or streams!"),
+
+      InvalidSharesThreshold => write!(f, "Invalid threshold for the number of shares!"),
+      NoShares => write!(f, "No shares to combine!"),
+      InconsistentShares => write!(f, "Inconsistent shares!"),
+      NotEnoughShares { required, found } => write!(f, "Not enough shares (required {}, found {})!", required, found),
+      InvalidRecoveredKey => write!(f, "Recovered key doesn't match the shares commit!"),
+
+      InvalidOpening => write!(f, "Sealed data doesn't match the commitment!"),
+      InvalidDisclosure { name } => write!(f, "Disclosed attribute {} doesn't match the signed digests!", name),
+      UnknownAttribute { name } => write!(f, "Unknown attribute {}!", name),
+      InvalidChallenge => write!(f, "Invalid disclosure challenge!"),
+      InvalidReceipt => write!(f, "Invalid disclosure receipt!"),
+      UnknownAnchor { r } => write!(f, "No anchor registered for {}!", r),
+
+      InvalidAnchorSn { last, found } => write!(f, "Anchor sn must increase (last {}, found {})!", last, found),
+      InvalidAnchorLink { index } => write!(f, "Stream {} is not linked to a registered anchor!", index),
+      ChainContinued { index } => write!(f, "Profile chain {} is already continued to this card!", index),
+      AnchorRevoked { al } => write!(f, "Anchor {} is revoked!", al),
+      NoAnchorStream { al } => write!(f, "No stream found for anchor {}!", al),
+
+      Deserialize { what } => write!(f, "Unable to deserialize {}!", what),
+      NonCanonical { what } => write!(f, "Non canonical encoding of {}!", what),
+      Storage { reason } => write!(f, "Storage failure: {}!", reason),
+      Signer { reason } => write!(f, "Signer failure: {}!", reason)
+    }
+  }
+}
+
+impl std::error::Error for Error {}
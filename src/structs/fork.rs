@@ -0,0 +1,375 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use serde::{Serialize, Deserialize};
+use ed25519_dalek::PublicKey;
+
+use crate::structs::{Result, Error, Hash, json};
+use crate::structs::identity::{Identity, Card, Cancel, Renew, Registry};
+use crate::structs::stream::{Stream, StreamBlock};
+
+//-----------------------------------------------------------------------------------------------------------
+// Signed (any signed object of a history)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Signed {
+  Card(Card),
+  Cancel(Cancel),
+  Renew(Renew),
+  Registry(Registry),
+  Stream(Box<Stream>),  // without blocks
+  Block(StreamBlock)
+}
+
+impl Signed {
+  pub fn hash(&self) -> Hash {
+    match self {
+      Signed::Card(card) => card.hash(),
+      Signed::Cancel(ev) => ev.hash(),
+      Signed::Renew(ev) => ev.hash(),
+      Signed::Registry(reg) => reg.hash(),
+      Signed::Stream(st) => st.hash(),
+      Signed::Block(bl) => bl.hash()
+    }
+  }
+
+  // cards and stream genesis don't link to a previous object
+  pub fn prev(&self) -> Option<Hash> {
+    match self {
+      Signed::Card(_) | Signed::Stream(_) => None,
+      Signed::Cancel(ev) => Some(ev.prev),
+      Signed::Renew(ev) => Some(ev.prev),
+      Signed::Registry(reg) => Some(reg.prev),
+      Signed::Block(bl) => Some(bl.prev)
+    }
+  }
+
+  pub fn verify(&self, key: &PublicKey) -> bool {
+    match self {
+      Signed::Card(card) => card.key() == key && card.verify(),
+      Signed::Cancel(ev) => ev.signers()[0] == *key && ev.verify(),
+      Signed::Renew(ev) => ev.verify(key),
+      Signed::Registry(reg) => reg.verify(key),
+      Signed::Stream(st) => st.verify(key),
+      Signed::Block(bl) => bl.verify(key)
+    }
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Fork proof (two different objects at the same point of a history, signed by the same key)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub enum Point {
+  Evolution { position: usize },            // genesis card, then the cancel, renew and card of each evolution
+  Registry { id: String, index: usize },
+  Stream { position: usize }                // 0 is the genesis, then the blocks
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ForkProof {
+  pub at: Point,
+  pub prev: Option<Hash>,    // hash of the last common object
+  pub first: Signed,
+  pub second: Signed
+}
+
+impl ForkProof {
+  // anyone with the key of the signer can check the proof, without the rest of the histories
+  pub fn verify(&self, key: &PublicKey) -> bool {
+    let links = [self.first.prev(), self.second.prev()].iter().all(|prev| prev.is_none() || *prev == self.prev);
+    self.is_same_point() && links && self.first.hash() != self.second.hash() && self.first.verify(key) && self.second.verify(key)
+  }
+
+  // both objects are of the same kind, at the point of the proof, of the same identity, registry or stream
+  fn is_same_point(&self) -> bool {
+    match (&self.at, &self.first, &self.second) {
+      // genesis cards of the same udi (the commit of the genesis key)
+      (Point::Evolution { position: 0 }, Signed::Card(a), Signed::Card(b)) =>
+        a.is_genesis && b.is_genesis && a.key() == b.key() && self.prev.is_none(),
+      // any two successors of the same object (e.g. a cancel and a direct renew of the same card)
+      (Point::Evolution { position }, a, b) if is_evolution(a) && is_evolution(b) =>
+        *position > 0 && self.prev.is_some(),
+      (Point::Registry { id, .. }, Signed::Registry(a), Signed::Registry(b)) =>
+        a.id == *id && b.id == *id && self.prev.is_some(),
+      (Point::Stream { position: 0 }, Signed::Stream(a), Signed::Stream(b)) =>
+        a.asi == b.asi && self.prev.is_none(),
+      (Point::Stream { position }, Signed::Block(_), Signed::Block(_)) =>
+        *position > 0 && self.prev.is_some(),
+      _ => false
+    }
+  }
+
+  pub fn to_json(&self) -> String {
+    json::to_string(self)
+  }
+}
+
+// cancels, renews and evolved cards
+fn is_evolution(signed: &Signed) -> bool {
+  match signed {
+    Signed::Card(card) => !card.is_genesis,
+    Signed::Cancel(_) | Signed::Renew(_) => true,
+    _ => false
+  }
+}
+
+#[derive(Debug, Clone)]
+pub enum Relation {
+  Equal,
+  Prefix,                   // the first copy is a prefix of the second
+  Extension,                // the second copy is a prefix of the first
+  Concurrent,               // each copy has entries of other registries that the other lacks, without conflicts
+  Fork(Box<ForkProof>)
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Comparison of copies (only the hashes are compared, each copy should be verified before)
+//-----------------------------------------------------------------------------------------------------------
+pub fn compare_identities(first: &Identity, second: &Identity) -> Result<Relation> {
+  if first.udi != second.udi {
+    return Err(Error::UnrelatedCopies)
+  }
+
+  let mut relations = vec![compare(&history(first), &history(second), |position| Point::Evolution { position })];
+
+  let ids: BTreeSet<&String> = first.db.keys().chain(second.db.keys()).collect();
+  for id in ids {
+    let chain = |identity: &Identity| -> Vec<Signed> {
+      identity.registry(id).map(|chain| chain.iter().cloned().map(Signed::Registry).collect()).unwrap_or_default()
+    };
+
+    relations.push(compare(&chain(first), &chain(second), |index| Point::Registry { id: id.clone(), index }));
+  }
+
+  Ok(combine(relations))
+}
+
+pub fn compare_streams(first: &Stream, second: &Stream) -> Result<Relation> {
+  if first.asi != second.asi {
+    return Err(Error::UnrelatedCopies)
+  }
+
+  let history = |stream: &Stream| -> Vec<Signed> {
+    let mut history = vec![Signed::Stream(Box::new(stream.without_blocks()))];
+    history.extend(stream.blocks.iter().cloned().map(Signed::Block));
+    history
+  };
+
+  Ok(compare(&history(first), &history(second), |position| Point::Stream { position }))
+}
+
+// cards and evolutions in order
+fn history(identity: &Identity) -> Vec<Signed> {
+  let mut history = Vec::<Signed>::new();
+  for (index, card) in identity.cards.iter().enumerate() {
+    history.push(Signed::Card(card.clone()));
+    if let Some(evol) = identity.evols.get(index) {
+      history.extend(evol.cancel.clone().map(Signed::Cancel));
+      history.extend(evol.renew.clone().map(Signed::Renew));
+    }
+  }
+
+  history
+}
+
+fn compare<F: Fn(usize) -> Point>(first: &[Signed], second: &[Signed], at: F) -> Relation {
+  let mut prev: Option<Hash> = None;
+  for (index, (a, b)) in first.iter().zip(second.iter()).enumerate() {
+    let hash = a.hash();
+    if hash != b.hash() {
+      return Relation::Fork(Box::new(ForkProof { at: at(index), prev, first: a.clone(), second: b.clone() }))
+    }
+
+    prev = Some(hash);
+  }
+
+  match first.len().cmp(&second.len()) {
+    Ordering::Equal => Relation::Equal,
+    Ordering::Less => Relation::Prefix,
+    Ordering::Greater => Relation::Extension
+  }
+}
+
+// the first fork, otherwise the relation of all parts
+fn combine(relations: Vec<Relation>) -> Relation {
+  let (mut shorter, mut longer) = (false, false);
+  for relation in relations.into_iter() {
+    match relation {
+      Relation::Fork(proof) => return Relation::Fork(proof),
+      Relation::Prefix => shorter = true,
+      Relation::Extension => longer = true,
+      _ => ()
+    }
+  }
+
+  match (shorter, longer) {
+    (false, false) => Relation::Equal,
+    (true, false) => Relation::Prefix,
+    (false, true) => Relation::Extension,
+    (true, true) => Relation::Concurrent
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::OType;
+  use crate::structs::identity::*;
+  use crate::structs::stream::*;
+
+  use rand::rngs::OsRng;
+  use ed25519_dalek::Keypair;
+
+  fn create() -> (Identity, Keypair, Keypair) {
+    let mut csprng = OsRng{};
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", &[master]).unwrap();
+    let mut identity = Identity::new(genesis).unwrap();
+    identity.append(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!").unwrap();
+
+    (identity, m_keypair, id_keypair)
+  }
+
+  #[test]
+  fn prefix_of_identity() {
+    let (identity, _, id_keypair) = create();
+    assert!(matches!(compare_identities(&identity, &identity.clone()).unwrap(), Relation::Equal));
+
+    let mut newer = identity.clone();
+    newer.append(&id_keypair, "idp.io/test", "test", OType::SET, b"More info!").unwrap();
+    newer.append(&id_keypair, "idp.io/other", "other", OType::SET, b"Other info!").unwrap();
+    assert!(matches!(compare_identities(&identity, &newer).unwrap(), Relation::Prefix));
+    assert!(matches!(compare_identities(&newer, &identity).unwrap(), Relation::Extension));
+
+    // new entries in different registries don't conflict
+    let mut other = identity.clone();
+    other.append(&id_keypair, "idp.io/kyc", "claims", OType::SET, b"Claims!").unwrap();
+    assert!(matches!(compare_identities(&newer, &other).unwrap(), Relation::Concurrent));
+
+    let (unrelated, _, _) = create();
+    assert!(compare_identities(&identity, &unrelated).err() == Some(Error::UnrelatedCopies));
+  }
+
+  #[test]
+  fn fork_of_identity() {
+    let (identity, m_keypair, id_keypair) = create();
+
+    // two registry entries after the same one
+    let mut copy1 = identity.clone();
+    copy1.append(&id_keypair, "idp.io/test", "test", OType::SET, b"More info!").unwrap();
+    let mut copy2 = identity.clone();
+    copy2.append(&id_keypair, "idp.io/test", "test", OType::DEL, b"").unwrap();
+
+    let proof = match compare_identities(&copy1, &copy2).unwrap() {
+      Relation::Fork(proof) => proof,
+      _ => panic!("expected a fork")
+    };
+
+    assert!(proof.at == Point::Registry { id: "idp.io/test".into(), index: 1 });
+    assert!(proof.prev == Some(identity.head("idp.io/test")));
+    assert!(proof.verify(&id_keypair.public) && !proof.verify(&m_keypair.public));
+
+    // the proof is published as JSON
+    let loaded: ForkProof = json::from_str(&proof.to_json(), "fork proof").unwrap();
+    assert!(loaded.verify(&id_keypair.public));
+
+    // two evolutions of the same card
+    let mut csprng = OsRng{};
+    let (mut copy1, mut copy2) = (identity.clone(), identity.clone());
+    copy1.cancel(Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap()).unwrap();
+    copy2.cancel(Cancel::new(true, &m_keypair, identity.prev().unwrap()).unwrap()).unwrap();
+
+    let proof = match compare_identities(&copy1, &copy2).unwrap() {
+      Relation::Fork(proof) => proof,
+      _ => panic!("expected a fork")
+    };
+
+    assert!(proof.at == Point::Evolution { position: 1 } && proof.prev == Some(identity.card().hash()));
+    assert!(proof.verify(&m_keypair.public));
+
+    // a cancel and a direct renew of the same card
+    let next: Keypair = Keypair::generate(&mut csprng);
+    let mut renewed = identity.clone();
+    renewed.renew(Renew::new(&m_keypair, &next.public, identity.prev().unwrap(), true).unwrap()).unwrap();
+
+    let mixed = match compare_identities(&copy1, &renewed).unwrap() {
+      Relation::Fork(proof) => proof,
+      _ => panic!("expected a fork")
+    };
+
+    assert!(mixed.at == Point::Evolution { position: 1 } && matches!(mixed.second, Signed::Renew(_)));
+    assert!(mixed.verify(&m_keypair.public));
+
+    // but not of different objects
+    let mut unlinked = (*mixed).clone();
+    unlinked.second = Signed::Renew(Renew::new(&m_keypair, &next.public, identity.head("idp.io/test"), true).unwrap());
+    assert!(!unlinked.verify(&m_keypair.public));
+    unlinked.second = Signed::Registry(identity.registry("idp.io/test").unwrap()[0].clone());
+    assert!(!unlinked.verify(&m_keypair.public));
+
+    // the point must match the objects
+    let mut moved = (*proof).clone();
+    moved.at = Point::Evolution { position: 0 };
+    assert!(!moved.verify(&m_keypair.public));
+    moved.at = Point::Registry { id: "idp.io/test".into(), index: 1 };
+    assert!(!moved.verify(&m_keypair.public));
+  }
+
+  #[test]
+  fn unrelated_objects() {
+    let (mut identity, _, id_keypair) = create();
+    identity.append(&id_keypair, "idp.io/a", "test", OType::SET, b"Not important!").unwrap();
+    identity.append(&id_keypair, "idp.io/b", "test", OType::SET, b"Not important!").unwrap();
+
+    // the first entries of two registries link to the same card, but they are not a fork
+    let (a, b) = (identity.registry("idp.io/a").unwrap()[0].clone(), identity.registry("idp.io/b").unwrap()[0].clone());
+    let mut proof = ForkProof { at: Point::Registry { id: "idp.io/a".into(), index: 0 }, prev: Some(a.prev), first: Signed::Registry(a.clone()), second: Signed::Registry(b) };
+    assert!(!proof.verify(&id_keypair.public));
+    proof.at = Point::Registry { id: "idp.io/b".into(), index: 0 };
+    assert!(!proof.verify(&id_keypair.public));
+
+    // a registry entry and a stream block of the same key
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let stream = Stream::new(&id_keypair, &identity.udi, "r-random", &[], genesis, None).unwrap();
+    let block = StreamBlock::new(&id_keypair, Record { oper: OType::SET, info: b"New info!".to_vec() }, a.prev).unwrap();
+    let proof = ForkProof { at: Point::Stream { position: 1 }, prev: Some(a.prev), first: Signed::Registry(a), second: Signed::Block(block) };
+    assert!(!proof.verify(&id_keypair.public));
+
+    // two streams with different asi
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let other = Stream::new(&id_keypair, &identity.udi, "other-random", &[], genesis, None).unwrap();
+    let proof = ForkProof { at: Point::Stream { position: 0 }, prev: None, first: Signed::Stream(Box::new(stream)), second: Signed::Stream(Box::new(other)) };
+    assert!(!proof.verify(&id_keypair.public));
+  }
+
+  #[test]
+  fn fork_of_stream() {
+    let mut csprng = OsRng{};
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let mut stream = Stream::new(&profile_keypair, "udi-random", "r-random", &[], genesis, None).unwrap();
+    let block = StreamBlock::new(&profile_keypair, Record { oper: OType::SET, info: b"New info!".to_vec() }, stream.prev()).unwrap();
+    stream.save(block).unwrap();
+
+    let (mut copy1, mut copy2) = (stream.clone(), stream.clone());
+    copy1.save(StreamBlock::new(&profile_keypair, Record { oper: OType::SET, info: b"More info!".to_vec() }, stream.prev()).unwrap()).unwrap();
+    assert!(matches!(compare_streams(&stream, &copy1).unwrap(), Relation::Prefix));
+
+    copy2.save(StreamBlock::new(&profile_keypair, Record { oper: OType::DEL, info: Vec::new() }, stream.prev()).unwrap()).unwrap();
+    match compare_streams(&copy1, &copy2).unwrap() {
+      Relation::Fork(proof) => {
+        assert!(proof.at == Point::Stream { position: 2 } && proof.prev == Some(stream.prev()));
+        assert!(proof.verify(&profile_keypair.public));
+
+        // a proof with the same object twice is not valid
+        let mut same = proof.clone();
+        same.second = same.first.clone();
+        assert!(!same.verify(&profile_keypair.public));
+      },
+      _ => panic!("expected a fork")
+    }
+  }
+}
@@ -0,0 +1,83 @@
+use std::fmt;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use sha2::{Sha256, Digest};
+use ciborium::value::Value;
+
+use crate::structs::cbor;
+use crate::structs::json::b64;
+
+pub const HASH_LENGTH: usize = 32;
+
+//-----------------------------------------------------------------------------------------------------------
+// Hash (SHA-256 content hash, structures link to the hash of the previous one)
+//   The content hash of a structure is the SHA-256 of the canonical CBOR array [domain tag, fields], where
+//   fields is the structure map without its own signature (and without the blocks of a stream). Keys, key
+//   indexes and approvals are covered, so links can be verified without the signatures and a link pins the
+//   signers of the previous object.
+//-----------------------------------------------------------------------------------------------------------
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Hash([u8; HASH_LENGTH]);
+
+impl Hash {
+  pub fn of(data: &[u8]) -> Self {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+
+    let mut hash = [0u8; HASH_LENGTH];
+    hash.copy_from_slice(hasher.result().as_slice());
+    Self(hash)
+  }
+
+  // content hash of a structure without the skipped fields
+  pub(crate) fn content<T: Serialize>(tag: &str, value: &T, skip: &[&str]) -> Self {
+    let fields = match cbor::value(value) {
+      Value::Map(entries) => Value::Map(entries.into_iter()
+        .filter(|(key, _)| !matches!(key, Value::Text(name) if skip.contains(&name.as_str())))
+        .collect()),
+      other => other
+    };
+
+    Self::of(&cbor::write(&Value::Array(vec![Value::Text(tag.into()), fields])))
+  }
+
+  pub fn from_bytes(data: &[u8]) -> Option<Self> {
+    match data.len() {
+      HASH_LENGTH => {
+        let mut hash = [0u8; HASH_LENGTH];
+        hash.copy_from_slice(data);
+        Some(Self(hash))
+      },
+      _ => None
+    }
+  }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+impl fmt::Display for Hash {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", base64::encode_config(&self.0, base64::URL_SAFE_NO_PAD))
+  }
+}
+
+impl fmt::Debug for Hash {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Hash({})", self)
+  }
+}
+
+// byte string in CBOR, base64url in JSON
+impl Serialize for Hash {
+  fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    b64::serialize(self, serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for Hash {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+    b64::deserialize(deserializer)
+  }
+}
@@ -1,13 +1,17 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::collections::HashMap;
 
 use serde::{Serialize, Deserialize};
-use ed25519_dalek::{Keypair, PublicKey, Signature};
+use ed25519_dalek::{PublicKey, Signature};
 
 use sha2::{Sha256, Digest};
 use base64::encode;
 
-use crate::structs::{Result, OType};
+use crate::structs::{Result, Error, OType, State, Hash};
+use crate::structs::signer::Signer;
+use crate::structs::payload::{self, Payload, Format, Migration};
+use crate::structs::{json, cbor};
+use crate::structs::report::{IdentityReport, Failure, Stage, Authorisation};
 
 pub fn commit(key: &PublicKey) -> String {
   let mut hasher = Sha256::new();
@@ -32,15 +36,31 @@ pub struct Identity {
 
 impl Identity {
   pub fn new(genesis: Card) -> Result<Self> {
-    if !genesis.verify() {
-      return Err("Invalid genesis card!".into())
+    Self::new_as(genesis, &mut Migration::current())
+  }
+
+  fn new_as(genesis: Card, migration: &mut Migration) -> Result<Self> {
+    if migration.verify(|format| genesis.verify_as(format)).is_none() {
+      return Err(Error::InvalidGenesis)
     }
 
     Ok(Self { udi: commit(&genesis.key), cards: vec![genesis], evols: Vec::new(), db: HashMap::new(), enabled: true })
   }
 
+  // identity restored from its stored evolution history, the signatures aren't verified
+  pub(crate) fn from_history(cards: Vec<Card>, evols: Vec<Evolve>) -> Result<Self> {
+    let genesis = cards.first().ok_or(Error::InvalidGenesis)?;
+    if cards.len() != evols.len() + 1 && cards.len() != evols.len() {
+      return Err(Error::InvalidEvolutions)
+    }
+
+    // the last evolution is in progress until the evolved card is added
+    let enabled = cards.len() > evols.len();
+    Ok(Self { udi: commit(&genesis.key), cards, evols, db: HashMap::new(), enabled })
+  }
+
   pub fn is_enabled(&self) -> bool {
-    return self.enabled;
+    self.enabled
   }
 
   pub fn card(&self) -> &Card {
@@ -52,14 +72,54 @@ impl Identity {
     self.db.get(id)
   }
 
-  pub fn prev(&self) -> Result<&Signature> {
+  // hash that the next entry of a registry must reference
+  pub fn head(&self, id: &str) -> Hash {
+    match self.db.get(id).and_then(|chain| chain.last()) {
+      None => self.card().hash(),
+      Some(last) => last.hash()
+    }
+  }
+
+  // current value of the registry after applying SET/DEL in order
+  pub fn state(&self, id: &str) -> State<&Registry> {
+    match self.db.get(id) {
+      None => State::Empty,
+      Some(chain) => State::of(chain.iter().enumerate().map(|(index, reg)| (index, &reg.oper, reg)))
+    }
+  }
+
+  // value of the registry as of entry n (inclusive), None if the registry has no entry n
+  pub fn state_at(&self, id: &str, n: usize) -> Option<State<&Registry>> {
+    let chain = self.db.get(id).filter(|chain| n < chain.len())?;
+    Some(State::of(chain.iter().enumerate().take(n + 1).map(|(index, reg)| (index, &reg.oper, reg))))
+  }
+
+  // current values of all registries that are not deleted
+  pub fn values(&self) -> BTreeMap<&str, &Registry> {
+    self.db.keys()
+      .filter_map(|id| self.state(id).value().map(|reg| (id.as_str(), *reg)))
+      .collect()
+  }
+
+  // hash of the cards, evolutions and registry heads (commits to the whole identity)
+  pub fn digest(&self) -> Hash {
+    let cards: Vec<Hash> = self.cards.iter().map(Card::hash).collect();
+    let evols: Vec<(Option<Hash>, Option<Hash>)> = self.evols.iter()
+      .map(|ev| (ev.cancel.as_ref().map(Cancel::hash), ev.renew.as_ref().map(Renew::hash)))
+      .collect();
+    let heads: BTreeMap<&str, Hash> = self.db.keys().map(|id| (id.as_str(), self.head(id))).collect();
+
+    Hash::of(&cbor::to_vec(&(&self.udi, cards, evols, heads)))
+  }
+
+  pub fn prev(&self) -> Result<Hash> {
     match self.enabled {
-      true => Ok(&self.card().sig),
+      true => Ok(self.card().hash()),
       false => match self.evols.last() {
-        None => Err("Identity is disabled, must have evolutions!".into()),
+        None => Err(Error::NoEvolutions),
         Some(current) => match &current.renew {
-          Some(ev) => Ok(&ev.sig),
-          None => Ok(&current.cancel.as_ref().ok_or("Expected to find cancel!")?.sig)
+          Some(ev) => Ok(ev.hash()),
+          None => Ok(current.cancel.as_ref().ok_or(Error::MissingCancel)?.hash())
         }
       }
     }
@@ -67,121 +127,114 @@ impl Identity {
 
   pub fn save(&mut self, registry: Registry) -> Result<()> {
     if !self.enabled {
-      return Err("Identity is disabled!".into())
+      return Err(Error::Disabled)
     }
 
     { // scope for immutable borrow
       let card = self.card();
       if self.cards.len() - 1 != registry.key_index {
-        return Err("Invalid key index!".into())
+        return Err(Error::InvalidKeyIndex { id: registry.id.clone(), expected: self.cards.len() - 1, found: registry.key_index })
       }
 
       if !registry.verify(&card.key) {
-        return Err("Invalid registry!".into())
+        return Err(Error::InvalidRegistry { id: registry.id.clone() })
       }
-    }
 
-    let chain = self.db.get_mut(&registry.id);
-    match chain {
-      None => {
-        let card = self.card();
-        if card.sig != registry.prev {
-          return Err("Invalid chain!".into())
-        }
-
-        let id = registry.id.clone();
-        let reg = vec![registry];
-        self.db.insert(id, reg);
-      },
-
-      Some(reg) => {
-        // should always exist
-        let current = reg.last().unwrap();
-        if current.sig != registry.prev {
-          return Err("Invalid chain!".into())
-        }
+      let last = self.db.get(&registry.id).and_then(|reg| reg.last());
+      self.check_link(&registry.id, last, &registry)?;
+    }
 
-        if registry.typ != current.typ {
-          return Err("Invalid chain (dif type)!".into())
-        }
+    self.db.entry(registry.id.clone()).or_default().push(registry);
+    Ok(())
+  }
 
-        reg.push(registry);
+  // sign and save the next entry of a registry (re-anchored to the current card if needed)
+  pub fn append(&mut self, signer: &dyn Signer, id: &str, typ: &str, oper: OType, info: &[u8]) -> Result<()> {
+    let key_index = self.cards.len() - 1;
+    if let Some(last) = self.db.get(id).and_then(|chain| chain.last()) {
+      if last.key_index != key_index {
+        let reanchor = Registry::reanchor(signer, last, self.card(), key_index)?;
+        self.save(reanchor)?;
       }
     }
 
-    Ok(())
+    let reg = Registry::new(signer, id, typ, oper, info, self.head(id), key_index)?;
+    self.save(reg)
   }
 
   pub fn cancel(&mut self, ev: Cancel) -> Result<()> {
+    self.cancel_as(ev, &mut Migration::current())
+  }
+
+  fn cancel_as(&mut self, ev: Cancel, migration: &mut Migration) -> Result<()> {
     let card = self.card();
 
     // identity must be enabled
     if !self.enabled {
-      return Err("Evolve is already in progress!".into())
+      return Err(Error::EvolveInProgress)
     }
 
     // the last card must be referenced
-    if card.sig != ev.prev {
-      return Err("Invalid chain!".into())
+    if card.hash() != ev.prev {
+      return Err(Error::InvalidChain { expected: card.hash(), found: ev.prev })
     }
 
     // verify signature and public-key
-    if !ev.verify() {
-      return Err("Invalid cancel!".into())
+    if migration.verify(|format| ev.verify_as(format)).is_none() {
+      return Err(Error::InvalidCancel)
     }
 
     // get the corresponding card group and disable identity
-    let commit = commit(&ev.key);
-    match card.groups.get(&commit) {
-      None => Err("No group found to evolve!".into()),
-      Some(gr) => {
-        if ev.is_close && gr.typ != TLType::MASTER {
-          return Err("Only master groups can close permanently!".into())
-        }
-
-        self.enabled = false;
-        self.evols.push(Evolve { cancel: Some(ev), renew: None });
-        Ok(())
-      }
+    let gr = authorize(&card.groups, &ev.signers())?;
+    if ev.is_close && gr.typ != TLType::MASTER {
+      return Err(Error::OnlyMasterClose)
     }
+
+    self.enabled = false;
+    self.evols.push(Evolve { cancel: Some(ev), renew: None });
+    Ok(())
   }
 
   pub fn renew(&mut self, ev: Renew) -> Result<()> {
+    self.renew_as(ev, &mut Migration::current())
+  }
+
+  fn renew_as(&mut self, ev: Renew, migration: &mut Migration) -> Result<()> {
     let card = self.card();
 
     // get the key to verify the signature
     let (key, evol) = match self.enabled {
       true => {
         // the last card must be referenced
-        if card.sig != ev.prev {
-          return Err("Invalid chain!".into())
+        if card.hash() != ev.prev {
+          return Err(Error::InvalidChain { expected: card.hash(), found: ev.prev })
         }
 
         // renew must also perform cancel
         match ev.key {
-          None => return Err("Renew(cancel) must have a key!".into()),
+          None => return Err(Error::MissingRenewKey),
           Some(key) => (key, Evolve { cancel: None, renew: Some(ev) })
         }
       },
       false => {
         // renew must evolve from an existing cancel
         match self.evols.last() {
-          None => return Err("Identity is disabled, must have evolutions!".into()),
+          None => return Err(Error::NoEvolutions),
           Some(current) => {
             if current.cancel.is_none() || current.renew.is_some() {
-              return Err("Identity in invalid state to perform a renew!".into())
+              return Err(Error::InvalidRenewState)
             }
 
             let cancel = current.cancel.as_ref().unwrap();
             
             // is it closed permanently?
             if cancel.is_close {
-              return Err("Identity closed permanently!".into())
+              return Err(Error::Closed)
             }
 
             // the last cancel must be referenced
-            if cancel.sig != ev.prev {
-              return Err("Invalid chain!".into())
+            if cancel.hash() != ev.prev {
+              return Err(Error::InvalidChain { expected: cancel.hash(), found: ev.prev })
             }
 
             (cancel.key, Evolve { cancel: Some(cancel.clone()), renew: Some(ev) })
@@ -191,51 +244,51 @@ impl Identity {
     };
 
     // verify signature and public-key
-    if !evol.renew.as_ref().unwrap().verify(&key) {
-      return Err("Invalid renew!".into())
+    let renew = evol.renew.as_ref().unwrap();
+    if migration.verify(|format| renew.verify_as(&key, format)).is_none() {
+      return Err(Error::InvalidRenew)
     }
 
     // get the corresponding card group and disable identity
-    let commit = commit(&key);
-    match card.groups.get(&commit) {
-      None => Err("No group found to evolve!".into()),
+    authorize(&card.groups, &renew.signers(&key))?;
+    //TODO: can I evolve to a new key?
+
+    self.enabled = false;
+    match evol.cancel {
+      None => self.evols.push(evol),  // push new evolve
       Some(_) => {
-        //TODO: can I evolve to a new key?
-
-        self.enabled = false;
-        match evol.cancel {
-          None => self.evols.push(evol),  // push new evolve
-          Some(_) => {
-            // replace existing evolve
-            let index = self.evols.len() - 1;
-            self.evols[index] = evol;
-          }            
-        }
-        
-        Ok(())
+        // replace existing evolve
+        let index = self.evols.len() - 1;
+        self.evols[index] = evol;
       }
     }
+
+    Ok(())
   }
 
   pub fn evolve(&mut self, card: Card) -> Result<()> {
+    self.evolve_as(card, &mut Migration::current())
+  }
+
+  fn evolve_as(&mut self, card: Card, migration: &mut Migration) -> Result<()> {
     if self.enabled {
-      return Err("Cannot evolve an enabled identity!".into())
+      return Err(Error::EvolveEnabled)
     }
 
     if card.is_genesis {
-      return Err("Cannot evolve to a genesis card!".into())
+      return Err(Error::EvolveToGenesis)
     }
 
     let renew = self.evols.last().as_ref()
-      .ok_or("Identity is disabled, must have evolutions!")?.renew.as_ref()
-      .ok_or("A renew must exist to evolve!")?;
+      .ok_or(Error::NoEvolutions)?.renew.as_ref()
+      .ok_or(Error::MissingRenew)?;
     
     if renew.commit != commit(&card.key) {
-      return Err("The card key is not valid!".into())
+      return Err(Error::InvalidCardKey)
     }
 
-    if !card.verify() {
-      return Err("Invalid card!".into())
+    if migration.verify(|format| card.verify_as(format)).is_none() {
+      return Err(Error::InvalidCard)
     }
 
     self.enabled = true;
@@ -243,6 +296,188 @@ impl Identity {
 
     Ok(())
   }
+
+  // replay the genesis, evolutions and registries of an identity received from a peer
+  pub fn verify_full(&self) -> Result<()> {
+    self.audit(Migration::current(), &mut IdentityReport::of(self)).map_err(|(_, err)| err)
+  }
+
+  // verification report of the evolution history and registries (stops at the first failure)
+  pub fn report(&self) -> IdentityReport {
+    let mut report = IdentityReport::of(self);
+    if let Err((stage, err)) = self.audit(Migration::current(), &mut report) {
+      report.failure = Some(Failure::new(stage, &err));
+    }
+
+    report
+  }
+
+  fn audit(&self, migration: Migration, report: &mut IdentityReport) -> std::result::Result<(), (Stage, Error)> {
+    let genesis = self.cards.first().ok_or((Stage::Genesis, Error::InvalidGenesis))?;
+    if !genesis.is_genesis {
+      return Err((Stage::Genesis, Error::InvalidGenesis))
+    }
+
+    let mut history = migration;
+    let mut replay = Identity::new_as(genesis.clone(), &mut history).map_err(|err| (Stage::Genesis, err))?;
+    if replay.udi != self.udi {
+      return Err((Stage::Genesis, Error::InvalidUdi))
+    }
+
+    // each evolve is followed by the evolved card, except the last one that may be in progress
+    if self.cards.len() != self.evols.len() + 1 && self.cards.len() != self.evols.len() {
+      return Err((Stage::Genesis, Error::InvalidEvolutions))
+    }
+
+    for (i, evol) in self.evols.iter().enumerate() {
+      let at = |err| (Stage::Evolution { index: i }, err);
+      let groups = replay.card().groups.clone();
+
+      if let Some(cancel) = evol.cancel.as_ref() {
+        replay.cancel_as(cancel.clone(), &mut history).map_err(at)?;
+        let signers = cancel.signers();
+        report.evolutions[i].cancel = authorize(&groups, &signers).ok().map(|gr| Authorisation::new(gr, &signers));
+      }
+
+      if let Some(renew) = evol.renew.as_ref() {
+        replay.renew_as(renew.clone(), &mut history).map_err(at)?;
+        if let Some(key) = renew.key.or_else(|| evol.cancel.as_ref().map(|ev| ev.key)) {
+          let signers = renew.signers(&key);
+          report.evolutions[i].renew = authorize(&groups, &signers).ok().map(|gr| Authorisation::new(gr, &signers));
+        }
+      }
+
+      if let Some(card) = self.cards.get(i + 1) {
+        replay.evolve_as(card.clone(), &mut history).map_err(at)?;
+        report.evolutions[i].card = Some(i + 1);
+      }
+    }
+
+    if replay.enabled != self.enabled {
+      return Err((Stage::Evolution { index: self.evols.len() }, Error::InvalidEnabled))
+    }
+
+    // sorted to report the same first violation on every run
+    let mut ids: Vec<&String> = self.db.keys().collect();
+    ids.sort();
+    for id in ids {
+      self.verify_registry(id, &self.db[id], migration)
+        .map_err(|(index, err)| (Stage::Registry { id: id.clone(), index }, err))?;
+    }
+
+    Ok(())
+  }
+
+  // errors with the index of the failing entry
+  fn verify_registry(&self, id: &str, chain: &[Registry], mut migration: Migration) -> std::result::Result<(), (usize, Error)> {
+    let mut last: Option<&Registry> = None;
+    for (index, reg) in chain.iter().enumerate() {
+      self.verify_entry(id, last, reg, &mut migration).map_err(|err| (index, err))?;
+      last = Some(reg);
+    }
+
+    match last {
+      None => Err((0, Error::InvalidRegistry { id: id.into() })),
+      Some(_) => Ok(())
+    }
+  }
+
+  fn verify_entry(&self, id: &str, last: Option<&Registry>, reg: &Registry, migration: &mut Migration) -> Result<()> {
+    if reg.id != id {
+      return Err(Error::InvalidRegistry { id: id.into() })
+    }
+
+    // key index can only move forward to existing cards
+    let min_index = last.map(|prev| prev.key_index).unwrap_or(0);
+    if reg.key_index >= self.cards.len() || reg.key_index < min_index {
+      return Err(Error::InvalidKeyIndex { id: id.into(), expected: min_index, found: reg.key_index })
+    }
+
+    let key = &self.cards[reg.key_index].key;
+    if migration.verify(|format| reg.verify_as(key, format)).is_none() {
+      return Err(Error::InvalidRegistry { id: id.into() })
+    }
+
+    self.check_link(id, last, reg)
+  }
+
+  // hash links of the evolutions and registries, no signature is verified
+  pub fn verify_links(&self) -> Result<()> {
+    for (i, evol) in self.evols.iter().enumerate() {
+      let card = self.cards.get(i).ok_or(Error::InvalidEvolutions)?;
+
+      let mut expected = card.hash();
+      if let Some(cancel) = evol.cancel.as_ref() {
+        if cancel.prev != expected {
+          return Err(Error::InvalidChain { expected, found: cancel.prev })
+        }
+
+        expected = cancel.hash();
+      }
+
+      if let Some(renew) = evol.renew.as_ref() {
+        if renew.prev != expected {
+          return Err(Error::InvalidChain { expected, found: renew.prev })
+        }
+      }
+    }
+
+    let mut ids: Vec<&String> = self.db.keys().collect();
+    ids.sort();
+    for id in ids {
+      let mut last: Option<&Registry> = None;
+      for reg in self.db[id].iter() {
+        if reg.key_index >= self.cards.len() {
+          return Err(Error::InvalidKeyIndex { id: id.clone(), expected: self.cards.len() - 1, found: reg.key_index })
+        }
+
+        self.check_link(id, last, reg)?;
+        last = Some(reg);
+      }
+    }
+
+    Ok(())
+  }
+
+  // the entry references the card of its key index (first entry) or the hash of the last entry
+  fn check_link(&self, id: &str, last: Option<&Registry>, reg: &Registry) -> Result<()> {
+    match last {
+      None => {
+        if reg.oper == OType::REANCHOR {
+          return Err(Error::InvalidReanchor { id: id.into() })
+        }
+
+        let card = &self.cards[reg.key_index];
+        if card.hash() != reg.prev {
+          return Err(Error::InvalidRegistryChain { id: id.into(), expected: card.hash(), found: reg.prev })
+        }
+      },
+
+      Some(prev) => {
+        if prev.hash() != reg.prev {
+          return Err(Error::InvalidRegistryChain { id: id.into(), expected: prev.hash(), found: reg.prev })
+        }
+
+        if prev.typ != reg.typ {
+          return Err(Error::InvalidRegistryType { id: id.into(), expected: prev.typ.clone(), found: reg.typ.clone() })
+        }
+
+        self.check_handover(prev, reg)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  // a chain only moves to a newer card key with a re-anchor entry that references the card
+  fn check_handover(&self, last: &Registry, reg: &Registry) -> Result<()> {
+    match (reg.key_index == last.key_index, reg.oper == OType::REANCHOR) {
+      (true, false) => Ok(()),
+      (false, false) => Err(Error::MissingReanchor { id: reg.id.clone() }),
+      (false, true) if reg.key_index > last.key_index && reg.info == self.cards[reg.key_index].hash().as_bytes() => Ok(()),
+      _ => Err(Error::InvalidReanchor { id: reg.id.clone() })
+    }
+  }
 }
 
 //-----------------------------------------------------------------------------------------------------------
@@ -251,58 +486,140 @@ impl Identity {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Card {
   pub is_genesis: bool,
+  #[serde(with = "crate::structs::json::b64")]
   pub info: Vec<u8>,
   pub groups: BTreeMap<String, TLGroup>,
+  #[serde(with = "crate::structs::json::b64")]
   pub sig: Signature,
+  #[serde(with = "crate::structs::json::b64")]
   key: PublicKey
 }
 
 impl Card {
-  pub fn new(is_genesis: bool, keypair: &Keypair, info: &[u8], groups: &[TLGroup]) -> Self {
+  pub fn new(is_genesis: bool, signer: &dyn Signer, info: &[u8], groups: &[TLGroup]) -> Result<Self> {
     let mut g_map = BTreeMap::<String, TLGroup>::new();
-    for gr in groups.into_iter() {
+    for gr in groups.iter() {
       g_map.insert(gr.commit.clone(), gr.clone());
     }
 
-    let sig_data = Self::data(is_genesis, info, &g_map);
-    let sig = keypair.sign(&sig_data);
+    let sig_data = Self::data(Format::CURRENT, is_genesis, info, &g_map);
+    let sig = signer.sign(&sig_data)?;
+
+    Ok(Self { is_genesis, info: info.into(), groups: g_map, sig, key: signer.public() })
+  }
+
+  pub fn key(&self) -> &PublicKey {
+    &self.key
+  }
 
-    Self { is_genesis, info: info.into(), groups: g_map, sig, key: keypair.public }
+  pub fn hash(&self) -> Hash {
+    Hash::content(payload::CARD, self, &["sig"])
   }
 
   pub fn verify(&self) -> bool {
-    let sig_data = Self::data(self.is_genesis, &self.info, &self.groups);
-    self.key.verify(&sig_data, &self.sig).is_ok()
+    self.verify_as(Format::CURRENT)
   }
 
-  fn data(is_genesis: bool, info: &[u8], groups: &BTreeMap<String, TLGroup>) -> Vec<u8> {
-    let mut data = Vec::<u8>::new();
+  fn verify_as(&self, format: Format) -> bool {
+    // group commits must cover the member set and threshold
+    if !self.groups.iter().all(|(key, gr)| *key == gr.commit && gr.is_valid()) {
+      return false
+    }
 
-    // These unwrap() should never fail, or it's a serious code bug!
-    data.extend(bincode::serialize(&is_genesis).unwrap());
-    data.extend(bincode::serialize(info).unwrap());
-    data.extend(bincode::serialize(groups).unwrap());
-    
-    data
+    let sig_data = Self::data(format, self.is_genesis, &self.info, &self.groups);
+    self.key.verify(&sig_data, &self.sig).is_ok()
+  }
+
+  pub(crate) fn data(format: Format, is_genesis: bool, info: &[u8], groups: &BTreeMap<String, TLGroup>) -> Vec<u8> {
+    Payload::new(payload::CARD, format)
+      .field(&is_genesis)
+      .bytes(info)
+      .field(groups)
+      .into_bytes()
   }
 }
 
 //-----------------------------------------------------------------------------------------------------------
 // TLType & TLGroup
 //-----------------------------------------------------------------------------------------------------------
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub enum TLType { MASTER, SLAVE }
 
+#[allow(clippy::manual_non_exhaustive)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TLGroup {
   pub typ: TLType,
+  pub threshold: usize,
+  pub members: BTreeSet<String>,  // key commits
   pub commit: String,
   #[serde(skip)] _phantom: ()
 }
 
 impl TLGroup {
   pub fn new(typ: TLType, key: &PublicKey) -> Self {
-    Self { typ, commit: commit(key), _phantom: () }
+    let members: BTreeSet<String> = vec![commit(key)].into_iter().collect();
+    let commit = Self::group_commit(&typ, 1, &members);
+
+    Self { typ, threshold: 1, members, commit, _phantom: () }
+  }
+
+  pub fn multi(typ: TLType, threshold: usize, keys: &[PublicKey]) -> Result<Self> {
+    let members: BTreeSet<String> = keys.iter().map(commit).collect();
+    if threshold == 0 || threshold > members.len() {
+      return Err(Error::InvalidThreshold)
+    }
+
+    let commit = Self::group_commit(&typ, threshold, &members);
+    Ok(Self { typ, threshold, members, commit, _phantom: () })
+  }
+
+  pub fn is_member(&self, key: &PublicKey) -> bool {
+    self.members.contains(&commit(key))
+  }
+
+  pub fn is_valid(&self) -> bool {
+    self.threshold != 0 && self.threshold <= self.members.len()
+      && self.commit == Self::group_commit(&self.typ, self.threshold, &self.members)
+  }
+
+  // all keys must be distinct members and reach the threshold
+  pub fn check(&self, keys: &[PublicKey]) -> bool {
+    let signers: BTreeSet<String> = keys.iter().map(commit).collect();
+    signers.len() == keys.len() && signers.len() >= self.threshold && signers.is_subset(&self.members)
+  }
+
+  // hash of the canonical CBOR array [tag, version, typ, threshold, members]
+  fn group_commit(typ: &TLType, threshold: usize, members: &BTreeSet<String>) -> String {
+    let data = Payload::new(payload::GROUP, Format::CURRENT)
+      .field(typ)
+      .field(&threshold)
+      .field(members)
+      .into_bytes();
+
+    let mut hasher = Sha256::new();
+    hasher.input(&data);
+    let result = hasher.result();
+
+    encode(&result)
+  }
+}
+
+// find the group authorised by the signers (master groups have precedence)
+pub fn authorize<'a>(groups: &'a BTreeMap<String, TLGroup>, keys: &[PublicKey]) -> Result<&'a TLGroup> {
+  let mut found: Option<&TLGroup> = None;
+  let mut is_member = false;
+  for gr in groups.values() {
+    is_member |= keys.iter().any(|key| gr.is_member(key));
+    if gr.check(keys) && (found.is_none() || gr.typ == TLType::MASTER) {
+      found = Some(gr);
+    }
+  }
+
+  match found {
+    Some(gr) => Ok(gr),
+    None if is_member => Err(Error::ThresholdNotReached),
+    None => Err(Error::NoGroup)
   }
 }
 
@@ -315,75 +632,135 @@ pub struct Evolve {
   pub renew: Option<Renew>
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Approval {
+  #[serde(with = "crate::structs::json::b64")]
+  pub key: PublicKey,
+  #[serde(with = "crate::structs::json::b64")]
+  pub sig: Signature
+}
+
+impl Approval {
+  fn new(signer: &dyn Signer, sig_data: &[u8]) -> Result<Self> {
+    Ok(Self { key: signer.public(), sig: signer.sign(sig_data)? })
+  }
+
+  fn verify(approvals: &[Approval], sig_data: &[u8]) -> bool {
+    approvals.iter().all(|ap| ap.key.verify(sig_data, &ap.sig).is_ok())
+  }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Cancel {
   pub is_close: bool,
-  pub prev: Signature,
+  pub prev: Hash,
+  #[serde(with = "crate::structs::json::b64")]
   pub sig: Signature,
+  pub approvals: Vec<Approval>,
+  #[serde(with = "crate::structs::json::b64")]
   key: PublicKey
 }
 
 impl Cancel {
-  pub fn new(is_close: bool, keypair: &Keypair, prev: &Signature) -> Self {
-    let sig_data = Self::data(is_close, prev);
-    let sig = keypair.sign(&sig_data);
+  pub fn new(is_close: bool, signer: &dyn Signer, prev: Hash) -> Result<Self> {
+    let sig_data = Self::data(Format::CURRENT, is_close, &prev);
+    let sig = signer.sign(&sig_data)?;
+
+    Ok(Self { is_close, prev, sig, approvals: Vec::new(), key: signer.public() })
+  }
+
+  pub fn hash(&self) -> Hash {
+    Hash::content(payload::CANCEL, self, &["sig"])
+  }
+
+  // signature of other group members over the same cancel
+  pub fn cosign(&mut self, signer: &dyn Signer) -> Result<()> {
+    let sig_data = Self::data(Format::CURRENT, self.is_close, &self.prev);
+    self.approvals.push(Approval::new(signer, &sig_data)?);
+    Ok(())
+  }
 
-    Self { is_close, prev: prev.clone(), sig, key: keypair.public }
+  pub fn signers(&self) -> Vec<PublicKey> {
+    let mut keys = vec![self.key];
+    keys.extend(self.approvals.iter().map(|ap| ap.key));
+    keys
   }
 
   pub fn verify(&self) -> bool {
-    let sig_data = Self::data(self.is_close, &self.prev);
-    self.key.verify(&sig_data, &self.sig).is_ok()
+    self.verify_as(Format::CURRENT)
   }
 
-  fn data(is_close: bool, prev: &Signature) -> Vec<u8> {
-    let mut data = Vec::<u8>::new();
+  fn verify_as(&self, format: Format) -> bool {
+    let sig_data = Self::data(format, self.is_close, &self.prev);
+    self.key.verify(&sig_data, &self.sig).is_ok() && Approval::verify(&self.approvals, &sig_data)
+  }
 
-    // These unwrap() should never fail, or it's a serious code bug!
-    data.extend(bincode::serialize(&is_close).unwrap());
-    data.extend(bincode::serialize(prev).unwrap());
-    
-    data
+  pub(crate) fn data(format: Format, is_close: bool, prev: &Hash) -> Vec<u8> {
+    Payload::new(payload::CANCEL, format)
+      .field(&is_close)
+      .field(prev)
+      .into_bytes()
   }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Renew {
   pub commit: String,
-  pub prev: Signature,
+  pub prev: Hash,
+  #[serde(with = "crate::structs::json::b64")]
   pub sig: Signature,
-  pub key: Option<PublicKey>
+  #[serde(with = "crate::structs::json::b64::option")]
+  pub key: Option<PublicKey>,
+  pub approvals: Vec<Approval>
 }
 
 impl Renew {
-  pub fn new(keypair: &Keypair, next: &PublicKey, prev: &Signature, inc_key: bool) -> Self {
+  pub fn new(signer: &dyn Signer, next: &PublicKey, prev: Hash, inc_key: bool) -> Result<Self> {
     let commit = commit(next);
 
-    let sig_data = Self::data(&commit, &prev);
-    let sig = keypair.sign(&sig_data);
+    let sig_data = Self::data(Format::CURRENT, &commit, &prev);
+    let sig = signer.sign(&sig_data)?;
 
     let key = if inc_key {
-      Some(keypair.public)
+      Some(signer.public())
     } else {
       None
     };
 
-    Self { commit, prev: prev.clone(), sig, key }
+    Ok(Self { commit, prev, sig, key, approvals: Vec::new() })
+  }
+
+  pub fn hash(&self) -> Hash {
+    Hash::content(payload::RENEW, self, &["sig"])
+  }
+
+  // signature of other group members over the same renew
+  pub fn cosign(&mut self, signer: &dyn Signer) -> Result<()> {
+    let sig_data = Self::data(Format::CURRENT, &self.commit, &self.prev);
+    self.approvals.push(Approval::new(signer, &sig_data)?);
+    Ok(())
+  }
+
+  pub fn signers(&self, key: &PublicKey) -> Vec<PublicKey> {
+    let mut keys = vec![*key];
+    keys.extend(self.approvals.iter().map(|ap| ap.key));
+    keys
   }
 
   pub fn verify(&self, key: &PublicKey) -> bool {
-    let sig_data = Self::data(&self.commit, &self.prev);
-    key.verify(&sig_data, &self.sig).is_ok()
+    self.verify_as(key, Format::CURRENT)
   }
 
-  fn data(commit: &str, prev: &Signature) -> Vec<u8> {
-    let mut data = Vec::<u8>::new();
+  fn verify_as(&self, key: &PublicKey, format: Format) -> bool {
+    let sig_data = Self::data(format, &self.commit, &self.prev);
+    key.verify(&sig_data, &self.sig).is_ok() && Approval::verify(&self.approvals, &sig_data)
+  }
 
-    // These unwrap() should never fail, or it's a serious code bug!
-    data.extend(bincode::serialize(commit).unwrap());
-    data.extend(bincode::serialize(prev).unwrap());
-    
-    data
+  pub(crate) fn data(format: Format, commit: &str, prev: &Hash) -> Vec<u8> {
+    Payload::new(payload::RENEW, format)
+      .field(commit)
+      .field(prev)
+      .into_bytes()
   }
 }
 
@@ -396,42 +773,67 @@ pub struct Registry {
   pub typ: String,
   pub oper: OType,
 
+  #[serde(with = "crate::structs::json::b64")]
   pub info: Vec<u8>,
-  pub prev: Signature,
+  pub prev: Hash,
+  #[serde(with = "crate::structs::json::b64")]
   pub sig: Signature,
   key_index: usize
 }
 
 impl Registry {
-  pub fn new(keypair: &Keypair, id: &str, typ: &str, oper: OType, info: &[u8], prev: &Signature, key_index: usize) -> Self {
-    let sig_data = Self::data(&id, &typ, &oper, &info, prev);
-    let sig = keypair.sign(&sig_data);
+  pub fn new(signer: &dyn Signer, id: &str, typ: &str, oper: OType, info: &[u8], prev: Hash, key_index: usize) -> Result<Self> {
+    let sig_data = Self::data(Format::CURRENT, id, typ, &oper, info, &prev);
+    let sig = signer.sign(&sig_data)?;
+
+    Ok(Self { id: id.into(), typ: typ.into(), oper, info: info.into(),  prev, sig, key_index })
+  }
+
+  pub fn key_index(&self) -> usize {
+    self.key_index
+  }
 
-    Self { id: id.into(), typ: typ.into(), oper, info: info.into(),  prev: prev.clone(), sig, key_index }
+  pub fn hash(&self) -> Hash {
+    Hash::content(payload::REGISTRY, self, &["sig"])
+  }
+
+  // migrate an existing chain to the key of an evolved card (signed by the new key)
+  pub fn reanchor(signer: &dyn Signer, head: &Registry, card: &Card, key_index: usize) -> Result<Self> {
+    Self::new(signer, &head.id, &head.typ, OType::REANCHOR, card.hash().as_bytes(), head.hash(), key_index)
+  }
+
+  pub fn to_compact(&self) -> String {
+    json::to_compact(json::REGISTRY_COMPACT, self)
+  }
+
+  pub fn from_compact(compact: &str) -> Result<Self> {
+    json::from_compact(json::REGISTRY_COMPACT, compact, "registry")
   }
 
   pub fn verify(&self, key: &PublicKey) -> bool {
-    let sig_data = Self::data(&self.id, &self.typ, &self.oper, &self.info, &self.prev);
-    key.verify(&sig_data, &self.sig).is_ok()
+    self.verify_as(key, Format::CURRENT)
   }
 
-  fn data(id: &str, typ: &str, oper: &OType, info: &[u8], prev: &Signature) -> Vec<u8> {
-    let mut data = Vec::<u8>::new();
+  fn verify_as(&self, key: &PublicKey, format: Format) -> bool {
+    let sig_data = Self::data(format, &self.id, &self.typ, &self.oper, &self.info, &self.prev);
+    key.verify(&sig_data, &self.sig).is_ok()
+  }
 
-    // These unwrap() should never fail, or it's a serious code bug!
-    data.extend(bincode::serialize(id).unwrap());
-    data.extend(bincode::serialize(typ).unwrap());
-    data.extend(bincode::serialize(oper).unwrap());
-    data.extend(bincode::serialize(info).unwrap());
-    data.extend(bincode::serialize(prev).unwrap());
-    
-    data
+  pub(crate) fn data(format: Format, id: &str, typ: &str, oper: &OType, info: &[u8], prev: &Hash) -> Vec<u8> {
+    Payload::new(payload::REGISTRY, format)
+      .field(id)
+      .field(typ)
+      .field(oper)
+      .bytes(info)
+      .field(prev)
+      .into_bytes()
   }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::structs::State;
   use rand::rngs::OsRng;
   use ed25519_dalek::Keypair;
 
@@ -444,7 +846,7 @@ mod tests {
 
     // create genesis card and identity
     let id_keypair: Keypair = Keypair::generate(&mut csprng);
-    let genesis = Card::new(true, &id_keypair, b"No important info!", &vec![master.clone()]);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", std::slice::from_ref(&master)).unwrap();
     let identity = Identity::new(genesis).unwrap();
     
     (identity, master, m_keypair, id_keypair)
@@ -457,18 +859,18 @@ mod tests {
     assert!(identity.is_enabled());
 
     // cancel identity with the master group
-    let cancel = Cancel::new(false, &m_keypair, identity.prev().unwrap());
+    let cancel = Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap();
     identity.cancel(cancel).unwrap();
     assert!(!identity.is_enabled());
 
     // renew identity with the master group
     let id_keypair2: Keypair = Keypair::generate(&mut csprng);
-    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), false);
+    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), false).unwrap();
     identity.renew(renew).unwrap();
     assert!(!identity.is_enabled());
 
     // evolve identity to the new card (commited in the renew)
-    let card2 = Card::new(false, &id_keypair2, b"No info!", &vec![master.clone()]);
+    let card2 = Card::new(false, &id_keypair2, b"No info!", std::slice::from_ref(&master)).unwrap();
     identity.evolve(card2).unwrap();
     assert!(identity.is_enabled());
   }
@@ -480,11 +882,11 @@ mod tests {
 
     // renew performs an implicit cancel
     let id_keypair2: Keypair = Keypair::generate(&mut csprng);
-    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), true);
+    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), true).unwrap();
     identity.renew(renew).unwrap();
 
     // evolve identity to the new card (commited in the renew)
-    let card2 = Card::new(false, &id_keypair2, b"No info!", &vec![master.clone()]);
+    let card2 = Card::new(false, &id_keypair2, b"No info!", std::slice::from_ref(&master)).unwrap();
     identity.evolve(card2).unwrap();
     assert!(identity.is_enabled());
   }
@@ -495,13 +897,13 @@ mod tests {
     let (mut identity, _, m_keypair, _) = create();
 
     // close identity permanently
-    let cancel = Cancel::new(true, &m_keypair, identity.prev().unwrap());
+    let cancel = Cancel::new(true, &m_keypair, identity.prev().unwrap()).unwrap();
     identity.cancel(cancel).unwrap();
 
     // renew must fail
     let id_keypair2: Keypair = Keypair::generate(&mut csprng);
-    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), false);
-    assert!(identity.renew(renew) == Err("Identity closed permanently!".into()));
+    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), false).unwrap();
+    assert!(identity.renew(renew) == Err(Error::Closed));
   }
 
   #[test]
@@ -511,13 +913,13 @@ mod tests {
 
     // renew performs an implicit cancel
     let id_keypair2: Keypair = Keypair::generate(&mut csprng);
-    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), true);
+    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), true).unwrap();
     identity.renew(renew).unwrap();
 
     // fail when evolving the identity to a wrong card (different key from the one in renew/commit)
     let id_keypair3: Keypair = Keypair::generate(&mut csprng);
-    let card2 = Card::new(false, &id_keypair3, b"No info!", &vec![master.clone()]);
-    assert!(identity.evolve(card2) == Err("The card key is not valid!".into()));
+    let card2 = Card::new(false, &id_keypair3, b"No info!", std::slice::from_ref(&master)).unwrap();
+    assert!(identity.evolve(card2) == Err(Error::InvalidCardKey));
   }
 
   #[test]
@@ -526,13 +928,13 @@ mod tests {
     let (mut identity, master, m_keypair, _) = create();
 
     // cancel identity with the master group
-    let cancel = Cancel::new(false, &m_keypair, identity.prev().unwrap());
+    let cancel = Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap();
     identity.cancel(cancel).unwrap();
 
     // fail when identity is disabled
     let id_keypair2: Keypair = Keypair::generate(&mut csprng);
-    let card2 = Card::new(false, &id_keypair2, b"No info!", &vec![master.clone()]);
-    assert!(identity.evolve(card2) == Err("A renew must exist to evolve!".into()));
+    let card2 = Card::new(false, &id_keypair2, b"No info!", std::slice::from_ref(&master)).unwrap();
+    assert!(identity.evolve(card2) == Err(Error::MissingRenew));
   }
 
   #[test]
@@ -540,16 +942,17 @@ mod tests {
     let mut csprng = OsRng{};
     let (mut identity, _, m_keypair, _) = create();
 
-    let previous_card = identity.prev().unwrap().clone();
+    let previous_card = identity.prev().unwrap();
 
     // cancel identity with the master group
-    let cancel = Cancel::new(false, &m_keypair, &previous_card);
+    let cancel = Cancel::new(false, &m_keypair, previous_card).unwrap();
+    let cancel_hash = cancel.hash();
     identity.cancel(cancel).unwrap();
 
     // fail when renewing with an invalid chain (pointing to the previous card instead of cancel)
     let id_keypair2: Keypair = Keypair::generate(&mut csprng);
-    let renew = Renew::new(&m_keypair, &id_keypair2.public, &previous_card, false);
-    assert!(identity.renew(renew) == Err("Invalid chain!".into()));
+    let renew = Renew::new(&m_keypair, &id_keypair2.public, previous_card, false).unwrap();
+    assert!(identity.renew(renew) == Err(Error::InvalidChain { expected: cancel_hash, found: previous_card }));
   }
 
   #[test]
@@ -557,10 +960,10 @@ mod tests {
     let (mut identity, _, m_keypair, _) = create();
 
     // cancel identity with the master group
-    let mut cancel1 = Cancel::new(true, &m_keypair, identity.prev().unwrap());
-    let cancel2 = Cancel::new(false, &m_keypair, identity.prev().unwrap());
+    let mut cancel1 = Cancel::new(true, &m_keypair, identity.prev().unwrap()).unwrap();
+    let cancel2 = Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap();
     cancel1.sig = cancel2.sig;
-    assert!(identity.cancel(cancel1) == Err("Invalid cancel!".into()));
+    assert!(identity.cancel(cancel1) == Err(Error::InvalidCancel));
   }
 
   #[test]
@@ -570,18 +973,232 @@ mod tests {
 
     // cancel identity with a non existing group
     let m_keypair: Keypair = Keypair::generate(&mut csprng);
-    let cancel = Cancel::new(false, &m_keypair, identity.prev().unwrap());
-    assert!(identity.cancel(cancel) == Err("No group found to evolve!".into()))
+    let cancel = Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap();
+    assert!(identity.cancel(cancel) == Err(Error::NoGroup))
+  }
+
+  #[test]
+  fn multisig_cancel_and_renew() {
+    let mut csprng = OsRng{};
+    let k1: Keypair = Keypair::generate(&mut csprng);
+    let k2: Keypair = Keypair::generate(&mut csprng);
+    let k3: Keypair = Keypair::generate(&mut csprng);
+
+    // create a 2-of-3 master group
+    let master = TLGroup::multi(TLType::MASTER, 2, &[k1.public, k2.public, k3.public]).unwrap();
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", std::slice::from_ref(&master)).unwrap();
+    let mut identity = Identity::new(genesis).unwrap();
+
+    // one signature is not enough
+    let mut cancel = Cancel::new(false, &k1, identity.prev().unwrap()).unwrap();
+    assert!(identity.cancel(cancel.clone()) == Err(Error::ThresholdNotReached));
+
+    // the same member can't sign twice
+    let mut dup = cancel.clone();
+    dup.cosign(&k1).unwrap();
+    assert!(identity.cancel(dup) == Err(Error::ThresholdNotReached));
+
+    cancel.cosign(&k2).unwrap();
+    identity.cancel(cancel).unwrap();
+
+    // renew with a different pair of members
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let mut renew = Renew::new(&k1, &id_keypair2.public, identity.prev().unwrap(), false).unwrap();
+    renew.cosign(&k3).unwrap();
+    identity.renew(renew).unwrap();
+
+    let card2 = Card::new(false, &id_keypair2, b"No info!", &[master]).unwrap();
+    identity.evolve(card2).unwrap();
+    assert!(identity.is_enabled());
+  }
+
+  #[test]
+  fn multisig_invalid_threshold() {
+    let mut csprng = OsRng{};
+    let k1: Keypair = Keypair::generate(&mut csprng);
+
+    assert!(TLGroup::multi(TLType::MASTER, 0, &[k1.public]).err() == Some(Error::InvalidThreshold));
+    assert!(TLGroup::multi(TLType::MASTER, 2, &[k1.public, k1.public]).err() == Some(Error::InvalidThreshold));
+  }
+
+  #[test]
+  fn tampered_group() {
+    let mut csprng = OsRng{};
+    let k1: Keypair = Keypair::generate(&mut csprng);
+    let k2: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::multi(TLType::MASTER, 2, &[k1.public, k2.public]).unwrap();
+
+    // lowering the threshold changes the group commit
+    let mut tampered = master.clone();
+    tampered.threshold = 1;
+    assert!(!tampered.is_valid());
+
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", &[tampered]).unwrap();
+    assert!(Identity::new(genesis).err() == Some(Error::InvalidGenesis));
+  }
+
+  #[test]
+  fn registry_reanchor() {
+    let mut csprng = OsRng{};
+    let (mut identity, master, m_keypair, id_keypair) = create();
+
+    let reg1 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", identity.prev().unwrap(), 0).unwrap();
+    identity.save(reg1.clone()).unwrap();
+
+    // a re-anchor can't start a chain or stay on the same key
+    let reanchor = Registry::reanchor(&id_keypair, &reg1, &identity.cards[0], 0).unwrap();
+    assert!(identity.save(reanchor) == Err(Error::InvalidReanchor { id: "idp.io/test".into() }));
+
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), true).unwrap();
+    identity.renew(renew).unwrap();
+    let card2 = Card::new(false, &id_keypair2, b"No info!", &[master]).unwrap();
+    identity.evolve(card2.clone()).unwrap();
+
+    // continuing the chain with the new key requires the handover
+    let reg2 = Registry::new(&id_keypair2, "idp.io/test", "test", OType::SET, b"More info!", reg1.hash(), 1).unwrap();
+    assert!(identity.save(reg2) == Err(Error::MissingReanchor { id: "idp.io/test".into() }));
+
+    // the re-anchor must reference the new card
+    let wrong = Registry::reanchor(&id_keypair2, &reg1, &identity.cards[0], 1).unwrap();
+    assert!(identity.save(wrong) == Err(Error::InvalidReanchor { id: "idp.io/test".into() }));
+
+    let reanchor = Registry::reanchor(&id_keypair2, &reg1, &card2, 1).unwrap();
+    identity.save(reanchor.clone()).unwrap();
+    let reg2 = Registry::new(&id_keypair2, "idp.io/test", "test", OType::SET, b"More info!", reanchor.hash(), 1).unwrap();
+    identity.save(reg2).unwrap();
+    identity.verify_full().unwrap();
+
+    // the re-anchor doesn't change the value
+    let state = identity.state_at("idp.io/test", 1).unwrap();
+    assert!(state.value().unwrap().info == b"Not important!".to_vec());
+
+    // received chain without the re-anchor
+    let mut tampered = identity.clone();
+    let chain = tampered.db.get_mut("idp.io/test").unwrap();
+    chain.remove(1);
+    chain[1] = Registry::new(&id_keypair2, "idp.io/test", "test", OType::SET, b"More info!", reg1.hash(), 1).unwrap();
+    assert!(tampered.verify_full() == Err(Error::MissingReanchor { id: "idp.io/test".into() }));
+  }
+
+  fn full_lifecycle() -> (Identity, Keypair, Keypair) {
+    let mut csprng = OsRng{};
+    let (mut identity, master, m_keypair, id_keypair) = create();
+
+    let reg1 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", identity.prev().unwrap(), 0).unwrap();
+    identity.save(reg1.clone()).unwrap();
+
+    let cancel = Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap();
+    identity.cancel(cancel).unwrap();
+
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), false).unwrap();
+    identity.renew(renew).unwrap();
+
+    let card2 = Card::new(false, &id_keypair2, b"No info!", &[master]).unwrap();
+    identity.evolve(card2.clone()).unwrap();
+
+    // move the existing chain to the new key
+    let reanchor = Registry::reanchor(&id_keypair2, &reg1, &card2, 1).unwrap();
+    identity.save(reanchor.clone()).unwrap();
+
+    let reg2 = Registry::new(&id_keypair2, "idp.io/test", "test", OType::SET, b"More info!", reanchor.hash(), 1).unwrap();
+    identity.save(reg2).unwrap();
+
+    let reg3 = Registry::new(&id_keypair2, "idp.io/other", "other", OType::SET, b"Other info!", identity.prev().unwrap(), 1).unwrap();
+    identity.save(reg3).unwrap();
+
+    (identity, m_keypair, id_keypair2)
+  }
+
+  #[test]
+  fn verify_full_received() {
+    let (identity, m_keypair, _) = full_lifecycle();
+
+    // identity received from a peer
+    let bytes = bincode::serialize(&identity).unwrap();
+    let mut received: Identity = bincode::deserialize(&bytes).unwrap();
+    received.verify_full().unwrap();
+
+    // an evolution in progress is also valid
+    let cancel = Cancel::new(false, &m_keypair, received.prev().unwrap()).unwrap();
+    received.cancel(cancel).unwrap();
+    received.verify_full().unwrap();
+  }
+
+  #[test]
+  fn verify_full_tampered() {
+    let (identity, _, _) = full_lifecycle();
+
+    let mut tampered = identity.clone();
+    tampered.enabled = false;
+    assert!(tampered.verify_full() == Err(Error::InvalidEnabled));
+
+    let mut tampered = identity.clone();
+    tampered.udi = "udi-random".into();
+    assert!(tampered.verify_full() == Err(Error::InvalidUdi));
+
+    let mut tampered = identity.clone();
+    tampered.evols[0].renew = None;
+    assert!(tampered.verify_full() == Err(Error::MissingRenew));
+
+    let mut tampered = identity.clone();
+    tampered.db.get_mut("idp.io/test").unwrap()[2].info = b"Tampered!".to_vec();
+    assert!(tampered.verify_full() == Err(Error::InvalidRegistry { id: "idp.io/test".into() }));
+
+    // registry signed by the old key after the evolution
+    let mut tampered = identity.clone();
+    tampered.db.get_mut("idp.io/other").unwrap()[0].key_index = 0;
+    assert!(tampered.verify_full() == Err(Error::InvalidRegistry { id: "idp.io/other".into() }));
+  }
+
+  #[test]
+  fn registry_state() {
+    let (mut identity, _, _ , id_keypair) = create();
+    assert!(matches!(identity.state("idp.io/test"), State::Empty));
+
+    let reg1 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", identity.prev().unwrap(), 0).unwrap();
+    identity.save(reg1.clone()).unwrap();
+    let reg2 = Registry::new(&id_keypair, "idp.io/test", "test", OType::DEL, b"", reg1.hash(), 0).unwrap();
+    identity.save(reg2.clone()).unwrap();
+    let other = Registry::new(&id_keypair, "idp.io/other", "test", OType::SET, b"Other info!", identity.prev().unwrap(), 0).unwrap();
+    identity.save(other).unwrap();
+
+    // deleted with the signed deletion record
+    let state = identity.state("idp.io/test");
+    assert!(state.is_deleted() && state.value().is_none());
+    match state {
+      State::Deleted { index, entry } => assert!(index == 1 && entry.sig == reg2.sig),
+      _ => panic!("registry must be deleted")
+    }
+
+    // history
+    let state = identity.state_at("idp.io/test", 0).unwrap();
+    assert!(state.value().unwrap().info == b"Not important!".to_vec());
+    assert!(identity.state_at("idp.io/test", 2).is_none());
+    assert!(identity.state_at("idp.io/none", 0).is_none());
+
+    let values = identity.values();
+    assert!(values.len() == 1);
+    assert!(values["idp.io/other"].info == b"Other info!".to_vec());
+
+    // set again after delete
+    let reg3 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"New info!", reg2.hash(), 0).unwrap();
+    identity.save(reg3).unwrap();
+    assert!(identity.state("idp.io/test").value().unwrap().info == b"New info!".to_vec());
+    assert!(identity.values().len() == 2);
   }
 
   #[test]
   fn insert_registry() {
     let (mut identity, _, _ , id_keypair) = create();
 
-    let reg1 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", identity.prev().unwrap(), 0);
+    let reg1 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", identity.prev().unwrap(), 0).unwrap();
     assert!(identity.save(reg1.clone()) == Ok(()));
 
-    let reg2 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"More info!", &reg1.sig, 0);
+    let reg2 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"More info!", reg1.hash(), 0).unwrap();
     assert!(identity.save(reg2) == Ok(()));
   }
 
@@ -589,18 +1206,64 @@ mod tests {
   fn insert_registry_invalid_chain() {
     let (mut identity, _, _ , id_keypair) = create();
 
-    let reg1 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", identity.prev().unwrap(), 0);
-    assert!(identity.save(reg1) == Ok(()));
+    let reg1 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", identity.prev().unwrap(), 0).unwrap();
+    assert!(identity.save(reg1.clone()) == Ok(()));
     
-    let reg2 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"More info!", identity.prev().unwrap(), 0);
-    assert!(identity.save(reg2) == Err("Invalid chain!".into()));
+    let reg2 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"More info!", identity.prev().unwrap(), 0).unwrap();
+    let found = reg2.prev;
+    assert!(identity.save(reg2) == Err(Error::InvalidRegistryChain { id: "idp.io/test".into(), expected: reg1.hash(), found }));
   }
 
   #[test]
   fn insert_registry_invalid_key_index() {
     let (mut identity, _, _ , id_keypair) = create();
 
-    let reg = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", identity.prev().unwrap(), 1);
-    assert!(identity.save(reg) == Err("Invalid key index!".into()));
+    let reg = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", identity.prev().unwrap(), 1).unwrap();
+    assert!(identity.save(reg) == Err(Error::InvalidKeyIndex { id: "idp.io/test".into(), expected: 0, found: 1 }));
+  }
+
+  #[test]
+  fn hash_links() {
+    let (mut identity, _, m_keypair, id_keypair) = create();
+    identity.append(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!").unwrap();
+    identity.append(&id_keypair, "idp.io/test", "test", OType::SET, b"More info!").unwrap();
+    let cancel = Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap();
+    identity.cancel(cancel).unwrap();
+    identity.verify_links().unwrap();
+
+    // the links don't depend on the signatures
+    let mut forged = identity.clone();
+    let chain = forged.db.get_mut("idp.io/test").unwrap();
+    chain[0].sig = chain[1].sig;
+    forged.verify_links().unwrap();
+    assert!(forged.verify_full() == Err(Error::InvalidRegistry { id: "idp.io/test".into() }));
+
+    // a changed entry breaks the link of the next one
+    let mut forged = identity.clone();
+    let chain = forged.db.get_mut("idp.io/test").unwrap();
+    chain[0].info = b"Other info!".to_vec();
+    let (expected, found) = (chain[0].hash(), chain[1].prev);
+    assert!(forged.verify_links() == Err(Error::InvalidRegistryChain { id: "idp.io/test".into(), expected, found }));
+
+    let mut forged = identity.clone();
+    let cancel = forged.evols[0].cancel.as_mut().unwrap();
+    cancel.prev = Hash::of(b"Other card!");
+    assert!(forged.verify_links() == Err(Error::InvalidChain { expected: identity.cards[0].hash(), found: Hash::of(b"Other card!") }));
+
+    // keys, key indexes and approvals are part of the hash, the signature isn't
+    let reg = identity.registry("idp.io/test").unwrap()[0].clone();
+    let mut changed = reg.clone();
+    changed.key_index = 1;
+    assert!(changed.hash() != reg.hash());
+    changed = reg.clone();
+    changed.sig = identity.registry("idp.io/test").unwrap()[1].sig;
+    assert!(changed.hash() == reg.hash());
+
+    let mut renew = Renew::new(&m_keypair, &id_keypair.public, identity.prev().unwrap(), true).unwrap();
+    let before = renew.hash();
+    renew.cosign(&id_keypair).unwrap();
+    assert!(renew.hash() != before);
+    renew.key = None;
+    assert!(renew.hash() != before);
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,255 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use base64::{encode_config, decode_config, URL_SAFE_NO_PAD};
+
+use crate::structs::{Result, Error};
+
+// compact form types
+pub const REGISTRY_COMPACT: &str = "raiap-registry";
+pub const STREAM_BLOCK_COMPACT: &str = "raiap-stream-block";
+
+fn b64url(data: &[u8]) -> String {
+  encode_config(data, URL_SAFE_NO_PAD)
+}
+
+fn from_b64url(data: &str, what: &'static str) -> Result<Vec<u8>> {
+  decode_config(data, URL_SAFE_NO_PAD).map_err(|_| Error::Deserialize { what })
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// JSON (keys, signatures and info are base64url strings without padding)
+//-----------------------------------------------------------------------------------------------------------
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> String {
+  // This unwrap() should never fail, or it's a serious code bug!
+  serde_json::to_string_pretty(value).unwrap()
+}
+
+pub fn from_str<T: DeserializeOwned>(json: &str, what: &'static str) -> Result<T> {
+  serde_json::from_str(json).map_err(|_| Error::Deserialize { what })
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Compact encoding: base64url(header).base64url(json without sig).base64url(sig)
+//   Not a JWS: the signature is the one of the structure (over the RAIAP signing payload), so the header has
+//   no "alg" and JOSE libraries reject it instead of checking the wrong signing input.
+//-----------------------------------------------------------------------------------------------------------
+pub fn to_compact<T: Serialize>(typ: &str, value: &T) -> String {
+  // This unwrap() should never fail, or it's a serious code bug!
+  let mut value = serde_json::to_value(value).unwrap();
+  let sig = value.as_object_mut().and_then(|obj| obj.remove("sig")).unwrap();
+
+  let header = json!({ "typ": typ });
+  let sig = from_b64url(sig.as_str().unwrap(), "signature").unwrap();
+
+  format!("{}.{}.{}", b64url(header.to_string().as_bytes()), b64url(value.to_string().as_bytes()), b64url(&sig))
+}
+
+pub fn from_compact<T: DeserializeOwned>(typ: &str, compact: &str, what: &'static str) -> Result<T> {
+  let parts: Vec<&str> = compact.split('.').collect();
+  if parts.len() != 3 {
+    return Err(Error::Deserialize { what })
+  }
+
+  let header: Value = serde_json::from_slice(&from_b64url(parts[0], what)?).map_err(|_| Error::Deserialize { what })?;
+  if header != json!({ "typ": typ }) {
+    return Err(Error::Deserialize { what })
+  }
+
+  let mut value: Value = serde_json::from_slice(&from_b64url(parts[1], what)?).map_err(|_| Error::Deserialize { what })?;
+  let sig = from_b64url(parts[2], what)?;
+  match value.as_object_mut() {
+    Some(obj) if !obj.contains_key("sig") => obj.insert("sig".into(), Value::String(b64url(&sig))),
+    _ => return Err(Error::Deserialize { what })
+  };
+
+  serde_json::from_value(value).map_err(|_| Error::Deserialize { what })
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Serde helpers (base64url for human-readable formats, byte strings for bincode and CBOR)
+//-----------------------------------------------------------------------------------------------------------
+pub mod b64 {
+  use std::fmt;
+  use std::marker::PhantomData;
+
+  use serde::{Serializer, Deserializer};
+  use serde::de::{self, Visitor};
+  use ed25519_dalek::{PublicKey, Signature};
+
+  use super::{b64url, from_b64url};
+  use crate::structs::hash::Hash;
+
+  pub trait Raw: Sized {
+    fn raw(&self) -> Vec<u8>;
+    fn from_raw(data: &[u8]) -> Option<Self>;
+  }
+
+  impl Raw for Vec<u8> {
+    fn raw(&self) -> Vec<u8> { self.clone() }
+    fn from_raw(data: &[u8]) -> Option<Self> { Some(data.to_vec()) }
+  }
+
+  impl Raw for PublicKey {
+    fn raw(&self) -> Vec<u8> { self.as_bytes().to_vec() }
+    fn from_raw(data: &[u8]) -> Option<Self> { PublicKey::from_bytes(data).ok() }
+  }
+
+  impl Raw for Signature {
+    fn raw(&self) -> Vec<u8> { self.to_bytes().to_vec() }
+    fn from_raw(data: &[u8]) -> Option<Self> { Signature::from_bytes(data).ok() }
+  }
+
+  impl Raw for Hash {
+    fn raw(&self) -> Vec<u8> { self.as_bytes().to_vec() }
+    fn from_raw(data: &[u8]) -> Option<Self> { Hash::from_bytes(data) }
+  }
+
+  pub fn serialize<T: Raw, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+    match serializer.is_human_readable() {
+      true => serializer.serialize_str(&b64url(&value.raw())),
+      false => serializer.serialize_bytes(&value.raw())
+    }
+  }
+
+  pub fn deserialize<'de, T: Raw, D: Deserializer<'de>>(deserializer: D) -> Result<T, D::Error> {
+    match deserializer.is_human_readable() {
+      true => deserializer.deserialize_str(RawVisitor(PhantomData)),
+      false => deserializer.deserialize_bytes(RawVisitor(PhantomData))
+    }
+  }
+
+  struct RawVisitor<T>(PhantomData<T>);
+
+  impl<'de, T: Raw> Visitor<'de> for RawVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+      write!(f, "base64url string or byte string")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+      let data = from_b64url(value, "base64url").map_err(E::custom)?;
+      self.visit_bytes(&data)
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<T, E> {
+      T::from_raw(value).ok_or_else(|| E::invalid_length(value.len(), &self))
+    }
+  }
+
+  pub mod option {
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+    use super::Raw;
+
+    #[derive(Serialize, Deserialize)]
+    struct Wrap(#[serde(with = "super")] Vec<u8>);
+
+    pub fn serialize<T: Raw, S: Serializer>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error> {
+      value.as_ref().map(|v| Wrap(v.raw())).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, T: Raw, D: Deserializer<'de>>(deserializer: D) -> Result<Option<T>, D::Error> {
+      let value: Option<Wrap> = Option::deserialize(deserializer)?;
+      match value {
+        None => Ok(None),
+        Some(Wrap(data)) => T::from_raw(&data).map(Some).ok_or_else(|| serde::de::Error::custom("invalid length"))
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::OType;
+  use crate::structs::identity::*;
+  use crate::structs::stream::*;
+
+  use rand::rngs::OsRng;
+  use ed25519_dalek::Keypair;
+
+  #[test]
+  fn identity_json() {
+    let mut csprng = OsRng{};
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", &[master]).unwrap();
+    let mut identity = Identity::new(genesis).unwrap();
+
+    let reg = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", identity.prev().unwrap(), 0).unwrap();
+    identity.save(reg).unwrap();
+
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), true).unwrap();
+    identity.renew(renew).unwrap();
+
+    // readable keys and signatures
+    let json = to_string(&identity);
+    let card = &identity.cards[0];
+    assert!(json.contains(&format!("\"sig\": \"{}\"", b64url(&card.sig.to_bytes()))));
+    assert!(json.contains(&format!("\"info\": \"{}\"", b64url(b"No important info!"))));
+
+    let loaded: Identity = from_str(&json, "identity").unwrap();
+    loaded.verify_full().unwrap();
+    assert!(to_string(&loaded) == json);
+
+    let card: Card = from_str(&to_string(&identity.cards[0]), "card").unwrap();
+    assert!(card.verify());
+
+    assert!(from_str::<Card>("{}", "card").err() == Some(Error::Deserialize { what: "card" }));
+  }
+
+  #[test]
+  fn stream_json_and_compact() {
+    let mut csprng = OsRng{};
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let mut stream = Stream::new(&profile_keypair, "udi-random", "r-random", &[], genesis, None).unwrap();
+    let record = Record { oper: OType::DEL, info: b"New info!".to_vec() };
+    stream.save(StreamBlock::new(&profile_keypair, record, stream.hash()).unwrap()).unwrap();
+
+    let loaded: Stream = from_str(&to_string(&stream), "stream").unwrap();
+    loaded.verify_stream(&profile_keypair.public).unwrap();
+
+    // compact block
+    let compact = stream.blocks[0].to_compact();
+    assert!(compact.split('.').count() == 3);
+    let block = StreamBlock::from_compact(&compact).unwrap();
+    assert!(block.verify(&profile_keypair.public));
+    assert!(block.sig == stream.blocks[0].sig);
+
+    // wrong type or tampered signature
+    assert!(Registry::from_compact(&compact).is_err());
+    let mut tampered = compact[..compact.rfind('.').unwrap()].to_string();
+    tampered.push_str(".AAAA");
+    assert!(StreamBlock::from_compact(&tampered).is_err());
+  }
+
+  #[test]
+  fn registry_compact() {
+    let mut csprng = OsRng{};
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", &[master]).unwrap();
+    let identity = Identity::new(genesis).unwrap();
+
+    let reg = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", identity.prev().unwrap(), 0).unwrap();
+    let compact = reg.to_compact();
+
+    let header: Value = serde_json::from_slice(&from_b64url(compact.split('.').next().unwrap(), "header").unwrap()).unwrap();
+    assert!(header == json!({ "typ": REGISTRY_COMPACT }));
+
+    let loaded = Registry::from_compact(&compact).unwrap();
+    assert!(loaded.verify(&id_keypair.public));
+    assert!(loaded.to_compact() == compact);
+
+    // a header that claims a JWS signature is rejected
+    let jws_header = b64url(json!({ "alg": "EdDSA", "typ": REGISTRY_COMPACT }).to_string().as_bytes());
+    let claimed = format!("{}{}", jws_header, &compact[compact.find('.').unwrap()..]);
+    assert!(Registry::from_compact(&claimed).is_err());
+  }
+}
@@ -0,0 +1,608 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Serialize, Deserialize};
+use ed25519_dalek::{PublicKey, Signature};
+
+use sha2::{Sha256, Digest};
+use base64::encode;
+
+use crate::structs::{Result, Error, OType};
+use crate::structs::identity::{commit, TLType};
+use crate::structs::stream::{asi, Record};
+use crate::structs::payload::{self, Payload, Format, Migration};
+
+//-----------------------------------------------------------------------------------------------------------
+// Legacy structures (releases linked by signatures, before the content hashes)
+//   The first release signed the untagged bincode fields (Format::Legacy) with single key groups, V1 signed the
+//   tagged bincode fields with M-of-N groups and approvals. The structures have the V1 layout, first release
+//   objects are decoded with from_first_release. Each history (evolutions, registry or stream) may start with
+//   first release objects up to its first V1 object (the migration point).
+//-----------------------------------------------------------------------------------------------------------
+fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8], what: &'static str) -> Result<T> {
+  bincode::deserialize(bytes).map_err(|_| Error::Deserialize { what })
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Group {
+  pub typ: TLType,
+  pub threshold: usize,
+  pub members: BTreeSet<String>,  // key commits
+  pub commit: String
+}
+
+impl Group {
+  // first release groups are a single key, committed with the key commit
+  fn is_valid(&self, format: Format) -> bool {
+    match format {
+      Format::Legacy => self.threshold == 1 && self.members.len() == 1 && self.members.contains(&self.commit),
+      _ => self.threshold != 0 && self.threshold <= self.members.len() && self.commit == self.group_commit()
+    }
+  }
+
+  fn check(&self, keys: &[PublicKey]) -> bool {
+    let signers: BTreeSet<String> = keys.iter().map(commit).collect();
+    signers.len() == keys.len() && signers.len() >= self.threshold && signers.is_subset(&self.members)
+  }
+
+  fn group_commit(&self) -> String {
+    let mut hasher = Sha256::new();
+
+    // These unwrap() should never fail, or it's a serious code bug!
+    hasher.input(bincode::serialize(&self.typ).unwrap());
+    hasher.input(bincode::serialize(&self.threshold).unwrap());
+    hasher.input(bincode::serialize(&self.members).unwrap());
+    let result = hasher.result();
+
+    encode(&result)
+  }
+}
+
+// the group authorised by the signers (master groups have precedence)
+fn authorize<'a>(groups: &'a BTreeMap<String, Group>, keys: &[PublicKey]) -> Result<&'a Group> {
+  let mut found: Option<&Group> = None;
+  for gr in groups.values() {
+    if gr.check(keys) && (found.is_none() || gr.typ == TLType::MASTER) {
+      found = Some(gr);
+    }
+  }
+
+  found.ok_or(Error::NoGroup)
+}
+
+// groups as signed by the first release (type and key commit)
+fn first_groups(groups: &BTreeMap<String, Group>) -> BTreeMap<&String, (&TLType, &String)> {
+  groups.iter().map(|(key, gr)| (key, (&gr.typ, &gr.commit))).collect()
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Identity
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Identity {
+  pub udi: String,
+  pub cards: Vec<Card>,
+  pub evols: Vec<Evolve>,
+
+  pub db: HashMap<String, Vec<Registry>>,
+  pub enabled: bool
+}
+
+impl Identity {
+  pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+    decode(bytes, "legacy identity")
+  }
+
+  pub fn from_first_release(bytes: &[u8]) -> Result<Self> {
+    decode::<first::Identity>(bytes, "legacy identity").map(Self::from)
+  }
+
+  // replay the evolutions and registries with the rules of the legacy releases
+  pub fn verify(&self) -> Result<()> {
+    let genesis = self.cards.first().ok_or(Error::InvalidGenesis)?;
+    let mut history = Migration::legacy();
+    if !genesis.is_genesis || history.verify(|format| genesis.verify_as(format)).is_none() {
+      return Err(Error::InvalidGenesis)
+    }
+
+    if commit(&genesis.key) != self.udi {
+      return Err(Error::InvalidUdi)
+    }
+
+    // each evolve is followed by the evolved card, except the last one that may be in progress
+    if self.cards.len() != self.evols.len() + 1 && self.cards.len() != self.evols.len() {
+      return Err(Error::InvalidEvolutions)
+    }
+
+    if self.enabled != (self.cards.len() > self.evols.len()) {
+      return Err(Error::InvalidEnabled)
+    }
+
+    for (i, evol) in self.evols.iter().enumerate() {
+      let card = &self.cards[i];
+      let mut prev = &card.sig;
+
+      if let Some(cancel) = evol.cancel.as_ref() {
+        if cancel.prev != *prev {
+          return Err(Error::InvalidLegacyChain)
+        }
+
+        if history.verify(|format| cancel.verify_as(format)).is_none() {
+          return Err(Error::InvalidCancel)
+        }
+
+        let gr = authorize(&card.groups, &cancel.signers())?;
+        if cancel.is_close && gr.typ != TLType::MASTER {
+          return Err(Error::OnlyMasterClose)
+        }
+
+        prev = &cancel.sig;
+      }
+
+      if let Some(renew) = evol.renew.as_ref() {
+        let key = match evol.cancel.as_ref() {
+          Some(cancel) if cancel.is_close => return Err(Error::Closed),
+          Some(cancel) => cancel.key,
+          None => renew.key.ok_or(Error::MissingRenewKey)?
+        };
+
+        if renew.prev != *prev {
+          return Err(Error::InvalidLegacyChain)
+        }
+
+        if history.verify(|format| renew.verify_as(&key, format)).is_none() {
+          return Err(Error::InvalidRenew)
+        }
+
+        authorize(&card.groups, &renew.signers(&key))?;
+      }
+
+      if let Some(next) = self.cards.get(i + 1) {
+        let renew = evol.renew.as_ref().ok_or(Error::MissingRenew)?;
+        if next.is_genesis || renew.commit != commit(&next.key) {
+          return Err(Error::InvalidCardKey)
+        }
+
+        if history.verify(|format| next.verify_as(format)).is_none() {
+          return Err(Error::InvalidCard)
+        }
+      }
+    }
+
+    // sorted to report the same first violation on every run
+    let mut ids: Vec<&String> = self.db.keys().collect();
+    ids.sort();
+    for id in ids {
+      self.verify_registry(id, &self.db[id])?;
+    }
+
+    Ok(())
+  }
+
+  fn verify_registry(&self, id: &str, chain: &[Registry]) -> Result<()> {
+    let mut migration = Migration::legacy();
+    let mut last: Option<&Registry> = None;
+    for reg in chain.iter() {
+      // key index can only move forward to existing cards
+      let min_index = last.map(|prev| prev.key_index).unwrap_or(0);
+      if reg.id != id || reg.key_index >= self.cards.len() || reg.key_index < min_index {
+        return Err(Error::InvalidKeyIndex { id: id.into(), expected: min_index, found: reg.key_index })
+      }
+
+      let card = &self.cards[reg.key_index];
+      if migration.verify(|format| reg.verify_as(&card.key, format)).is_none() {
+        return Err(Error::InvalidRegistry { id: id.into() })
+      }
+
+      let prev = last.map(|prev| &prev.sig).unwrap_or(&card.sig);
+      if reg.prev != *prev {
+        return Err(Error::InvalidLegacyChain)
+      }
+
+      if let Some(prev) = last.filter(|prev| prev.typ != reg.typ) {
+        return Err(Error::InvalidRegistryType { id: id.into(), expected: prev.typ.clone(), found: reg.typ.clone() })
+      }
+
+      last = Some(reg);
+    }
+
+    match last {
+      None => Err(Error::InvalidRegistry { id: id.into() }),
+      Some(_) => Ok(())
+    }
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Card, Evolve & Registry
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Card {
+  pub is_genesis: bool,
+  pub info: Vec<u8>,
+  pub groups: BTreeMap<String, Group>,
+  pub sig: Signature,
+  pub key: PublicKey
+}
+
+impl Card {
+  fn verify_as(&self, format: Format) -> bool {
+    if !self.groups.iter().all(|(key, gr)| *key == gr.commit && gr.is_valid(format)) {
+      return false
+    }
+
+    let sig_data = match format {
+      Format::Legacy => Payload::new(payload::CARD, format).field(&self.is_genesis).bytes(&self.info).field(&first_groups(&self.groups)),
+      _ => Payload::new(payload::CARD, format).field(&self.is_genesis).bytes(&self.info).field(&self.groups)
+    };
+
+    self.key.verify(&sig_data.into_bytes(), &self.sig).is_ok()
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Evolve {
+  pub cancel: Option<Cancel>,
+  pub renew: Option<Renew>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Approval {
+  pub key: PublicKey,
+  pub sig: Signature
+}
+
+impl Approval {
+  fn verify(approvals: &[Approval], sig_data: &[u8]) -> bool {
+    approvals.iter().all(|ap| ap.key.verify(sig_data, &ap.sig).is_ok())
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cancel {
+  pub is_close: bool,
+  pub prev: Signature,
+  pub sig: Signature,
+  pub approvals: Vec<Approval>,
+  pub key: PublicKey
+}
+
+impl Cancel {
+  fn signers(&self) -> Vec<PublicKey> {
+    let mut keys = vec![self.key];
+    keys.extend(self.approvals.iter().map(|ap| ap.key));
+    keys
+  }
+
+  fn verify_as(&self, format: Format) -> bool {
+    let sig_data = Payload::new(payload::CANCEL, format).field(&self.is_close).field(&self.prev).into_bytes();
+    self.key.verify(&sig_data, &self.sig).is_ok() && Approval::verify(&self.approvals, &sig_data)
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Renew {
+  pub commit: String,
+  pub prev: Signature,
+  pub sig: Signature,
+  pub key: Option<PublicKey>,
+  pub approvals: Vec<Approval>
+}
+
+impl Renew {
+  fn signers(&self, key: &PublicKey) -> Vec<PublicKey> {
+    let mut keys = vec![*key];
+    keys.extend(self.approvals.iter().map(|ap| ap.key));
+    keys
+  }
+
+  fn verify_as(&self, key: &PublicKey, format: Format) -> bool {
+    let sig_data = Payload::new(payload::RENEW, format).field(&self.commit).field(&self.prev).into_bytes();
+    key.verify(&sig_data, &self.sig).is_ok() && Approval::verify(&self.approvals, &sig_data)
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Registry {
+  pub id: String,
+  pub typ: String,
+  pub oper: OType,
+
+  pub info: Vec<u8>,
+  pub prev: Signature,
+  pub sig: Signature,
+  pub key_index: usize
+}
+
+impl Registry {
+  fn verify_as(&self, key: &PublicKey, format: Format) -> bool {
+    let sig_data = Payload::new(payload::REGISTRY, format)
+      .field(&self.id)
+      .field(&self.typ)
+      .field(&self.oper)
+      .bytes(&self.info)
+      .field(&self.prev)
+      .into_bytes();
+
+    key.verify(&sig_data, &self.sig).is_ok()
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Stream & StreamBlock
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ExtRenew {
+  pub renew: Renew,
+  pub key: PublicKey
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Stream {
+  pub asi: String,
+  pub groups: BTreeMap<String, Group>,
+  pub genesis: Record,
+  pub renew: Option<ExtRenew>,
+  pub sig: Signature,
+
+  pub blocks: Vec<StreamBlock>
+}
+
+impl Stream {
+  pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+    decode(bytes, "legacy stream")
+  }
+
+  pub fn from_first_release(bytes: &[u8]) -> Result<Self> {
+    decode::<first::Stream>(bytes, "legacy stream").map(Self::from)
+  }
+
+  // proof of ownership with the ASI signature (the first release signed it as the anchor AL)
+  pub fn check_asi(&self, udi: &str, r: &str, key: &PublicKey, sig: &Signature) -> bool {
+    let sig_data = |format| Payload::new(payload::STREAM_ASI, format).field(udi).field(r).into_bytes();
+    asi(key, sig) == self.asi && Migration::legacy().verify(|format| key.verify(&sig_data(format), sig).is_ok()).is_some()
+  }
+
+  // the genesis and blocks, linked by the signatures
+  pub fn verify_stream(&self, key: &PublicKey) -> Result<()> {
+    let mut migration = Migration::legacy();
+    if migration.verify(|format| self.verify_as(key, format)).is_none() {
+      return Err(Error::InvalidStreamGenesis)
+    }
+
+    let mut prev = &self.sig;
+    for (index, bl) in self.blocks.iter().enumerate() {
+      if bl.prev != *prev || migration.verify(|format| bl.verify_as(key, format)).is_none() {
+        return Err(Error::InvalidBlock { index })
+      }
+
+      prev = &bl.sig;
+    }
+
+    Ok(())
+  }
+
+  fn verify_as(&self, key: &PublicKey, format: Format) -> bool {
+    if !self.groups.iter().all(|(key, gr)| *key == gr.commit && gr.is_valid(format)) {
+      return false
+    }
+
+    let payload = Payload::new(payload::STREAM, format).field(&self.asi);
+    let sig_data = match format {
+      Format::Legacy => {
+        let renew = self.renew.as_ref().map(|ext| ((&ext.renew.commit, &ext.renew.prev, &ext.renew.sig, &ext.renew.key), &ext.key));
+        payload.field(&first_groups(&self.groups)).field(&self.genesis).field(&renew)
+      },
+      _ => payload.field(&self.groups).field(&self.genesis).field(&self.renew)
+    };
+
+    key.verify(&sig_data.into_bytes(), &self.sig).is_ok()
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamBlock {
+  pub record: Record,
+  pub prev: Signature,
+  pub sig: Signature
+}
+
+impl StreamBlock {
+  fn verify_as(&self, key: &PublicKey, format: Format) -> bool {
+    let sig_data = Payload::new(payload::STREAM_BLOCK, format).field(&self.record).field(&self.prev).into_bytes();
+    key.verify(&sig_data, &self.sig).is_ok()
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// first release layout (single key groups, no approvals)
+//-----------------------------------------------------------------------------------------------------------
+mod first {
+  use super::*;
+
+  #[derive(Deserialize)]
+  pub struct Group {
+    typ: TLType,
+    commit: String
+  }
+
+  #[derive(Deserialize)]
+  pub struct Card {
+    is_genesis: bool,
+    info: Vec<u8>,
+    groups: BTreeMap<String, Group>,
+    sig: Signature,
+    key: PublicKey
+  }
+
+  #[derive(Deserialize)]
+  pub struct Cancel {
+    is_close: bool,
+    prev: Signature,
+    sig: Signature,
+    key: PublicKey
+  }
+
+  #[derive(Deserialize)]
+  pub struct Renew {
+    commit: String,
+    prev: Signature,
+    sig: Signature,
+    key: Option<PublicKey>
+  }
+
+  #[derive(Deserialize)]
+  pub struct Evolve {
+    cancel: Option<Cancel>,
+    renew: Option<Renew>
+  }
+
+  #[derive(Deserialize)]
+  pub struct Identity {
+    udi: String,
+    cards: Vec<Card>,
+    evols: Vec<Evolve>,
+    db: HashMap<String, Vec<super::Registry>>,
+    enabled: bool
+  }
+
+  #[derive(Deserialize)]
+  pub struct ExtRenew {
+    renew: Renew,
+    key: PublicKey
+  }
+
+  #[derive(Deserialize)]
+  pub struct Stream {
+    asi: String,
+    groups: BTreeMap<String, Group>,
+    genesis: Record,
+    renew: Option<ExtRenew>,
+    sig: Signature,
+    blocks: Vec<super::StreamBlock>
+  }
+
+  fn groups(groups: BTreeMap<String, Group>) -> BTreeMap<String, super::Group> {
+    groups.into_iter().map(|(key, gr)| {
+      let members = vec![gr.commit.clone()].into_iter().collect();
+      (key, super::Group { typ: gr.typ, threshold: 1, members, commit: gr.commit })
+    }).collect()
+  }
+
+  fn renew(ev: Renew) -> super::Renew {
+    super::Renew { commit: ev.commit, prev: ev.prev, sig: ev.sig, key: ev.key, approvals: Vec::new() }
+  }
+
+  impl From<Card> for super::Card {
+    fn from(card: Card) -> Self {
+      Self { is_genesis: card.is_genesis, info: card.info, groups: groups(card.groups), sig: card.sig, key: card.key }
+    }
+  }
+
+  impl From<Identity> for super::Identity {
+    fn from(identity: Identity) -> Self {
+      let evols = identity.evols.into_iter().map(|ev| super::Evolve {
+        cancel: ev.cancel.map(|ev| super::Cancel { is_close: ev.is_close, prev: ev.prev, sig: ev.sig, approvals: Vec::new(), key: ev.key }),
+        renew: ev.renew.map(renew)
+      }).collect();
+
+      let cards = identity.cards.into_iter().map(super::Card::from).collect();
+      Self { udi: identity.udi, cards, evols, db: identity.db, enabled: identity.enabled }
+    }
+  }
+
+  impl From<Stream> for super::Stream {
+    fn from(st: Stream) -> Self {
+      let renew = st.renew.map(|ext| super::ExtRenew { renew: renew(ext.renew), key: ext.key });
+      Self { asi: st.asi, groups: groups(st.groups), genesis: st.genesis, renew, sig: st.sig, blocks: st.blocks }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use ed25519_dalek::{Keypair, SecretKey};
+
+  const FIXTURES: &str = include_str!("../../vectors/legacy.txt");
+
+  fn fixture(name: &str) -> Vec<u8> {
+    let line = FIXTURES.lines().find(|line| line.starts_with(&format!("{}: ", name))).unwrap();
+    let hex = &line[name.len() + 2..];
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+  }
+
+  fn keypair(seed: u8) -> Keypair {
+    let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+  }
+
+  #[test]
+  fn first_release_fixture() {
+    let identity = Identity::from_first_release(&fixture("first-identity")).unwrap();
+    assert!(identity.cards.len() == 2 && identity.enabled);
+    assert!(identity.db["idp.io/other"].len() == 2);
+    identity.verify().unwrap();
+
+    let profile = keypair(4);
+    let stream = Stream::from_first_release(&fixture("first-stream")).unwrap();
+    assert!(stream.blocks.len() == 2);
+    stream.verify_stream(&profile.public).unwrap();
+
+    let sig = Signature::from_bytes(&fixture("first-asi-sig")).unwrap();
+    assert!(stream.check_asi(&identity.udi, "r-random", &profile.public, &sig));
+    assert!(!stream.check_asi(&identity.udi, "other", &profile.public, &sig));
+
+    // the first release layout doesn't decode as V1
+    assert!(Identity::from_bytes(&fixture("first-identity")).is_err());
+  }
+
+  #[test]
+  fn v1_fixture() {
+    let identity = Identity::from_bytes(&fixture("v1-identity")).unwrap();
+    assert!(identity.evols[0].cancel.as_ref().unwrap().approvals.len() == 1);
+    identity.verify().unwrap();
+
+    let profile = keypair(4);
+    let stream = Stream::from_bytes(&fixture("v1-stream")).unwrap();
+    stream.verify_stream(&profile.public).unwrap();
+
+    let sig = Signature::from_bytes(&fixture("v1-asi-sig")).unwrap();
+    assert!(stream.check_asi(&identity.udi, "r-random", &profile.public, &sig));
+  }
+
+  #[test]
+  fn migration_point() {
+    let profile = keypair(4);
+
+    // a V1 block after the first release blocks
+    let mut stream = Stream::from_first_release(&fixture("first-stream")).unwrap();
+    let record = Record { oper: OType::SET, info: b"V1 info!".to_vec() };
+    let prev = stream.blocks[1].sig;
+    let sig_data = Payload::new(payload::STREAM_BLOCK, Format::V1).field(&record).field(&prev).into_bytes();
+    stream.blocks.push(StreamBlock { record, prev, sig: profile.sign(&sig_data) });
+    stream.verify_stream(&profile.public).unwrap();
+
+    // first release objects after the migration point are rejected
+    let record = Record { oper: OType::SET, info: b"Old info!".to_vec() };
+    let prev = stream.blocks[2].sig;
+    let sig_data = Payload::new(payload::STREAM_BLOCK, Format::Legacy).field(&record).field(&prev).into_bytes();
+    stream.blocks.push(StreamBlock { record, prev, sig: profile.sign(&sig_data) });
+    assert!(stream.verify_stream(&profile.public).err() == Some(Error::InvalidBlock { index: 3 }));
+
+    // tampered objects and links
+    let mut identity = Identity::from_first_release(&fixture("first-identity")).unwrap();
+    identity.db.get_mut("idp.io/other").unwrap()[1].info = b"Tampered!".to_vec();
+    assert!(identity.verify().err() == Some(Error::InvalidRegistry { id: "idp.io/other".into() }));
+
+    let mut identity = Identity::from_bytes(&fixture("v1-identity")).unwrap();
+    let renew = identity.evols[0].renew.as_mut().unwrap();
+    renew.approvals[0].sig = renew.sig;
+    assert!(identity.verify().err() == Some(Error::InvalidRenew));
+
+    let mut identity = Identity::from_bytes(&fixture("v1-identity")).unwrap();
+    identity.evols[0].cancel.as_mut().unwrap().prev = identity.evols[0].cancel.as_ref().unwrap().sig;
+    assert!(identity.verify().err() == Some(Error::InvalidLegacyChain));
+  }
+}
@@ -0,0 +1,298 @@
+use serde::{Serialize, Deserialize};
+
+use crate::structs::{Result, Error};
+use crate::structs::identity::*;
+
+//-----------------------------------------------------------------------------------------------------------
+// Event & LogEntry
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Event {
+  Genesis(Card),
+  Cancel(Cancel),
+  Renew(Renew),
+  Evolve(Card),
+  Registry(Registry)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogEntry {
+  pub seq: usize,
+  pub event: Event
+}
+
+impl LogEntry {
+  pub fn to_bytes(&self) -> Vec<u8> {
+    // This unwrap() should never fail, or it's a serious code bug!
+    bincode::serialize(self).unwrap()
+  }
+}
+
+// apply a non genesis event with the identity validation rules
+fn apply(identity: &mut Identity, event: &Event) -> Result<()> {
+  match event {
+    Event::Genesis(_) => Err(Error::UnexpectedGenesis),
+    Event::Cancel(ev) => identity.cancel(ev.clone()),
+    Event::Renew(ev) => identity.renew(ev.clone()),
+    Event::Evolve(card) => identity.evolve(card.clone()),
+    Event::Registry(reg) => identity.save(reg.clone())
+  }
+}
+
+// evolution history of the identity as events (genesis, cancels, renews and evolved cards, no registries)
+pub fn events(identity: &Identity) -> Vec<Event> {
+  let mut events = vec![Event::Genesis(identity.cards[0].clone())];
+  for (i, evol) in identity.evols.iter().enumerate() {
+    events.extend(evol.cancel.clone().map(Event::Cancel));
+    events.extend(evol.renew.clone().map(Event::Renew));
+    events.extend(identity.cards.get(i + 1).cloned().map(Event::Evolve));
+  }
+
+  events
+}
+
+// identity from the events of its evolution history, without verifying them
+pub fn restore(events: &[Event]) -> Result<Identity> {
+  let mut cards = Vec::<Card>::new();
+  let mut evols = Vec::<Evolve>::new();
+  for event in events.iter() {
+    // a renew completes the cancel of an evolution in progress
+    let in_progress = !cards.is_empty() && evols.len() == cards.len();
+    match event {
+      Event::Genesis(card) if cards.is_empty() => cards.push(card.clone()),
+      Event::Genesis(_) | Event::Registry(_) => return Err(Error::InvalidEvolutions),
+      _ if cards.is_empty() => return Err(Error::InvalidGenesis),
+      Event::Cancel(ev) => evols.push(Evolve { cancel: Some(ev.clone()), renew: None }),
+      Event::Renew(ev) => match evols.last_mut() {
+        Some(evol) if in_progress && evol.renew.is_none() => evol.renew = Some(ev.clone()),
+        _ => evols.push(Evolve { cancel: None, renew: Some(ev.clone()) })
+      },
+      Event::Evolve(card) => cards.push(card.clone())
+    }
+  }
+
+  Identity::from_history(cards, evols)
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// IdentityBuilder (rebuilds an identity by replaying events)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Default)]
+pub struct IdentityBuilder {
+  identity: Option<Identity>,
+  next: usize
+}
+
+impl IdentityBuilder {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn apply(&mut self, entry: &LogEntry) -> Result<()> {
+    if entry.seq != self.next {
+      return Err(Error::InvalidSequence { expected: self.next, found: entry.seq })
+    }
+
+    match (self.identity.as_mut(), &entry.event) {
+      (None, Event::Genesis(card)) => self.identity = Some(Identity::new(card.clone())?),
+      (None, _) => return Err(Error::InvalidGenesis),
+      (Some(identity), event) => apply(identity, event)?
+    }
+
+    self.next += 1;
+    Ok(())
+  }
+
+  pub fn build(self) -> Result<Identity> {
+    self.identity.ok_or(Error::InvalidGenesis)
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// EventLog (append-only log of an identity)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone)]
+pub struct EventLog {
+  identity: Identity,
+  entries: Vec<LogEntry>
+}
+
+impl EventLog {
+  pub fn new(genesis: Card) -> Result<Self> {
+    let identity = Identity::new(genesis.clone())?;
+    Ok(Self { identity, entries: vec![LogEntry { seq: 0, event: Event::Genesis(genesis) }] })
+  }
+
+  pub fn replay(entries: &[LogEntry]) -> Result<Self> {
+    let mut builder = IdentityBuilder::new();
+    for entry in entries.iter() {
+      builder.apply(entry)?;
+    }
+
+    Ok(Self { identity: builder.build()?, entries: entries.to_vec() })
+  }
+
+  pub fn identity(&self) -> &Identity {
+    &self.identity
+  }
+
+  pub fn entries(&self) -> &[LogEntry] {
+    &self.entries
+  }
+
+  pub fn head(&self) -> usize {
+    // must always have the genesis
+    self.entries.len() - 1
+  }
+
+  // entries after the given sequence number (to sync a replica)
+  pub fn since(&self, seq: usize) -> &[LogEntry] {
+    let start = (seq + 1).min(self.entries.len());
+    &self.entries[start..]
+  }
+
+  // sequence number of the first entry that differs from the other log
+  pub fn diff(&self, other: &[LogEntry]) -> Option<usize> {
+    let common = self.entries.iter().zip(other.iter())
+      .take_while(|(a, b)| a.to_bytes() == b.to_bytes())
+      .count();
+
+    if common == self.entries.len() && common == other.len() {
+      return None
+    }
+
+    Some(common)
+  }
+
+  pub fn append(&mut self, entry: LogEntry) -> Result<()> {
+    let next = self.entries.len();
+    if entry.seq != next {
+      return Err(Error::InvalidSequence { expected: next, found: entry.seq })
+    }
+
+    apply(&mut self.identity, &entry.event)?;
+    self.entries.push(entry);
+    Ok(())
+  }
+
+  pub fn cancel(&mut self, ev: Cancel) -> Result<()> {
+    self.push(Event::Cancel(ev))
+  }
+
+  pub fn renew(&mut self, ev: Renew) -> Result<()> {
+    self.push(Event::Renew(ev))
+  }
+
+  pub fn evolve(&mut self, card: Card) -> Result<()> {
+    self.push(Event::Evolve(card))
+  }
+
+  pub fn save(&mut self, registry: Registry) -> Result<()> {
+    self.push(Event::Registry(registry))
+  }
+
+  fn push(&mut self, event: Event) -> Result<()> {
+    let seq = self.entries.len();
+    self.append(LogEntry { seq, event })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::OType;
+
+  use rand::rngs::OsRng;
+  use ed25519_dalek::Keypair;
+
+  fn create() -> EventLog {
+    let mut csprng = OsRng{};
+
+    // create master group
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+
+    // create genesis card and log
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", std::slice::from_ref(&master)).unwrap();
+    let mut log = EventLog::new(genesis).unwrap();
+
+    let reg1 = Registry::new(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!", log.identity().prev().unwrap(), 0).unwrap();
+    log.save(reg1.clone()).unwrap();
+
+    // evolve identity with the master group
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let renew = Renew::new(&m_keypair, &id_keypair2.public, log.identity().prev().unwrap(), true).unwrap();
+    log.renew(renew).unwrap();
+
+    let card2 = Card::new(false, &id_keypair2, b"No info!", &[master]).unwrap();
+    log.evolve(card2.clone()).unwrap();
+
+    let reanchor = Registry::reanchor(&id_keypair2, &reg1, &card2, 1).unwrap();
+    log.save(reanchor.clone()).unwrap();
+
+    let reg2 = Registry::new(&id_keypair2, "idp.io/test", "test", OType::SET, b"More info!", reanchor.hash(), 1).unwrap();
+    log.save(reg2).unwrap();
+
+    log
+  }
+
+  #[test]
+  fn replay_log() {
+    let log = create();
+    assert!(log.head() == 5);
+
+    // persist and rebuild the identity
+    let bytes = bincode::serialize(log.entries()).unwrap();
+    let entries: Vec<LogEntry> = bincode::deserialize(&bytes).unwrap();
+    let replayed = EventLog::replay(&entries).unwrap();
+
+    let identity = replayed.identity();
+    assert!(identity.udi == log.identity().udi);
+    assert!(identity.cards.len() == 2);
+    assert!(identity.registry("idp.io/test").unwrap().len() == 3);
+    assert!(identity.is_enabled());
+    identity.verify_full().unwrap();
+  }
+
+  #[test]
+  fn sync_replica() {
+    let log = create();
+
+    // replica only has the first entries
+    let mut replica = EventLog::replay(&log.entries()[0..2]).unwrap();
+    assert!(replica.diff(log.entries()) == Some(2));
+
+    for entry in log.since(replica.head()).iter() {
+      replica.append(entry.clone()).unwrap();
+    }
+
+    assert!(replica.diff(log.entries()).is_none());
+    assert!(replica.identity().cards.len() == 2);
+  }
+
+  #[test]
+  fn invalid_replay() {
+    let log = create();
+    let entries = log.entries();
+
+    // missing entry
+    let mut gap = entries.to_vec();
+    gap.remove(2);
+    assert!(EventLog::replay(&gap).err() == Some(Error::InvalidSequence { expected: 2, found: 3 }));
+
+    // reordered events (evolve before renew) fail the identity validation
+    let mut reordered = entries.to_vec();
+    reordered.swap(2, 3);
+    reordered[2].seq = 2;
+    reordered[3].seq = 3;
+    assert!(EventLog::replay(&reordered).err() == Some(Error::EvolveEnabled));
+
+    // no genesis
+    assert!(EventLog::replay(&entries[1..]).err() == Some(Error::InvalidSequence { expected: 0, found: 1 }));
+
+    let mut no_genesis = entries[1..].to_vec();
+    no_genesis[0].seq = 0;
+    assert!(EventLog::replay(&no_genesis).err() == Some(Error::InvalidGenesis));
+  }
+}
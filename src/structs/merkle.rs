@@ -0,0 +1,447 @@
+use serde::{Serialize, Deserialize, Deserializer, de};
+use ed25519_dalek::{PublicKey, Signature};
+
+use crate::structs::{Result, Error, Hash};
+use crate::structs::signer::Signer;
+use crate::structs::payload::{self, Payload, Format};
+use crate::structs::stream::{Stream, StreamBlock};
+
+// RFC 6962 domain separation of leaves and nodes
+const LEAF: u8 = 0x00;
+const NODE: u8 = 0x01;
+
+// tree leaf of a block hash
+pub fn leaf(hash: &Hash) -> Hash {
+  let mut data = vec![LEAF];
+  data.extend(hash.as_bytes());
+  Hash::of(&data)
+}
+
+fn node(left: &Hash, right: &Hash) -> Hash {
+  let mut data = vec![NODE];
+  data.extend(left.as_bytes());
+  data.extend(right.as_bytes());
+  Hash::of(&data)
+}
+
+// largest power of 2 smaller than n (n > 1)
+fn split(n: usize) -> usize {
+  let mut k = 1;
+  while k << 1 < n {
+    k <<= 1;
+  }
+
+  k
+}
+
+// root of the tree over the leaves (RFC 6962 MTH)
+fn root(leaves: &[Hash]) -> Hash {
+  match leaves.len() {
+    0 => Hash::of(&[]),
+    1 => leaves[0],
+    n => {
+      let k = split(n);
+      node(&root(&leaves[..k]), &root(&leaves[k..]))
+    }
+  }
+}
+
+// audit path of leaf m (RFC 6962 PATH)
+fn path(m: usize, leaves: &[Hash]) -> Vec<Hash> {
+  let n = leaves.len();
+  if n <= 1 {
+    return Vec::new()
+  }
+
+  let k = split(n);
+  let (mut path, sibling) = match m < k {
+    true => (path(m, &leaves[..k]), root(&leaves[k..])),
+    false => (path(m - k, &leaves[k..]), root(&leaves[..k]))
+  };
+
+  path.push(sibling);
+  path
+}
+
+// consistency path between the first m leaves and all leaves (RFC 6962 SUBPROOF)
+fn subproof(m: usize, leaves: &[Hash], complete: bool) -> Vec<Hash> {
+  let n = leaves.len();
+  if m == n {
+    return match complete {
+      true => Vec::new(),
+      false => vec![root(leaves)]
+    }
+  }
+
+  let k = split(n);
+  let (mut path, sibling) = match m <= k {
+    true => (subproof(m, &leaves[..k], complete), root(&leaves[k..])),
+    false => (subproof(m - k, &leaves[k..], false), root(&leaves[..k]))
+  };
+
+  path.push(sibling);
+  path
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Accumulator (Merkle mountain range of the block hashes)
+//   Keeps only the roots of the perfect subtrees (one per bit of the size), a push is O(log n). The root is
+//   the same as the RFC 6962 tree over all blocks.
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct Accumulator {
+  size: usize,
+  peaks: Vec<Hash>
+}
+
+// a received accumulator must have one peak per bit of its size
+impl<'de> Deserialize<'de> for Accumulator {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+    #[derive(Deserialize)]
+    struct Peaks {
+      size: usize,
+      peaks: Vec<Hash>
+    }
+
+    let acc = Peaks::deserialize(deserializer)?;
+    if acc.peaks.len() != acc.size.count_ones() as usize {
+      return Err(de::Error::custom(Error::InvalidAccumulator))
+    }
+
+    Ok(Self { size: acc.size, peaks: acc.peaks })
+  }
+}
+
+impl Accumulator {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn size(&self) -> usize {
+    self.size
+  }
+
+  // append the hash of the next block
+  pub fn push(&mut self, hash: &Hash) -> Result<()> {
+    if self.peaks.len() != self.size.count_ones() as usize {
+      return Err(Error::InvalidAccumulator)
+    }
+
+    let mut peak = leaf(hash);
+    let mut n = self.size;
+    while n & 1 == 1 {
+      let left = self.peaks.pop().ok_or(Error::InvalidAccumulator)?;
+      peak = node(&left, &peak);
+      n >>= 1;
+    }
+
+    self.peaks.push(peak);
+    self.size += 1;
+    Ok(())
+  }
+
+  pub fn root(&self) -> Hash {
+    let mut peaks = self.peaks.iter().rev();
+    match peaks.next() {
+      None => Hash::of(&[]),
+      Some(last) => peaks.fold(*last, |acc, peak| node(peak, &acc))
+    }
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Proofs
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct InclusionProof {
+  pub index: usize,     // block index
+  pub size: usize,      // number of blocks of the tree
+  pub path: Vec<Hash>
+}
+
+impl InclusionProof {
+  pub(crate) fn new(index: usize, hashes: &[Hash]) -> Option<Self> {
+    if index >= hashes.len() {
+      return None
+    }
+
+    let leaves: Vec<Hash> = hashes.iter().map(leaf).collect();
+    Some(Self { index, size: hashes.len(), path: path(index, &leaves) })
+  }
+
+  // the block hash is in the tree with the root (RFC 9162, 2.1.3.2)
+  pub fn verify(&self, hash: &Hash, root: &Hash) -> bool {
+    if self.index >= self.size {
+      return false
+    }
+
+    let (mut fnode, mut snode) = (self.index, self.size - 1);
+    let mut r = leaf(hash);
+    for p in self.path.iter() {
+      if snode == 0 {
+        return false
+      }
+
+      if fnode & 1 == 1 || fnode == snode {
+        r = node(p, &r);
+        while fnode & 1 == 0 && fnode != 0 {
+          fnode >>= 1;
+          snode >>= 1;
+        }
+      } else {
+        r = node(&r, p);
+      }
+
+      fnode >>= 1;
+      snode >>= 1;
+    }
+
+    snode == 0 && r == *root
+  }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct ConsistencyProof {
+  pub first: usize,     // size of the older tree
+  pub second: usize,    // size of the newer tree
+  pub path: Vec<Hash>
+}
+
+impl ConsistencyProof {
+  pub(crate) fn new(first: usize, hashes: &[Hash]) -> Option<Self> {
+    if first > hashes.len() {
+      return None
+    }
+
+    let leaves: Vec<Hash> = hashes.iter().map(leaf).collect();
+    let path = match first {
+      0 => Vec::new(),
+      _ => subproof(first, &leaves, true)
+    };
+
+    Some(Self { first, second: hashes.len(), path })
+  }
+
+  // the older tree is a prefix of the newer one (RFC 9162, 2.1.4.2)
+  pub fn verify(&self, first_root: &Hash, second_root: &Hash) -> bool {
+    if self.first > self.second {
+      return false
+    }
+
+    // the empty tree is a prefix of any tree, and a tree of itself
+    if self.first == 0 || self.first == self.second {
+      return self.path.is_empty() && (self.first == 0 || first_root == second_root)
+    }
+
+    let mut path = self.path.clone();
+    if self.first.is_power_of_two() {
+      path.insert(0, *first_root);
+    }
+
+    let (first, rest) = match path.split_first() {
+      None => return false,
+      Some(split) => split
+    };
+
+    let (mut fnode, mut snode) = (self.first - 1, self.second - 1);
+    while fnode & 1 == 1 {
+      fnode >>= 1;
+      snode >>= 1;
+    }
+
+    let (mut fr, mut sr) = (*first, *first);
+    for c in rest.iter() {
+      if snode == 0 {
+        return false
+      }
+
+      if fnode & 1 == 1 || fnode == snode {
+        fr = node(c, &fr);
+        sr = node(c, &sr);
+        while fnode & 1 == 0 && fnode != 0 {
+          fnode >>= 1;
+          snode >>= 1;
+        }
+      } else {
+        sr = node(&sr, c);
+      }
+
+      fnode >>= 1;
+      snode >>= 1;
+    }
+
+    snode == 0 && fr == *first_root && sr == *second_root
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Checkpoint (root of the blocks of a stream, signed by the stream key)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Checkpoint {
+  pub asi: String,
+  pub size: usize,
+  pub root: Hash,
+  #[serde(with = "crate::structs::json::b64")]
+  pub sig: Signature
+}
+
+impl Checkpoint {
+  pub fn new(signer: &dyn Signer, stream: &Stream) -> Result<Self> {
+    let acc = stream.accumulator();
+    let sig = signer.sign(&Self::data(&stream.asi, acc.size(), &acc.root()))?;
+
+    Ok(Self { asi: stream.asi.clone(), size: acc.size(), root: acc.root(), sig })
+  }
+
+  pub fn verify(&self, key: &PublicKey) -> bool {
+    let sig_data = Self::data(&self.asi, self.size, &self.root);
+    key.verify(&sig_data, &self.sig).is_ok()
+  }
+
+  // a third party only needs the stream key, the checkpoint, the block and the proof
+  pub fn check_inclusion(&self, key: &PublicKey, block: &StreamBlock, proof: &InclusionProof) -> Result<()> {
+    if !self.verify(key) {
+      return Err(Error::InvalidCheckpoint)
+    }
+
+    if proof.size != self.size || !proof.verify(&block.hash(), &self.root) {
+      return Err(Error::InvalidInclusionProof { index: proof.index })
+    }
+
+    Ok(())
+  }
+
+  // the newer checkpoint of the same stream extends this one
+  pub fn check_consistency(&self, key: &PublicKey, newer: &Checkpoint, proof: &ConsistencyProof) -> Result<()> {
+    if !self.verify(key) || !newer.verify(key) || self.asi != newer.asi {
+      return Err(Error::InvalidCheckpoint)
+    }
+
+    if proof.first != self.size || proof.second != newer.size || !proof.verify(&self.root, &newer.root) {
+      return Err(Error::InvalidConsistencyProof { first: proof.first, second: proof.second })
+    }
+
+    Ok(())
+  }
+
+  pub(crate) fn data(asi: &str, size: usize, root: &Hash) -> Vec<u8> {
+    Payload::new(payload::STREAM_CHECKPOINT, Format::CURRENT)
+      .field(asi)
+      .field(&size)
+      .field(root)
+      .into_bytes()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::{OType, cbor};
+  use crate::structs::stream::*;
+
+  use rand::rngs::OsRng;
+  use ed25519_dalek::Keypair;
+
+  fn create(blocks: usize) -> (Stream, Keypair) {
+    let mut csprng = OsRng{};
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let mut stream = Stream::new(&profile_keypair, "udi-random", "r-random", &[], genesis, None).unwrap();
+    for index in 0..blocks {
+      let record = Record { oper: OType::SET, info: format!("Info {}!", index).into_bytes() };
+      let block = StreamBlock::new(&profile_keypair, record, stream.prev()).unwrap();
+      stream.save(block).unwrap();
+    }
+
+    (stream, profile_keypair)
+  }
+
+  #[test]
+  fn accumulator_root() {
+    let (stream, _) = create(13);
+    let hashes: Vec<Hash> = stream.blocks.iter().map(|bl| bl.hash()).collect();
+
+    // the incremental root is the same as the full tree, for every size
+    let mut acc = Accumulator::new();
+    assert!(acc.root() == root(&[]));
+    for (index, hash) in hashes.iter().enumerate() {
+      acc.push(hash).unwrap();
+      let leaves: Vec<Hash> = hashes[..=index].iter().map(leaf).collect();
+      assert!(acc.size() == index + 1 && acc.root() == root(&leaves));
+    }
+
+    assert!(*stream.accumulator() == acc);
+  }
+
+  #[test]
+  fn inclusion_proofs() {
+    let (stream, profile_keypair) = create(7);
+    let checkpoint = Checkpoint::new(&profile_keypair, &stream).unwrap();
+    assert!(checkpoint.size == 7 && checkpoint.verify(&profile_keypair.public));
+
+    for (index, block) in stream.blocks.iter().enumerate() {
+      let proof = stream.prove(index).unwrap();
+      assert!(proof.path.len() <= 3);
+      checkpoint.check_inclusion(&profile_keypair.public, block, &proof).unwrap();
+    }
+
+    // another block, index or root
+    let proof = stream.prove(2).unwrap();
+    let err = checkpoint.check_inclusion(&profile_keypair.public, &stream.blocks[3], &proof);
+    assert!(err == Err(Error::InvalidInclusionProof { index: 2 }));
+
+    let mut moved = proof.clone();
+    moved.index = 3;
+    assert!(!moved.verify(&stream.blocks[2].hash(), &checkpoint.root));
+    assert!(!proof.verify(&stream.blocks[2].hash(), &stream.blocks[2].hash()));
+    assert!(stream.prove(7).is_none());
+
+    // only valid with the stream key
+    let mut csprng = OsRng{};
+    let other: Keypair = Keypair::generate(&mut csprng);
+    let err = checkpoint.check_inclusion(&other.public, &stream.blocks[2], &proof);
+    assert!(err == Err(Error::InvalidCheckpoint));
+  }
+
+  #[test]
+  fn consistency_proofs() {
+    let (mut stream, profile_keypair) = create(3);
+    let old = Checkpoint::new(&profile_keypair, &stream).unwrap();
+
+    for size in 4..=9 {
+      let record = Record { oper: OType::SET, info: b"More info!".to_vec() };
+      let block = StreamBlock::new(&profile_keypair, record, stream.prev()).unwrap();
+      stream.save(block).unwrap();
+
+      let new = Checkpoint::new(&profile_keypair, &stream).unwrap();
+      let proof = stream.prove_consistency(3).unwrap();
+      assert!(proof.second == size);
+      old.check_consistency(&profile_keypair.public, &new, &proof).unwrap();
+    }
+
+    // every older size is consistent with the current tree
+    let acc = stream.accumulator();
+    for first in 0..=stream.blocks.len() {
+      let proof = stream.prove_consistency(first).unwrap();
+      let mut old = Accumulator::new();
+      stream.blocks[..first].iter().for_each(|bl| old.push(&bl.hash()).unwrap());
+      assert!(proof.verify(&old.root(), &acc.root()));
+    }
+
+    // an accumulator without a peak for each bit of its size is rejected
+    let forged = Accumulator { size: 3, peaks: vec![acc.root()] };
+    assert!(forged.clone().push(&acc.root()) == Err(Error::InvalidAccumulator));
+    assert!(cbor::from_slice::<Accumulator>(&cbor::to_vec(&forged), "accumulator").is_err());
+    assert!(cbor::from_slice::<Accumulator>(&cbor::to_vec(acc), "accumulator").unwrap() == *acc);
+
+    // a rewritten history is not consistent
+    let (other, _) = create(9);
+    let new = Checkpoint::new(&profile_keypair, &stream).unwrap();
+    let proof = other.prove_consistency(3).unwrap();
+    let err = old.check_consistency(&profile_keypair.public, &new, &proof);
+    assert!(err == Err(Error::InvalidConsistencyProof { first: 3, second: 9 }));
+    assert!(stream.prove_consistency(10).is_none());
+  }
+}
@@ -1,9 +1,66 @@
 pub mod identity;
 pub mod anchor;
 pub mod stream;
+pub mod recovery;
+pub mod error;
+pub mod log;
+pub mod signer;
+pub mod payload;
+pub mod cbor;
+pub mod json;
+pub mod erasure;
+pub mod claims;
+pub mod receipt;
+pub mod derive;
+pub mod continuation;
+pub mod report;
+pub mod hash;
+pub mod merkle;
+pub mod fork;
+pub mod legacy;
+
+// the anchor registry of the identities
+pub use anchor::{DOMAIN, TYPE};
 
 use serde::{Serialize, Deserialize};
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum OType { SET, DEL }
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub enum OType { SET, DEL, REANCHOR }
+
+// materialised value of a chain of SET/DEL entries (as of some entry index)
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum State<T> {
+  Empty,
+  Set { index: usize, entry: T },
+  Deleted { index: usize, entry: T }  // the signed deletion entry
+}
+
+impl<T> State<T> {
+  // the last SET/DEL of the (index, oper, entry) list, a re-anchor doesn't change the value
+  pub(crate) fn of<'a, I: DoubleEndedIterator<Item = (usize, &'a OType, T)>>(entries: I) -> Self {
+    for (index, oper, entry) in entries.rev() {
+      match oper {
+        OType::SET => return State::Set { index, entry },
+        OType::DEL => return State::Deleted { index, entry },
+        OType::REANCHOR => continue
+      }
+    }
+
+    State::Empty
+  }
+
+  pub fn value(&self) -> Option<&T> {
+    match self {
+      State::Set { entry, .. } => Some(entry),
+      _ => None
+    }
+  }
+
+  pub fn is_deleted(&self) -> bool {
+    matches!(self, State::Deleted { .. })
+  }
+}
 
-pub type Result<T> = std::result::Result<T, String>;
\ No newline at end of file
+pub use error::{Error, ErrorKind};
+pub use hash::Hash;
+pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
@@ -0,0 +1,164 @@
+use serde::Serialize;
+use serde_bytes::Bytes;
+use ciborium::value::Value;
+
+use crate::structs::cbor;
+
+// domain tags, a signature is only valid for the structure it was made for
+pub const CARD: &str = "raiap.io/card";
+pub const CANCEL: &str = "raiap.io/cancel";
+pub const RENEW: &str = "raiap.io/renew";
+pub const GROUP: &str = "raiap.io/group";
+pub const REGISTRY: &str = "raiap.io/registry";
+pub const ANCHOR_AL: &str = "raiap.io/anchor/al";
+pub const STREAM_ASI: &str = "raiap.io/stream/asi";
+pub const STREAM: &str = "raiap.io/stream";
+pub const STREAM_BLOCK: &str = "raiap.io/stream/block";
+pub const STREAM_CHECKPOINT: &str = "raiap.io/stream/checkpoint";
+pub const DISCLOSURE_CHALLENGE: &str = "raiap.io/disclosure/challenge";
+pub const DISCLOSURE_RECEIPT: &str = "raiap.io/disclosure/receipt";
+pub const DERIVE_ROOT: &str = "raiap.io/derive/root";
+pub const DERIVE_PROFILE_KEY: &str = "raiap.io/derive/profile/key";
+pub const DERIVE_PROFILE_R: &str = "raiap.io/derive/profile/r";
+pub const LEDGER_COMMITMENT: &str = "raiap.io/ledger/commitment";
+
+//-----------------------------------------------------------------------------------------------------------
+// Format
+//   Legacy: concatenation of bincode fields without domain tag or version (the first release)
+//   V1: bincode of tag + version + fields
+//   V2: canonical CBOR array [tag, version, fields...]
+//-----------------------------------------------------------------------------------------------------------
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Format { Legacy, V1, V2 }
+
+impl Format {
+  pub const CURRENT: Format = Format::V2;
+
+  pub fn version(&self) -> u8 {
+    match self {
+      Format::Legacy => 0,
+      Format::V1 => 1,
+      Format::V2 => 2
+    }
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Migration (format of each object of a replayed chain)
+//   The formats of a chain can only move forward: objects after the first one in a newer format (the migration
+//   point) can't fall back to the older payloads.
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+  newest: Format,
+  oldest: Format
+}
+
+impl Migration {
+  // only the current format
+  pub fn current() -> Self {
+    Self { newest: Format::CURRENT, oldest: Format::CURRENT }
+  }
+
+  // objects of the releases linked by signatures, first release objects up to the first V1 object
+  pub fn legacy() -> Self {
+    Self { newest: Format::V1, oldest: Format::Legacy }
+  }
+
+  // the format that verifies the object (None if no accepted format does)
+  pub fn verify<F: Fn(Format) -> bool>(&mut self, check: F) -> Option<Format> {
+    let found = [Format::V2, Format::V1, Format::Legacy].iter().copied()
+      .filter(|format| format.version() <= self.newest.version() && format.version() >= self.oldest.version())
+      .find(|format| check(*format))?;
+
+    self.oldest = found;
+    Some(found)
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Payload (signed message of a structure)
+//-----------------------------------------------------------------------------------------------------------
+pub struct Payload {
+  format: Format,
+  data: Vec<u8>,
+  fields: Vec<Value>
+}
+
+impl Payload {
+  pub fn new(tag: &str, format: Format) -> Self {
+    let payload = Self { format, data: Vec::new(), fields: Vec::new() };
+    match format {
+      Format::Legacy => payload,
+      _ => payload.field(tag).field(&format.version())
+    }
+  }
+
+  pub fn field<T: Serialize + ?Sized>(mut self, value: &T) -> Self {
+    match self.format {
+      Format::V2 => self.fields.push(cbor::value(value)),
+
+      // This unwrap() should never fail, or it's a serious code bug!
+      _ => self.data.extend(bincode::serialize(value).unwrap())
+    }
+
+    self
+  }
+
+  // byte string field (same bincode layout as a slice)
+  pub fn bytes(self, value: &[u8]) -> Self {
+    self.field(Bytes::new(value))
+  }
+
+  pub fn into_bytes(self) -> Vec<u8> {
+    match self.format {
+      Format::V2 => cbor::write(&Value::Array(self.fields)),
+      _ => self.data
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn tagged_payloads() {
+    let al = Payload::new(ANCHOR_AL, Format::V1).field("udi").field("r").into_bytes();
+    let asi = Payload::new(STREAM_ASI, Format::V1).field("udi").field("r").into_bytes();
+    assert!(al != asi);
+
+    // the version is part of the message
+    let legacy = Payload::new(ANCHOR_AL, Format::Legacy).field("udi").field("r").into_bytes();
+    assert!(legacy != al);
+    assert!(al.ends_with(&legacy));
+
+    // byte strings keep the bincode layout
+    let field = Payload::new(CARD, Format::V1).field(&b"info"[..]).into_bytes();
+    let bytes = Payload::new(CARD, Format::V1).bytes(b"info").into_bytes();
+    assert!(field == bytes);
+  }
+
+  #[test]
+  fn migration_order() {
+    let mut migration = Migration::legacy();
+    assert!(migration.verify(|format| format == Format::V2).is_none());
+    assert!(migration.verify(|format| format == Format::Legacy) == Some(Format::Legacy));
+    assert!(migration.verify(|_| true) == Some(Format::V1));
+
+    // no way back after the migration point
+    assert!(migration.verify(|format| format == Format::Legacy).is_none());
+    assert!(Migration::current().verify(|format| format != Format::V2).is_none());
+  }
+
+  #[test]
+  fn cbor_payload() {
+    let payload = Payload::new(STREAM_ASI, Format::V2).field("udi").bytes(b"r").into_bytes();
+
+    // [tag, 2, "udi", h'72']
+    let mut expected = vec![0x84, 0x73];
+    expected.extend(STREAM_ASI.as_bytes());
+    expected.extend(&[0x02, 0x63, b'u', b'd', b'i', 0x41, b'r']);
+    assert!(payload == expected);
+  }
+}
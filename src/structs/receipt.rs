@@ -0,0 +1,289 @@
+use serde::{Serialize, Deserialize};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use ed25519_dalek::{PublicKey, Signature};
+
+use crate::structs::{Result, Error, OType, cbor};
+use crate::structs::signer::Signer;
+use crate::structs::payload::{self, Payload, Format};
+use crate::structs::identity::Identity;
+use crate::structs::anchor::{self, Anchor, Anchors};
+use crate::structs::erasure::{Vault, Content};
+
+pub const DOMAIN: &str = "raiap.io/disclosure";
+pub const TYPE: &str = "receipt";
+
+//-----------------------------------------------------------------------------------------------------------
+// Challenge (issued and signed by the verifier)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Challenge {
+  #[serde(with = "crate::structs::json::b64")]
+  pub verifier: PublicKey,
+  pub purpose: String,
+  #[serde(with = "crate::structs::json::b64")]
+  pub nonce: Vec<u8>,
+  #[serde(with = "crate::structs::json::b64")]
+  pub sig: Signature
+}
+
+impl Challenge {
+  pub fn new(signer: &dyn Signer, purpose: &str) -> Result<Self> {
+    let mut csprng = OsRng{};
+    let mut nonce = vec![0u8; 32];
+    csprng.fill_bytes(&mut nonce);
+
+    let verifier = signer.public();
+    let sig = signer.sign(&Self::data(&verifier, purpose, &nonce))?;
+
+    Ok(Self { verifier, purpose: purpose.into(), nonce, sig })
+  }
+
+  pub fn verify(&self) -> bool {
+    let sig_data = Self::data(&self.verifier, &self.purpose, &self.nonce);
+    self.verifier.verify(&sig_data, &self.sig).is_ok()
+  }
+
+  pub(crate) fn data(verifier: &PublicKey, purpose: &str, nonce: &[u8]) -> Vec<u8> {
+    Payload::new(payload::DISCLOSURE_CHALLENGE, Format::CURRENT)
+      .field(verifier)
+      .field(purpose)
+      .bytes(nonce)
+      .into_bytes()
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Receipt (answer of the holder, signed with the identity key)
+//   The AL signature (with the profile key) proves the anchor, the receipt signature makes it non-repudiable.
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Receipt {
+  pub challenge: Challenge,
+  pub udi: String,
+  pub r: String,
+  pub sn: usize,
+
+  #[serde(with = "crate::structs::json::b64")]
+  pub key: PublicKey,     // profile key
+  #[serde(with = "crate::structs::json::b64")]
+  pub al_sig: Signature,
+  #[serde(with = "crate::structs::json::b64")]
+  pub sig: Signature,
+  key_index: usize
+}
+
+impl Receipt {
+  pub fn new(signer: &dyn Signer, profile: &dyn Signer, challenge: &Challenge, identity: &Identity, anchor: &Anchor) -> Result<Self> {
+    if !challenge.verify() {
+      return Err(Error::InvalidChallenge)
+    }
+
+    let key = profile.public();
+    let al_sig = anchor.al_signature(profile, &identity.udi)?;
+    let key_index = identity.cards.len() - 1;
+
+    let sig_data = Self::data(challenge, &identity.udi, &anchor.r, anchor.sn, &key, &al_sig, key_index);
+    let sig = signer.sign(&sig_data)?;
+
+    Ok(Self { challenge: challenge.clone(), udi: identity.udi.clone(), r: anchor.r.clone(), sn: anchor.sn, key, al_sig, sig, key_index })
+  }
+
+  pub fn key_index(&self) -> usize {
+    self.key_index
+  }
+
+  pub fn verify(&self, key: &PublicKey) -> bool {
+    let sig_data = Self::data(&self.challenge, &self.udi, &self.r, self.sn, &self.key, &self.al_sig, self.key_index);
+    key.verify(&sig_data, &self.sig).is_ok()
+  }
+
+  // verifier side: the receipt answers the challenge and the disclosed anchor is registered in the identity
+  pub fn check(&self, challenge: &Challenge, identity: &Identity) -> Result<()> {
+    if !challenge.verify() || *challenge != self.challenge {
+      return Err(Error::InvalidChallenge)
+    }
+
+    let card = identity.cards.get(self.key_index).ok_or(Error::InvalidReceipt)?;
+    if self.udi != identity.udi || !self.verify(card.key()) {
+      return Err(Error::InvalidReceipt)
+    }
+
+    if self.key.verify(&Anchor::al_data(&self.udi, &self.r), &self.al_sig).is_err() {
+      return Err(Error::InvalidReceipt)
+    }
+
+    let al = anchor::al(&self.al_sig);
+    let anchors = Anchors::of(identity)?;
+    match anchors.get(&al) {
+      Some(an) if an.r == self.r && an.sn == self.sn => (),
+      _ => return Err(Error::UnknownAnchor { r: self.r.clone() })
+    }
+
+    match anchors.is_revoked(&al) {
+      true => Err(Error::AnchorRevoked { al }),
+      false => Ok(())
+    }
+  }
+
+  // holder side: append a sealed commitment of the receipt to the disclosure registry, the receipt stays in the vault
+  pub fn record(&self, signer: &dyn Signer, identity: &mut Identity, vault: &mut Vault) -> Result<()> {
+    let info = vault.seal(&self.to_bytes());
+    identity.append(signer, DOMAIN, TYPE, OType::SET, &info)
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    cbor::to_vec(self)
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Result<Receipt> {
+    cbor::from_slice(bytes, "receipt")
+  }
+
+  pub(crate) fn data(challenge: &Challenge, udi: &str, r: &str, sn: usize, key: &PublicKey, al_sig: &Signature, key_index: usize) -> Vec<u8> {
+    Payload::new(payload::DISCLOSURE_RECEIPT, Format::CURRENT)
+      .field(&challenge.verifier)
+      .field(&challenge.purpose)
+      .bytes(&challenge.nonce)
+      .field(udi)
+      .field(r)
+      .field(&sn)
+      .field(key)
+      .field(al_sig)
+      .field(&key_index)
+      .into_bytes()
+  }
+}
+
+// the receipts recorded in the identity that are still in the vault (oldest first)
+pub fn receipts(identity: &Identity, vault: &Vault) -> Result<Vec<Receipt>> {
+  let chain = identity.registry(DOMAIN).map(|chain| chain.as_slice()).unwrap_or(&[]);
+
+  let mut receipts = Vec::new();
+  for reg in chain.iter().filter(|reg| reg.oper == OType::SET) {
+    if let Content::Sealed(bytes) = vault.open(&reg.info)? {
+      receipts.push(Receipt::from_bytes(bytes)?);
+    }
+  }
+
+  Ok(receipts)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::identity::*;
+
+  use ed25519_dalek::Keypair;
+
+  fn create() -> (Identity, Keypair, Keypair, Keypair, Anchor) {
+    let mut csprng = OsRng{};
+
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", &[master]).unwrap();
+    let mut identity = Identity::new(genesis).unwrap();
+
+    // anchored profile
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+    let anchor = Anchor::new(&profile_keypair, &identity.udi, "r-random", 0).unwrap();
+    let reg = Registry::new(&id_keypair, anchor::DOMAIN, anchor::TYPE, OType::SET, &anchor.to_bytes(), identity.prev().unwrap(), 0).unwrap();
+    identity.save(reg).unwrap();
+
+    (identity, m_keypair, id_keypair, profile_keypair, anchor)
+  }
+
+  #[test]
+  fn disclose_profile() {
+    let mut csprng = OsRng{};
+    let (mut identity, _, id_keypair, profile_keypair, anchor) = create();
+    let v_keypair: Keypair = Keypair::generate(&mut csprng);
+    let mut vault = Vault::new();
+
+    let challenge = Challenge::new(&v_keypair, "age verification").unwrap();
+    let receipt = Receipt::new(&id_keypair, &profile_keypair, &challenge, &identity, &anchor).unwrap();
+    receipt.check(&challenge, &identity).unwrap();
+
+    // the holder records the receipt, both sides keep the signed objects
+    receipt.record(&id_keypair, &mut identity, &mut vault).unwrap();
+    receipt.record(&id_keypair, &mut identity, &mut vault).unwrap();
+    identity.verify_full().unwrap();
+
+    let recorded = receipts(&identity, &vault).unwrap();
+    assert!(recorded.len() == 2 && recorded[0] == receipt);
+    assert!(recorded[0].challenge.verify());
+    assert!(recorded[0].check(&challenge, &identity).is_ok());
+
+    // the chain only has the commitments, the erased receipts are gone
+    let chain = identity.registry(DOMAIN).unwrap();
+    assert!(chain.iter().all(|reg| Receipt::from_bytes(&reg.info).is_err()));
+    assert!(vault.erase(&chain[0].info));
+    assert!(receipts(&identity, &vault).unwrap().len() == 1);
+    assert!(receipts(&identity, &Vault::new()).unwrap().is_empty());
+  }
+
+  #[test]
+  fn record_after_evolution() {
+    let mut csprng = OsRng{};
+    let (mut identity, m_keypair, _, profile_keypair, anchor) = create();
+    let v_keypair: Keypair = Keypair::generate(&mut csprng);
+    let mut vault = Vault::new();
+
+    // evolution to a new identity key
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    identity.cancel(Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap()).unwrap();
+    identity.renew(Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), true).unwrap()).unwrap();
+    identity.evolve(Card::new(false, &id_keypair2, b"No important info!", &[master]).unwrap()).unwrap();
+
+    let challenge = Challenge::new(&v_keypair, "age verification").unwrap();
+    let receipt = Receipt::new(&id_keypair2, &profile_keypair, &challenge, &identity, &anchor).unwrap();
+    receipt.check(&challenge, &identity).unwrap();
+
+    receipt.record(&id_keypair2, &mut identity, &mut vault).unwrap();
+    identity.verify_full().unwrap();
+    assert!(receipts(&identity, &vault).unwrap() == vec![receipt]);
+  }
+
+  #[test]
+  fn invalid_disclosure() {
+    let mut csprng = OsRng{};
+    let (identity, _, id_keypair, profile_keypair, anchor) = create();
+    let v_keypair: Keypair = Keypair::generate(&mut csprng);
+    let challenge = Challenge::new(&v_keypair, "age verification").unwrap();
+
+    // the purpose is bound to the verifier signature
+    let mut changed = challenge.clone();
+    changed.purpose = "marketing".into();
+    assert!(Receipt::new(&id_keypair, &profile_keypair, &changed, &identity, &anchor) == Err(Error::InvalidChallenge));
+
+    // answer to another challenge
+    let receipt = Receipt::new(&id_keypair, &profile_keypair, &challenge, &identity, &anchor).unwrap();
+    let other = Challenge::new(&v_keypair, "age verification").unwrap();
+    assert!(receipt.check(&other, &identity) == Err(Error::InvalidChallenge));
+
+    // not signed by the identity key
+    let receipt = Receipt::new(&profile_keypair, &profile_keypair, &challenge, &identity, &anchor).unwrap();
+    assert!(receipt.check(&challenge, &identity) == Err(Error::InvalidReceipt));
+
+    // anchor not registered in the identity
+    let unknown = Anchor::new(&profile_keypair, &identity.udi, "other-random", 1).unwrap();
+    let receipt = Receipt::new(&id_keypair, &profile_keypair, &challenge, &identity, &unknown).unwrap();
+    assert!(receipt.check(&challenge, &identity) == Err(Error::UnknownAnchor { r: "other-random".into() }));
+  }
+
+  #[test]
+  fn revoked_anchor() {
+    let mut csprng = OsRng{};
+    let (mut identity, _, id_keypair, profile_keypair, anchor) = create();
+    let v_keypair: Keypair = Keypair::generate(&mut csprng);
+    let challenge = Challenge::new(&v_keypair, "age verification").unwrap();
+
+    let receipt = Receipt::new(&id_keypair, &profile_keypair, &challenge, &identity, &anchor).unwrap();
+    receipt.check(&challenge, &identity).unwrap();
+
+    anchor.revoke(&id_keypair, &mut identity).unwrap();
+    assert!(receipt.check(&challenge, &identity) == Err(Error::AnchorRevoked { al: anchor.al.clone() }));
+  }
+}
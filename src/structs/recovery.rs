@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, SECRET_KEY_LENGTH};
+
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+use crate::structs::{Result, Error};
+use crate::structs::identity::*;
+
+//-----------------------------------------------------------------------------------------------------------
+// GF(2^8) arithmetic (AES polynomial x^8 + x^4 + x^3 + x + 1)
+//-----------------------------------------------------------------------------------------------------------
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+  let mut res = 0u8;
+  while b != 0 {
+    if b & 1 != 0 {
+      res ^= a;
+    }
+
+    let carry = a & 0x80;
+    a <<= 1;
+    if carry != 0 {
+      a ^= 0x1b;
+    }
+
+    b >>= 1;
+  }
+
+  res
+}
+
+fn gf_inv(a: u8) -> u8 {
+  // a^254 == a^-1 for every non-zero element
+  let mut res = 1u8;
+  for _ in 0..254 {
+    res = gf_mul(res, a);
+  }
+
+  res
+}
+
+// evaluate the polynomial (coefficients in increasing order) at x
+fn eval(coefs: &[u8], x: u8) -> u8 {
+  coefs.iter().rev().fold(0u8, |acc, c| gf_mul(acc, x) ^ c)
+}
+
+// lagrange interpolation of the points at x (all point x's must be distinct)
+fn interpolate(points: &[(u8, u8)], x: u8) -> u8 {
+  let mut res = 0u8;
+  for (i, (xi, yi)) in points.iter().enumerate() {
+    let mut basis = 1u8;
+    for (j, (xj, _)) in points.iter().enumerate() {
+      if i != j {
+        basis = gf_mul(basis, gf_mul(x ^ xj, gf_inv(xi ^ xj)));
+      }
+    }
+
+    res ^= gf_mul(*yi, basis);
+  }
+
+  res
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Share
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Share {
+  pub index: u8,
+  pub threshold: u8,
+  pub commit: String,   // commit of the shared public key
+  pub data: Vec<u8>
+}
+
+pub fn split(keypair: &Keypair, threshold: u8, total: u8) -> Result<Vec<Share>> {
+  if threshold == 0 || threshold > total {
+    return Err(Error::InvalidSharesThreshold)
+  }
+
+  let mut csprng = OsRng{};
+  let secret = keypair.secret.to_bytes();
+  let commit = commit(&keypair.public);
+
+  // one random polynomial of degree (threshold - 1) per secret byte
+  let mut polys = Vec::<Vec<u8>>::with_capacity(SECRET_KEY_LENGTH);
+  for byte in secret.iter() {
+    let mut coefs = vec![0u8; threshold as usize];
+    csprng.fill_bytes(&mut coefs);
+    coefs[0] = *byte;
+    polys.push(coefs);
+  }
+
+  let shares = (1..=total).map(|index| {
+    let data = polys.iter().map(|coefs| eval(coefs, index)).collect();
+    Share { index, threshold, commit: commit.clone(), data }
+  }).collect();
+
+  Ok(shares)
+}
+
+pub fn combine(shares: &[Share]) -> Result<Keypair> {
+  let first = shares.first().ok_or(Error::NoShares)?;
+
+  // remove duplicated shares and check consistency
+  let mut selected = BTreeMap::<u8, &Share>::new();
+  for sh in shares.iter() {
+    if sh.index == 0 || sh.threshold != first.threshold || sh.commit != first.commit || sh.data.len() != SECRET_KEY_LENGTH {
+      return Err(Error::InconsistentShares)
+    }
+
+    selected.insert(sh.index, sh);
+  }
+
+  if selected.len() < first.threshold as usize {
+    return Err(Error::NotEnoughShares { required: first.threshold as usize, found: selected.len() })
+  }
+
+  let selected: Vec<&Share> = selected.values().take(first.threshold as usize).cloned().collect();
+  let mut secret = [0u8; SECRET_KEY_LENGTH];
+  for (i, byte) in secret.iter_mut().enumerate() {
+    let points: Vec<(u8, u8)> = selected.iter().map(|sh| (sh.index, sh.data[i])).collect();
+    *byte = interpolate(&points, 0);
+  }
+
+  let secret = SecretKey::from_bytes(&secret).map_err(|_| Error::InvalidRecoveredKey)?;
+  let public = PublicKey::from(&secret);
+  if commit(&public) != first.commit {
+    return Err(Error::InvalidRecoveredKey)
+  }
+
+  Ok(Keypair { secret, public })
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Identity recovery
+//-----------------------------------------------------------------------------------------------------------
+pub fn recover(shares: &[Share], identity: &Identity, next: &PublicKey) -> Result<Renew> {
+  let keypair = combine(shares)?;
+
+  // the recovered key must be a member of a group in the current card
+  if !identity.card().groups.values().any(|gr| gr.is_member(&keypair.public)) {
+    return Err(Error::NoGroup)
+  }
+
+  // an enabled identity requires a renew(cancel) that includes the key
+  let prev = identity.prev()?;
+  Renew::new(&keypair, next, prev, identity.is_enabled())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ed25519_dalek::Keypair;
+
+  fn create() -> (Identity, TLGroup, Keypair) {
+    let mut csprng = OsRng{};
+
+    // create master group
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+
+    // create genesis card and identity
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", std::slice::from_ref(&master)).unwrap();
+    let identity = Identity::new(genesis).unwrap();
+
+    (identity, master, m_keypair)
+  }
+
+  #[test]
+  fn split_and_combine() {
+    let mut csprng = OsRng{};
+    let keypair: Keypair = Keypair::generate(&mut csprng);
+    let shares = split(&keypair, 3, 5).unwrap();
+    assert!(shares.len() == 5);
+
+    // any subset of 3 shares recovers the key
+    for subset in [[0, 1, 2], [0, 2, 4], [4, 3, 1], [1, 2, 3]].iter() {
+      let selected: Vec<Share> = subset.iter().map(|i| shares[*i].clone()).collect();
+      let recovered = combine(&selected).unwrap();
+      assert!(recovered.to_bytes()[..] == keypair.to_bytes()[..]);
+    }
+  }
+
+  #[test]
+  fn not_enough_shares() {
+    let mut csprng = OsRng{};
+    let keypair: Keypair = Keypair::generate(&mut csprng);
+    let shares = split(&keypair, 3, 5).unwrap();
+
+    // duplicated shares don't count
+    let selected = vec![shares[0].clone(), shares[3].clone(), shares[3].clone()];
+    assert!(combine(&selected).err() == Some(Error::NotEnoughShares { required: 3, found: 2 }));
+  }
+
+  #[test]
+  fn fewer_shares_reveal_nothing() {
+    let mut csprng = OsRng{};
+    let keypair: Keypair = Keypair::generate(&mut csprng);
+    let shares = split(&keypair, 3, 5).unwrap();
+    let known = &shares[0..2];
+
+    // with K-1 shares every candidate secret byte is equally consistent:
+    // for each candidate there is exactly one value of the missing share that produces it
+    for i in 0..SECRET_KEY_LENGTH {
+      let mut candidates = [false; 256];
+      for missing in 0..=255u8 {
+        let mut points: Vec<(u8, u8)> = known.iter().map(|sh| (sh.index, sh.data[i])).collect();
+        points.push((shares[2].index, missing));
+        candidates[interpolate(&points, 0) as usize] = true;
+      }
+
+      assert!(candidates.iter().all(|c| *c));
+    }
+  }
+
+  #[test]
+  fn invalid_threshold() {
+    let mut csprng = OsRng{};
+    let keypair: Keypair = Keypair::generate(&mut csprng);
+    assert!(split(&keypair, 0, 5).err() == Some(Error::InvalidSharesThreshold));
+    assert!(split(&keypair, 6, 5).err() == Some(Error::InvalidSharesThreshold));
+  }
+
+  #[test]
+  fn recover_identity() {
+    let mut csprng = OsRng{};
+    let (mut identity, master, m_keypair) = create();
+    let udi = identity.udi.clone();
+
+    // distribute the master key to trustees and lose it
+    let shares = split(&m_keypair, 3, 5).unwrap();
+    drop(m_keypair);
+
+    // trustees hand back their shares
+    let returned = vec![shares[4].clone(), shares[1].clone(), shares[2].clone()];
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let renew = recover(&returned, &identity, &id_keypair2.public).unwrap();
+    identity.renew(renew).unwrap();
+
+    // evolve identity to the new card (commited in the renew)
+    let card2 = Card::new(false, &id_keypair2, b"No info!", &[master]).unwrap();
+    identity.evolve(card2).unwrap();
+    assert!(identity.is_enabled());
+    assert!(identity.udi == udi);
+  }
+
+  #[test]
+  fn recover_after_cancel() {
+    let mut csprng = OsRng{};
+    let (mut identity, _, m_keypair) = create();
+    let shares = split(&m_keypair, 2, 3).unwrap();
+
+    let cancel = Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap();
+    identity.cancel(cancel).unwrap();
+
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let renew = recover(&shares[1..], &identity, &id_keypair2.public).unwrap();
+    identity.renew(renew).unwrap();
+  }
+}
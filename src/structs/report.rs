@@ -0,0 +1,243 @@
+use serde::{Serialize, Deserialize};
+use ed25519_dalek::PublicKey;
+
+use crate::structs::{Error, ErrorKind, json};
+use crate::structs::identity::{commit, Identity, TLGroup, TLType};
+use crate::structs::stream::Chain;
+
+//-----------------------------------------------------------------------------------------------------------
+// Failure (where and why a verification stopped)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub enum Stage {
+  Genesis,
+  Evolution { index: usize },
+  Registry { id: String, index: usize },
+  Stream { index: usize }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Failure {
+  pub stage: Stage,
+  pub kind: ErrorKind,
+  pub reason: String
+}
+
+impl Failure {
+  pub fn new(stage: Stage, err: &Error) -> Self {
+    Self { stage, kind: err.kind(), reason: err.to_string() }
+  }
+}
+
+// group that authorised a cancel or renew, and the commits of the signers
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Authorisation {
+  pub group: String,
+  pub typ: TLType,
+  pub threshold: usize,
+  pub signers: Vec<String>
+}
+
+impl Authorisation {
+  pub(crate) fn new(group: &TLGroup, signers: &[PublicKey]) -> Self {
+    Self { group: group.commit.clone(), typ: group.typ.clone(), threshold: group.threshold, signers: signers.iter().map(commit).collect() }
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Chain report
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct StreamReport {
+  pub index: usize,
+  pub asi: String,
+  pub key: Option<String>,            // commit of the key that verified the stream
+  pub blocks: usize,
+  pub renew: Option<Authorisation>    // master group of the previous stream that authorised the ExtRenew
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct ChainReport {
+  pub streams: Vec<StreamReport>,
+  pub failure: Option<Failure>
+}
+
+impl ChainReport {
+  pub(crate) fn of(chain: &Chain) -> Self {
+    let streams = chain.streams().iter().enumerate()
+      .map(|(index, st)| StreamReport { index, asi: st.asi.clone(), key: None, blocks: st.blocks.len(), renew: None })
+      .collect();
+
+    Self { streams, failure: None }
+  }
+
+  pub fn is_valid(&self) -> bool {
+    self.failure.is_none()
+  }
+
+  pub fn to_json(&self) -> String {
+    json::to_string(self)
+  }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Identity report
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct CardReport {
+  pub index: usize,
+  pub key: String,
+  pub is_genesis: bool,
+  pub groups: Vec<String>
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct EvolutionReport {
+  pub index: usize,
+  pub cancel: Option<Authorisation>,
+  pub is_close: bool,
+  pub renew: Option<Authorisation>,
+  pub next: Option<String>,    // commit of the renewed key
+  pub card: Option<usize>      // index of the evolved card
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct RegistryReport {
+  pub id: String,
+  pub typ: String,
+  pub entries: usize,
+  pub key_index: usize         // key index of the last entry
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct IdentityReport {
+  pub udi: String,
+  pub enabled: bool,
+  pub cards: Vec<CardReport>,
+  pub evolutions: Vec<EvolutionReport>,
+  pub registries: Vec<RegistryReport>,
+  pub failure: Option<Failure>
+}
+
+impl IdentityReport {
+  pub(crate) fn of(identity: &Identity) -> Self {
+    let cards = identity.cards.iter().enumerate()
+      .map(|(index, card)| CardReport { index, key: commit(card.key()), is_genesis: card.is_genesis, groups: card.groups.keys().cloned().collect() })
+      .collect();
+
+    let evolutions = identity.evols.iter().enumerate()
+      .map(|(index, evol)| EvolutionReport {
+        index,
+        cancel: None,
+        is_close: evol.cancel.as_ref().map(|ev| ev.is_close).unwrap_or(false),
+        renew: None,
+        next: evol.renew.as_ref().map(|ev| ev.commit.clone()),
+        card: None
+      })
+      .collect();
+
+    let mut registries: Vec<RegistryReport> = identity.db.iter()
+      .filter_map(|(id, chain)| chain.last().map(|last| RegistryReport { id: id.clone(), typ: last.typ.clone(), entries: chain.len(), key_index: last.key_index() }))
+      .collect();
+    registries.sort_by(|a, b| a.id.cmp(&b.id));
+
+    Self { udi: identity.udi.clone(), enabled: identity.is_enabled(), cards, evolutions, registries, failure: None }
+  }
+
+  pub fn is_valid(&self) -> bool {
+    self.failure.is_none()
+  }
+
+  pub fn to_json(&self) -> String {
+    json::to_string(self)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::OType;
+  use crate::structs::identity::*;
+  use crate::structs::stream::*;
+
+  use rand::rngs::OsRng;
+  use ed25519_dalek::Keypair;
+
+  #[test]
+  fn chain_report() {
+    let mut csprng = OsRng{};
+    let keypair1: Keypair = Keypair::generate(&mut csprng);
+    let keypair2: Keypair = Keypair::generate(&mut csprng);
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let mut stream1 = Stream::new(&keypair1, "udi-random", "r1-random", std::slice::from_ref(&master), genesis, None).unwrap();
+    let block = StreamBlock::new(&keypair1, Record { oper: OType::SET, info: b"New info!".to_vec() }, stream1.hash()).unwrap();
+    stream1.save(block).unwrap();
+
+    let ext_renew = ExtRenew::new(Renew::new(&m_keypair, &keypair2.public, stream1.prev(), true).unwrap(), &keypair1.public);
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let stream2 = Stream::new(&keypair2, "udi-random", "r2-random", &[], genesis, Some(ext_renew)).unwrap();
+
+    let mut chain = Chain::new(stream1);
+    chain.save(stream2).unwrap();
+
+    let report = chain.report(&keypair2.public);
+    assert!(report.is_valid());
+    assert!(report.streams[0].blocks == 1 && report.streams[0].key == Some(commit(&keypair1.public)));
+    assert!(report.streams[0].renew.is_none());
+    let renew = report.streams[1].renew.as_ref().unwrap();
+    assert!(renew.group == master.commit && renew.signers == vec![commit(&m_keypair.public)]);
+
+    // the failure has the stream index and reason
+    let report = chain.report(&keypair1.public);
+    let failure = report.failure.as_ref().unwrap();
+    assert!(failure.stage == Stage::Stream { index: 1 } && failure.kind == ErrorKind::Signature);
+    assert!(failure.reason == "Invalid genesis signature!");
+    assert!(report.streams[1].key.is_none());
+
+    let loaded: ChainReport = json::from_str(&report.to_json(), "report").unwrap();
+    assert!(loaded == report);
+  }
+
+  #[test]
+  fn identity_report() {
+    let mut csprng = OsRng{};
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+    let id_keypair: Keypair = Keypair::generate(&mut csprng);
+    let genesis = Card::new(true, &id_keypair, b"No important info!", std::slice::from_ref(&master)).unwrap();
+    let mut identity = Identity::new(genesis).unwrap();
+    identity.append(&id_keypair, "idp.io/test", "test", OType::SET, b"Not important!").unwrap();
+
+    let id_keypair2: Keypair = Keypair::generate(&mut csprng);
+    let cancel = Cancel::new(false, &m_keypair, identity.prev().unwrap()).unwrap();
+    identity.cancel(cancel).unwrap();
+    let renew = Renew::new(&m_keypair, &id_keypair2.public, identity.prev().unwrap(), false).unwrap();
+    identity.renew(renew).unwrap();
+    identity.evolve(Card::new(false, &id_keypair2, b"No important info!", std::slice::from_ref(&master)).unwrap()).unwrap();
+    identity.append(&id_keypair2, "idp.io/test", "test", OType::SET, b"New info!").unwrap();
+
+    let report = identity.report();
+    assert!(report.is_valid() && report.enabled);
+    assert!(report.cards.len() == 2 && report.cards[1].key == commit(&id_keypair2.public));
+
+    let evol = &report.evolutions[0];
+    assert!(evol.cancel.as_ref().unwrap().group == master.commit);
+    assert!(evol.renew.as_ref().unwrap().typ == TLType::MASTER);
+    assert!(evol.next == Some(commit(&id_keypair2.public)) && evol.card == Some(1));
+    assert!(report.registries[0].entries == 3 && report.registries[0].key_index == 1);
+
+    // tampered registry entry
+    let chain = identity.db.get_mut("idp.io/test").unwrap();
+    chain[2].info = b"Other info!".to_vec();
+    let report = identity.report();
+    let failure = report.failure.as_ref().unwrap();
+    assert!(failure.stage == Stage::Registry { id: "idp.io/test".into(), index: 2 });
+    assert!(identity.verify_full() == Err(Error::InvalidRegistry { id: "idp.io/test".into() }));
+
+    let loaded: IdentityReport = json::from_str(&report.to_json(), "report").unwrap();
+    assert!(loaded == report);
+  }
+}
@@ -0,0 +1,190 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::thread;
+
+use ed25519_dalek::{Keypair, PublicKey, Signature, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+
+use crate::structs::{Result, Error};
+
+//-----------------------------------------------------------------------------------------------------------
+// Signer (the private key may live outside of the application process)
+//-----------------------------------------------------------------------------------------------------------
+pub trait Signer {
+  fn public(&self) -> PublicKey;
+  fn sign(&self, data: &[u8]) -> Result<Signature>;
+}
+
+// in-process software signer
+impl Signer for Keypair {
+  fn public(&self) -> PublicKey {
+    self.public
+  }
+
+  fn sign(&self, data: &[u8]) -> Result<Signature> {
+    Ok(Keypair::sign(self, data))
+  }
+}
+
+fn io(err: std::io::Error) -> Error {
+  Error::Signer { reason: err.to_string() }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Mock remote token (signs over a local socket)
+//   connect: token -> public key (32 bytes)
+//   request: data length (u32) + data -> signature (64 bytes)
+//-----------------------------------------------------------------------------------------------------------
+pub struct MockToken {
+  addr: SocketAddr
+}
+
+impl MockToken {
+  pub fn start(keypair: Keypair) -> Result<Self> {
+    let listener = TcpListener::bind("127.0.0.1:0").map_err(io)?;
+    let addr = listener.local_addr().map_err(io)?;
+
+    thread::spawn(move || {
+      // a failed connection doesn't stop the token
+      for mut conn in listener.incoming().flatten() {
+        let _ = Self::serve(&keypair, &mut conn);
+      }
+    });
+
+    Ok(Self { addr })
+  }
+
+  pub fn addr(&self) -> SocketAddr {
+    self.addr
+  }
+
+  fn serve(keypair: &Keypair, conn: &mut TcpStream) -> std::io::Result<()> {
+    conn.write_all(keypair.public.as_bytes())?;
+    loop {
+      let mut len = [0u8; 4];
+      conn.read_exact(&mut len)?;
+
+      let mut data = vec![0u8; u32::from_le_bytes(len) as usize];
+      conn.read_exact(&mut data)?;
+      conn.write_all(&keypair.sign(&data).to_bytes())?;
+    }
+  }
+}
+
+pub struct RemoteSigner {
+  public: PublicKey,
+  conn: Mutex<TcpStream>
+}
+
+impl RemoteSigner {
+  pub fn connect(addr: &SocketAddr) -> Result<Self> {
+    let mut conn = TcpStream::connect(addr).map_err(io)?;
+
+    let mut key = [0u8; PUBLIC_KEY_LENGTH];
+    conn.read_exact(&mut key).map_err(io)?;
+    let public = PublicKey::from_bytes(&key).map_err(|_| Error::Signer { reason: "Invalid token public key".into() })?;
+
+    Ok(Self { public, conn: Mutex::new(conn) })
+  }
+}
+
+impl Signer for RemoteSigner {
+  fn public(&self) -> PublicKey {
+    self.public
+  }
+
+  // structures can't be built without the token, same as a missing smart card
+  fn sign(&self, data: &[u8]) -> Result<Signature> {
+    let mut conn = self.conn.lock().map_err(|_| Error::Signer { reason: "Token connection poisoned".into() })?;
+    conn.write_all(&(data.len() as u32).to_le_bytes()).map_err(io)?;
+    conn.write_all(data).map_err(io)?;
+
+    let mut sig = [0u8; SIGNATURE_LENGTH];
+    conn.read_exact(&mut sig).map_err(io)?;
+    let sig = Signature::from_bytes(&sig).map_err(|_| Error::Signer { reason: "Invalid token signature".into() })?;
+
+    // never trust the token output
+    self.public.verify(data, &sig).map_err(|_| Error::Signer { reason: "Token signature doesn't verify".into() })?;
+    Ok(sig)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::structs::OType;
+  use crate::structs::identity::*;
+  use crate::structs::anchor::*;
+  use crate::structs::stream::*;
+
+  use rand::rngs::OsRng;
+
+  #[test]
+  fn software_signer() {
+    let mut csprng = OsRng{};
+    let keypair: Keypair = Keypair::generate(&mut csprng);
+
+    let signer: &dyn Signer = &keypair;
+    let sig = signer.sign(b"data").unwrap();
+    assert!(signer.public().verify(b"data", &sig).is_ok());
+  }
+
+  #[test]
+  fn remote_signer() {
+    let mut csprng = OsRng{};
+
+    // keys live in the token (moved to the token thread)
+    let m_token = MockToken::start(Keypair::generate(&mut csprng)).unwrap();
+    let id_token = MockToken::start(Keypair::generate(&mut csprng)).unwrap();
+    let profile_token = MockToken::start(Keypair::generate(&mut csprng)).unwrap();
+
+    let m_signer = RemoteSigner::connect(&m_token.addr()).unwrap();
+    let id_signer = RemoteSigner::connect(&id_token.addr()).unwrap();
+    let profile_signer = RemoteSigner::connect(&profile_token.addr()).unwrap();
+
+    // identity
+    let master = TLGroup::new(TLType::MASTER, &m_signer.public());
+    let genesis = Card::new(true, &id_signer, b"No important info!", &[master]).unwrap();
+    let mut identity = Identity::new(genesis).unwrap();
+
+    // anchor and registry
+    let anchor = Anchor::new(&profile_signer, &identity.udi, "some-random", 0).unwrap();
+    let reg = Registry::new(&id_signer, "raiap.io/test", "anchor", OType::SET, &anchor.to_bytes(), identity.prev().unwrap(), 0).unwrap();
+    identity.save(reg).unwrap();
+
+    // stream
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let mut stream = Stream::new(&profile_signer, &identity.udi, "some-random", &[], genesis, None).unwrap();
+    let record = Record { oper: OType::SET, info: b"New info!".to_vec() };
+    let block = StreamBlock::new(&profile_signer, record, stream.hash()).unwrap();
+    stream.save(block).unwrap();
+    stream.verify_stream(&profile_signer.public()).unwrap();
+
+    // cancel with the master token
+    let cancel = Cancel::new(false, &m_signer, identity.prev().unwrap()).unwrap();
+    identity.cancel(cancel).unwrap();
+    identity.verify_full().unwrap();
+  }
+
+  #[test]
+  fn remote_signer_failure() {
+    let mut csprng = OsRng{};
+    let keypair: Keypair = Keypair::generate(&mut csprng);
+
+    // the token sends its key and disconnects
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let public = keypair.public;
+    let token = thread::spawn(move || {
+      let (mut conn, _) = listener.accept().unwrap();
+      conn.write_all(public.as_bytes()).unwrap();
+    });
+
+    let signer = RemoteSigner::connect(&addr).unwrap();
+    token.join().unwrap();
+
+    // the failure is returned instead of a panic
+    let err = Card::new(true, &signer, b"No important info!", &[]).err().unwrap();
+    assert!(matches!(err, Error::Signer { .. }));
+  }
+}
@@ -1,13 +1,18 @@
 use std::collections::BTreeMap;
 
-use serde::{Serialize, Deserialize};
-use ed25519_dalek::{Keypair, PublicKey, Signature};
+use serde::{Serialize, Deserialize, Deserializer, de};
+use ed25519_dalek::{PublicKey, Signature};
 
 use sha2::{Sha256, Digest};
 use base64::encode;
 
 use crate::structs::identity::*;
-use crate::structs::{Result, OType};
+use crate::structs::{Result, Error, OType, State, Hash};
+use crate::structs::signer::Signer;
+use crate::structs::payload::{self, Payload, Format, Migration};
+use crate::structs::{json, cbor};
+use crate::structs::report::{ChainReport, Failure, Stage, Authorisation};
+use crate::structs::merkle::{Accumulator, Checkpoint, InclusionProof, ConsistencyProof};
 
 pub fn asi(key: &PublicKey, sig: &Signature) -> String {
   let mut hasher = Sha256::new();
@@ -24,9 +29,16 @@ pub fn asi(key: &PublicKey, sig: &Signature) -> String {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ExtRenew {
   renew: Renew,
+  #[serde(with = "crate::structs::json::b64")]
   key: PublicKey
 }
 
+impl ExtRenew {
+  pub fn new(renew: Renew, key: &PublicKey) -> Self {
+    Self { renew, key: *key }
+  }
+}
+
 //-----------------------------------------------------------------------------------------------------------
 // Stream (also represents stream genesis)
 //-----------------------------------------------------------------------------------------------------------
@@ -36,63 +48,172 @@ pub struct Stream {
   pub groups: BTreeMap<String, TLGroup>,
   pub genesis: Record,
   pub renew: Option<ExtRenew>,
+  #[serde(with = "crate::structs::json::b64")]
   pub sig: Signature,
   
-  pub blocks: Vec<StreamBlock>
+  pub blocks: Vec<StreamBlock>,
+  acc: Accumulator  // merkle accumulator of the block hashes
 }
 
 impl Stream {
-  pub fn new(keypair: &Keypair, udi: &str, r: &str, groups: &[TLGroup], genesis: Record, renew: Option<ExtRenew>) -> Self {
+  pub fn new(signer: &dyn Signer, udi: &str, r: &str, groups: &[TLGroup], genesis: Record, renew: Option<ExtRenew>) -> Result<Self> {
+    if genesis.oper == OType::REANCHOR {
+      return Err(Error::InvalidStreamOper { index: 0 })
+    }
+
     let mut g_map = BTreeMap::<String, TLGroup>::new();
-    for gr in groups.into_iter() {
+    for gr in groups.iter() {
       g_map.insert(gr.commit.clone(), gr.clone());
     }
 
-    let sig_data = Self::asi_data(udi, r);
-    let sig = keypair.sign(&sig_data);
-    let asi = asi(&keypair.public, &sig);
+    let sig = signer.sign(&Self::asi_data(Format::CURRENT, udi, r))?;
+    let asi = asi(&signer.public(), &sig);
 
-    let sig_data = Self::data(&asi, &g_map, &genesis, &renew);
-    let sig = keypair.sign(&sig_data);
+    let sig_data = Self::data(Format::CURRENT, &asi, &g_map, &genesis, &renew);
+    let sig = signer.sign(&sig_data)?;
 
-    Self { asi, groups: g_map, genesis, sig, blocks: Vec::new(), renew }
+    Ok(Self { asi, groups: g_map, genesis, sig, blocks: Vec::new(), renew, acc: Accumulator::new() })
   }
 
-  pub fn prev(&self) -> &Signature {
+  pub fn hash(&self) -> Hash {
+    Hash::content(payload::STREAM, self, &["sig", "blocks", "acc"])
+  }
+
+  // the stream genesis, without blocks
+  pub fn without_blocks(&self) -> Self {
+    Self { blocks: Vec::new(), acc: Accumulator::new(), ..self.clone() }
+  }
+
+  // hash that the next block must reference
+  pub fn prev(&self) -> Hash {
     match self.blocks.last() {
-      None => &self.sig,
-      Some(bl) => &bl.sig
+      None => self.hash(),
+      Some(bl) => bl.hash()
     }
   }
 
   pub fn save(&mut self, block: StreamBlock) -> Result<()> {
-    let sig = self.prev();
-    if block.prev != *sig {
-      return Err("Invalid stream chain!".into())
+    let prev = self.prev();
+    if block.prev != prev {
+      return Err(Error::InvalidBlockChain { index: self.blocks.len(), expected: prev, found: block.prev })
+    }
+
+    // a re-anchor only exists in registries
+    if block.record.oper == OType::REANCHOR {
+      return Err(Error::InvalidStreamOper { index: self.blocks.len() + 1 })
     }
 
+    // the accumulator of a received stream may not cover its blocks
+    if self.acc.size() != self.blocks.len() {
+      return Err(Error::InvalidAccumulator)
+    }
+
+    self.acc.push(&block.hash())?;
     self.blocks.push(block);
     Ok(())
   }
 
+  pub fn accumulator(&self) -> &Accumulator {
+    &self.acc
+  }
+
+  pub fn checkpoint(&self, signer: &dyn Signer) -> Result<Checkpoint> {
+    Checkpoint::new(signer, self)
+  }
+
+  // inclusion of block index in the current tree
+  pub fn prove(&self, index: usize) -> Option<InclusionProof> {
+    let hashes: Vec<Hash> = self.blocks.iter().map(|bl| bl.hash()).collect();
+    InclusionProof::new(index, &hashes)
+  }
+
+  // the tree of the first blocks is a prefix of the current tree
+  pub fn prove_consistency(&self, first: usize) -> Option<ConsistencyProof> {
+    let hashes: Vec<Hash> = self.blocks.iter().map(|bl| bl.hash()).collect();
+    ConsistencyProof::new(first, &hashes)
+  }
+
+  // hash links of the blocks and the accumulator over them, no signature is verified
+  pub fn verify_links(&self) -> Result<()> {
+    let mut prev = self.hash();
+    let mut acc = Accumulator::new();
+    for (index, bl) in self.blocks.iter().enumerate() {
+      if bl.prev != prev {
+        return Err(Error::InvalidBlockChain { index, expected: prev, found: bl.prev })
+      }
+
+      prev = bl.hash();
+      acc.push(&prev)?;
+    }
+
+    match acc == self.acc {
+      true => Ok(()),
+      false => Err(Error::InvalidAccumulator)
+    }
+  }
+
+  // record n of the stream (0 is the genesis) with the signature that covers it
+  pub fn entry(&self, n: usize) -> Option<StreamEntry<'_>> {
+    match n {
+      0 => Some(StreamEntry { record: &self.genesis, sig: &self.sig }),
+      _ => self.blocks.get(n - 1).map(|bl| StreamEntry { record: &bl.record, sig: &bl.sig })
+    }
+  }
+
+  // current value of the stream after applying SET/DEL in order
+  pub fn state(&self) -> State<StreamEntry<'_>> {
+    self.records(self.blocks.len())
+  }
+
+  // value of the stream as of record n (inclusive), None if the stream has no record n
+  pub fn state_at(&self, n: usize) -> Option<State<StreamEntry<'_>>> {
+    match n <= self.blocks.len() {
+      true => Some(self.records(n)),
+      false => None
+    }
+  }
+
+  fn records(&self, n: usize) -> State<StreamEntry<'_>> {
+    let entries = (0..=n).filter_map(|index| self.entry(index).map(|entry| (index, entry)));
+    State::of(entries.map(|(index, entry)| (index, &entry.record.oper, entry)))
+  }
+
+  // proof of ownership with the ASI signature (see asi_signature)
   pub fn check_asi(&self, udi: &str, r: &str, key: &PublicKey, sig: &Signature) -> bool {
+    self.check_asi_as(udi, r, key, sig, Format::CURRENT)
+  }
+
+  pub fn asi_signature(signer: &dyn Signer, udi: &str, r: &str) -> Result<Signature> {
+    signer.sign(&Self::asi_data(Format::CURRENT, udi, r))
+  }
+
+  pub fn check_asi_as(&self, udi: &str, r: &str, key: &PublicKey, sig: &Signature, format: Format) -> bool {
     let asi = asi(key, sig);
     if asi != self.asi {
       return false
     }
 
-    let sig_data = Self::asi_data(udi, r);
+    let sig_data = Self::asi_data(format, udi, r);
     key.verify(&sig_data, sig).is_ok()
   }
 
   pub fn verify_stream(&self, key: &PublicKey) -> Result<()> {
-    if !self.verify(key) {
-      return Err("Invalid genesis signature!".into())
+    self.verify_stream_with(key, Migration::current())
+  }
+
+  fn verify_stream_with(&self, key: &PublicKey, mut migration: Migration) -> Result<()> {
+    let mut records = std::iter::once(&self.genesis).chain(self.blocks.iter().map(|bl| &bl.record));
+    if let Some(index) = records.position(|record| record.oper == OType::REANCHOR) {
+      return Err(Error::InvalidStreamOper { index })
     }
 
-    for bl in self.blocks.iter() {
-      if !bl.verify(key) {
-        return Err("Invalid block signature!".into())
+    if migration.verify(|format| self.verify_as(key, format)).is_none() {
+      return Err(Error::InvalidStreamGenesis)
+    }
+
+    for (index, bl) in self.blocks.iter().enumerate() {
+      if migration.verify(|format| bl.verify_as(key, format)).is_none() {
+        return Err(Error::InvalidBlock { index })
       }
     }
 
@@ -100,30 +221,28 @@ impl Stream {
   }
 
   pub fn verify(&self, key: &PublicKey) -> bool {
-    let sig_data = Self::data(&self.asi, &self.groups, &self.genesis, &self.renew);
-    key.verify(&sig_data, &self.sig).is_ok()
+    self.verify_as(key, Format::CURRENT)
   }
 
-  fn data(asi: &str, groups: &BTreeMap<String, TLGroup>, genesis: &Record, renew: &Option<ExtRenew>) -> Vec<u8> {
-    let mut data = Vec::<u8>::new();
-
-    // These unwrap() should never fail, or it's a serious code bug!
-    data.extend(bincode::serialize(asi).unwrap());
-    data.extend(bincode::serialize(groups).unwrap());
-    data.extend(bincode::serialize(genesis).unwrap());
-    data.extend(bincode::serialize(renew).unwrap());
-    
-    data
+  fn verify_as(&self, key: &PublicKey, format: Format) -> bool {
+    let sig_data = Self::data(format, &self.asi, &self.groups, &self.genesis, &self.renew);
+    key.verify(&sig_data, &self.sig).is_ok()
   }
 
-  fn asi_data(udi: &str, r: &str) -> Vec<u8> {
-    let mut data = Vec::<u8>::new();
+  pub(crate) fn data(format: Format, asi: &str, groups: &BTreeMap<String, TLGroup>, genesis: &Record, renew: &Option<ExtRenew>) -> Vec<u8> {
+    Payload::new(payload::STREAM, format)
+      .field(asi)
+      .field(groups)
+      .field(genesis)
+      .field(renew)
+      .into_bytes()
+  }
 
-    // These unwrap() should never fail, or it's a serious code bug!
-    data.extend(bincode::serialize(udi).unwrap());
-    data.extend(bincode::serialize(r).unwrap());
-    
-    data
+  pub(crate) fn asi_data(format: Format, udi: &str, r: &str) -> Vec<u8> {
+    Payload::new(payload::STREAM_ASI, format)
+      .field(udi)
+      .field(r)
+      .into_bytes()
   }
 }
 //-----------------------------------------------------------------------------------------------------------
@@ -132,47 +251,86 @@ impl Stream {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Record {
   pub oper: OType,
+  #[serde(with = "crate::structs::json::b64")]
   pub info: Vec<u8>
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct StreamEntry<'a> {
+  pub record: &'a Record,
+  pub sig: &'a Signature
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct StreamBlock {
   pub record: Record,
-  pub prev: Signature,
+  pub prev: Hash,
+  #[serde(with = "crate::structs::json::b64")]
   pub sig: Signature
 }
 
 impl StreamBlock {
-  pub fn new(keypair: &Keypair, record: Record, prev: &Signature) -> Self {
-    let sig_data = Self::data(&record, &prev);
-    let sig = keypair.sign(&sig_data);
+  pub fn new(signer: &dyn Signer, record: Record, prev: Hash) -> Result<Self> {
+    let sig_data = Self::data(Format::CURRENT, &record, &prev);
+    let sig = signer.sign(&sig_data)?;
+
+    Ok(Self { record, prev, sig })
+  }
+
+  pub fn hash(&self) -> Hash {
+    Hash::content(payload::STREAM_BLOCK, self, &["sig"])
+  }
+
+  pub fn to_compact(&self) -> String {
+    json::to_compact(json::STREAM_BLOCK_COMPACT, self)
+  }
 
-    Self { record, prev: prev.clone(), sig }
+  pub fn from_compact(compact: &str) -> Result<Self> {
+    json::from_compact(json::STREAM_BLOCK_COMPACT, compact, "stream block")
   }
 
   pub fn verify(&self, key: &PublicKey) -> bool {
-    let sig_data = Self::data(&self.record, &self.prev);
-    key.verify(&sig_data, &self.sig).is_ok()
+    self.verify_as(key, Format::CURRENT)
   }
 
-  fn data(record: &Record, prev: &Signature) -> Vec<u8> {
-    let mut data = Vec::<u8>::new();
+  fn verify_as(&self, key: &PublicKey, format: Format) -> bool {
+    let sig_data = Self::data(format, &self.record, &self.prev);
+    key.verify(&sig_data, &self.sig).is_ok()
+  }
 
-    // These unwrap() should never fail, or it's a serious code bug!    
-    data.extend(bincode::serialize(record).unwrap());
-    data.extend(bincode::serialize(prev).unwrap());
-    
-    data
+  pub(crate) fn data(format: Format, record: &Record, prev: &Hash) -> Vec<u8> {
+    Payload::new(payload::STREAM_BLOCK, format)
+      .field(record)
+      .field(prev)
+      .into_bytes()
   }
 }
 
 //-----------------------------------------------------------------------------------------------------------
 // Stream Chain
 //-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Debug, Clone)]
 pub struct Chain {
   chain: Vec<Stream>
 }
 
+// a chain always has a stream, an empty one is rejected when decoded
+impl<'de> Deserialize<'de> for Chain {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+    #[derive(Deserialize)]
+    struct Streams {
+      chain: Vec<Stream>
+    }
+
+    let streams = Streams::deserialize(deserializer)?;
+    if streams.chain.is_empty() {
+      return Err(de::Error::custom(Error::ChainInvalidEnd))
+    }
+
+    Ok(Self { chain: streams.chain })
+  }
+}
+
 impl Chain {
   pub fn new(genesis: Stream) -> Self {
     Self { chain: vec![genesis] }
@@ -182,81 +340,187 @@ impl Chain {
     self.chain.last().unwrap()
   }
 
+  pub fn streams(&self) -> &[Stream] {
+    &self.chain
+  }
+
+  pub fn iter(&self) -> std::slice::Iter<'_, Stream> {
+    self.chain.iter()
+  }
+
+  // number of streams (never empty)
+  pub fn len(&self) -> usize {
+    self.chain.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.chain.is_empty()
+  }
+
+  pub fn get(&self, asi: &str) -> Option<&Stream> {
+    self.chain.iter().find(|st| st.asi == asi)
+  }
+
+  pub fn position(&self, asi: &str) -> Option<usize> {
+    self.chain.iter().position(|st| st.asi == asi)
+  }
+
+  // total number of blocks of all streams
+  pub fn blocks(&self) -> usize {
+    self.chain.iter().map(|st| st.blocks.len()).sum()
+  }
+
+  // up to limit streams starting at offset
+  pub fn page(&self, offset: usize, limit: usize) -> ChainPage {
+    let start = offset.min(self.chain.len());
+    let end = start.saturating_add(limit).min(self.chain.len());
+    ChainPage { offset: start, total: self.chain.len(), streams: self.chain[start..end].to_vec() }
+  }
+
+  // join consecutive pages and re-verify the chain with the current stream key
+  pub fn from_pages(pages: Vec<ChainPage>, key: &PublicKey) -> Result<Self> {
+    let total = pages.first().map(|pg| pg.total).unwrap_or(0);
+    // the total comes from the peer, the streams are only allocated as they arrive
+    let mut chain = Vec::<Stream>::new();
+    for pg in pages.into_iter() {
+      if pg.offset != chain.len() || pg.total != total || pg.streams.is_empty() || pg.streams.len() > total - chain.len() {
+        return Err(Error::InvalidPage { offset: pg.offset })
+      }
+
+      chain.extend(pg.streams);
+    }
+
+    if chain.len() != total {
+      return Err(Error::InvalidPage { offset: chain.len() })
+    }
+
+    Self::verified(Self { chain }, key)
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    cbor::to_vec(self)
+  }
+
+  // load a chain received from a peer, only returned if it verifies with the current stream key
+  pub fn from_bytes(bytes: &[u8], key: &PublicKey) -> Result<Self> {
+    Self::verified(cbor::from_slice(bytes, "chain")?, key)
+  }
+
+  fn verified(chain: Self, key: &PublicKey) -> Result<Self> {
+    if chain.chain.is_empty() {
+      return Err(Error::ChainInvalidEnd)
+    }
+
+    chain.check(key)?;
+    Ok(chain)
+  }
+
   pub fn save(&mut self, stream: Stream) -> Result<()> {
-    let srenew = stream.renew.as_ref().ok_or("Stream requires a renew block!")?;
+    let srenew = stream.renew.as_ref().ok_or(Error::MissingStreamRenew)?;
 
     // verify current stream with renew stream key
     let st = self.current();
     st.verify_stream(&srenew.key)?;
 
-    let mkey = srenew.renew.key.ok_or("Renew block requires a master public key!")?;
-    let mcommit = commit(&mkey);
+    let mkey = srenew.renew.key.ok_or(Error::MissingRenewKey)?;
 
     // verify renew signature with master key
     if !srenew.renew.verify(&mkey) {
-      return Err("Invalid renew!".into())
+      return Err(Error::InvalidRenew)
     }
 
     // check if group commit is correct
-    if !st.groups.contains_key(&mcommit) {
-      return Err("No group found on previous stream!".into())
+    let index = self.chain.len() - 1;
+    if authorize(&st.groups, &srenew.renew.signers(&mkey)).is_err() {
+      return Err(Error::NoStreamGroup { index })
     }
 
     // check chain
-    if &srenew.renew.prev != st.prev() {
-      return Err("Invalid stream chain!".into())
+    if srenew.renew.prev != st.prev() {
+      return Err(Error::InvalidStreamChain { index, expected: st.prev(), found: srenew.renew.prev })
     }
 
     self.chain.push(stream);
     Ok(())
   }
 
+  // hash links of all streams and renews, no signature is verified
+  pub fn verify_links(&self) -> Result<()> {
+    for (index, st) in self.chain.iter().enumerate() {
+      st.verify_links()?;
+
+      if let Some(next) = self.chain.get(index + 1) {
+        let srenew = next.renew.as_ref().ok_or(Error::MissingStreamRenew)?;
+        if srenew.renew.prev != st.prev() {
+          return Err(Error::InvalidStreamChain { index, expected: st.prev(), found: srenew.renew.prev })
+        }
+      }
+    }
+
+    Ok(())
+  }
+
   pub fn check(&self, key: &PublicKey) -> Result<()> {
-    let mut mcommit: Option<String> = None;
-    let mut prev: Option<&Signature> = None;
+    self.audit(key, &mut ChainReport::of(self)).map_err(|(_, err)| err)
+  }
+
+  // verification report of every stream (stops at the first failure)
+  pub fn report(&self, key: &PublicKey) -> ChainReport {
+    let mut report = ChainReport::of(self);
+    if let Err((index, err)) = self.audit(key, &mut report) {
+      report.failure = Some(Failure::new(Stage::Stream { index }, &err));
+    }
+
+    report
+  }
+
+  // errors with the index of the failing stream
+  fn audit(&self, key: &PublicKey, report: &mut ChainReport) -> std::result::Result<(), (usize, Error)> {
+    let mut signers: Option<Vec<PublicKey>> = None;
+    let mut prev: Option<Hash> = None;
     let mut skey = Some(key);
-    for st in self.chain.iter().rev() {
-      if skey.is_none() {
-        return Err("Chain contains more streams without a stream key!".into())
-      }
+    for (index, st) in self.chain.iter().enumerate().rev() {
+      let at = |err| (index, err);
+      let st_key = skey.ok_or(at(Error::ChainWithoutKey { index }))?;
 
       // check if group commit is correct
-      if let Some(commit) = mcommit.as_ref() {
-        if !st.groups.contains_key(commit) {
-          return Err("No group found on previous stream!".into())
-        }
+      if let Some(signers) = signers.as_ref() {
+        let group = authorize(&st.groups, signers).map_err(|_| at(Error::NoStreamGroup { index }))?;
+        report.streams[index + 1].renew = Some(Authorisation::new(group, signers));
       }
 
       // check chain
       if let Some(prev) = prev {
         if prev != st.prev() {
-         return Err("Invalid stream chain!".into())
+         return Err(at(Error::InvalidStreamChain { index, expected: st.prev(), found: prev }))
         }
       }
 
-      // verify stream with stream key
-      st.verify_stream(skey.unwrap())?;
+      // verify links and then the stream with stream key
+      st.verify_links().map_err(at)?;
+      st.verify_stream(st_key).map_err(at)?;
+      report.streams[index].key = Some(commit(st_key));
 
       skey = match st.renew.as_ref() {
         None => None,
         Some(ext_renew) => {
           let srenew = &ext_renew.renew;
-          let mkey = srenew.key.ok_or("Renew block requires a master public key!")?;
+          let mkey = srenew.key.ok_or(at(Error::MissingRenewKey))?;
 
           // verify renew signature with master key
           if !srenew.verify(&mkey) {
-            return Err("Invalid renew!".into())
+            return Err(at(Error::InvalidRenew))
           }
 
-          mcommit = Some(commit(&mkey));
-          prev = Some(&srenew.prev);
+          signers = Some(srenew.signers(&mkey));
+          prev = Some(srenew.prev);
           Some(&ext_renew.key)
         }
       };
     }
 
     if skey.is_some() {
-      return Err("Chain with invalid end!".into())
+      return Err((0, Error::ChainInvalidEnd))
     }
 
     Ok(())
@@ -264,9 +528,27 @@ impl Chain {
 }
 
 
+impl<'a> IntoIterator for &'a Chain {
+  type Item = &'a Stream;
+  type IntoIter = std::slice::Iter<'a, Stream>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.chain.iter()
+  }
+}
+
+// a page of streams of a chain (to ship chains with many streams)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChainPage {
+  pub offset: usize,
+  pub total: usize,
+  pub streams: Vec<Stream>
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::structs::ErrorKind;
   use crate::structs::anchor::*;
 
   use rand::rngs::OsRng;
@@ -279,23 +561,141 @@ mod tests {
     let r = "r-random";
     let mut csprng = OsRng{};
     let profile_keypair: Keypair = Keypair::generate(&mut csprng);
-    let anchor = Anchor::new(&profile_keypair, udi, r, 0);
+    let anchor = Anchor::new(&profile_keypair, udi, r, 0).unwrap();
 
     // create stream
     let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
-    let mut stream = Stream::new(&profile_keypair, udi, r, &vec![], genesis, None);
+    let mut stream = Stream::new(&profile_keypair, udi, r, &[], genesis, None).unwrap();
   
     // add block to stream
     let record = Record { oper: OType::SET, info: b"New info!".to_vec() };
-    let block = StreamBlock::new(&profile_keypair, record, &stream.sig);
+    let block = StreamBlock::new(&profile_keypair, record, stream.hash()).unwrap();
     stream.save(block).unwrap();
 
     // check if the stream is valid with the public key (verify all signatures)
     stream.verify_stream(&profile_keypair.public).unwrap();
 
-    // check if ASI is connected to the anchor AL ?
-    let al_sig = anchor.al_signature(&profile_keypair, udi);
-    assert!(stream.check_asi(udi, r, &profile_keypair.public, &al_sig));
+    // check if ASI is connected to the anchor
+    let asi_sig = Stream::asi_signature(&profile_keypair, udi, &anchor.r).unwrap();
+    assert!(stream.check_asi(udi, r, &profile_keypair.public, &asi_sig));
+
+    // the AL signature can't be replayed as the ASI signature
+    let al_sig = anchor.al_signature(&profile_keypair, udi).unwrap();
+    assert!(!stream.check_asi(udi, r, &profile_keypair.public, &al_sig));
+  }
+
+  #[test]
+  fn stream_state() {
+    let mut csprng = OsRng{};
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let mut stream = Stream::new(&profile_keypair, "udi-random", "r-random", &[], genesis, None).unwrap();
+    assert!(stream.state().value().unwrap().record.info == b"Not important!".to_vec());
+
+    let block1 = StreamBlock::new(&profile_keypair, Record { oper: OType::SET, info: b"New info!".to_vec() }, stream.hash()).unwrap();
+    stream.save(block1.clone()).unwrap();
+    let block2 = StreamBlock::new(&profile_keypair, Record { oper: OType::DEL, info: Vec::new() }, block1.hash()).unwrap();
+    stream.save(block2.clone()).unwrap();
+
+    // deleted with the signed deletion block
+    match stream.state() {
+      State::Deleted { index, entry } => assert!(index == 2 && *entry.sig == block2.sig),
+      _ => panic!("stream must be deleted")
+    }
+
+    // history (the genesis is signed by the stream signature)
+    let state = stream.state_at(0).unwrap();
+    assert!(*state.value().unwrap().sig == stream.sig);
+    assert!(stream.state_at(1).unwrap().value().unwrap().record.info == b"New info!".to_vec());
+    assert!(stream.entry(3).is_none());
+    assert!(stream.state_at(3).is_none());
+  }
+
+  #[test]
+  fn invalid_block_chain() {
+    let mut csprng = OsRng{};
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let mut stream = Stream::new(&profile_keypair, "udi-random", "r-random", &[], genesis, None).unwrap();
+
+    let record = Record { oper: OType::SET, info: b"New info!".to_vec() };
+    let block = StreamBlock::new(&profile_keypair, record.clone(), stream.hash()).unwrap();
+    stream.save(block.clone()).unwrap();
+
+    // second block pointing to the genesis instead of the first block
+    let block2 = StreamBlock::new(&profile_keypair, record, stream.hash()).unwrap();
+    let err = stream.save(block2).unwrap_err();
+    assert!(err == Error::InvalidBlockChain { index: 1, expected: block.hash(), found: stream.hash() });
+    assert!(err.kind() == ErrorKind::Chain);
+  }
+
+  #[test]
+  fn reanchor_record() {
+    let mut csprng = OsRng{};
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+
+    let reanchor = Record { oper: OType::REANCHOR, info: b"Not important!".to_vec() };
+    let err = Stream::new(&profile_keypair, "udi-random", "r-random", &[], reanchor.clone(), None).unwrap_err();
+    assert!(err == Error::InvalidStreamOper { index: 0 });
+
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let mut stream = Stream::new(&profile_keypair, "udi-random", "r-random", &[], genesis, None).unwrap();
+    let block = StreamBlock::new(&profile_keypair, reanchor, stream.prev()).unwrap();
+    assert!(stream.save(block.clone()) == Err(Error::InvalidStreamOper { index: 1 }));
+
+    // a received stream with a signed re-anchor block
+    let mut received = stream.clone();
+    received.blocks.push(block);
+    assert!(received.verify_stream(&profile_keypair.public) == Err(Error::InvalidStreamOper { index: 1 }));
+    stream.verify_stream(&profile_keypair.public).unwrap();
+  }
+
+  #[test]
+  fn hash_links() {
+    let mut csprng = OsRng{};
+    let profile_keypair: Keypair = Keypair::generate(&mut csprng);
+
+    let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+    let mut stream = Stream::new(&profile_keypair, "udi-random", "r-random", &[], genesis, None).unwrap();
+    for info in [b"New info!", b"Old info!"].iter() {
+      let block = StreamBlock::new(&profile_keypair, Record { oper: OType::SET, info: info.to_vec() }, stream.prev()).unwrap();
+      stream.save(block).unwrap();
+    }
+
+    // the links don't depend on the signatures
+    let mut forged = stream.clone();
+    forged.blocks[0].sig = forged.blocks[1].sig;
+    forged.verify_links().unwrap();
+    assert!(forged.verify_stream(&profile_keypair.public) == Err(Error::InvalidBlock { index: 0 }));
+
+    // a changed block breaks the link of the next one
+    let mut forged = stream.clone();
+    forged.blocks[0].record.info = b"Other info!".to_vec();
+    let (expected, found) = (forged.blocks[0].hash(), forged.blocks[1].prev);
+    assert!(forged.verify_links() == Err(Error::InvalidBlockChain { index: 1, expected, found }));
+    assert!(Chain::new(forged.clone()).verify_links() == Err(Error::InvalidBlockChain { index: 1, expected, found }));
+    assert!(Chain::new(forged).check(&profile_keypair.public) == Err(Error::InvalidBlockChain { index: 1, expected, found }));
+
+    // the accumulator must match the blocks
+    let mut forged = stream.clone();
+    forged.acc = stream.without_blocks().acc;
+    assert!(forged.verify_links() == Err(Error::InvalidAccumulator));
+    let mut forged = stream.clone();
+    let block = StreamBlock::new(&profile_keypair, Record { oper: OType::DEL, info: Vec::new() }, stream.prev()).unwrap();
+    forged.blocks.push(block.clone());
+    assert!(forged.verify_links() == Err(Error::InvalidAccumulator));
+
+    // a received stream whose accumulator doesn't cover its blocks can't be extended
+    let mut forged = stream.clone();
+    forged.acc = stream.without_blocks().acc;
+    assert!(forged.save(block) == Err(Error::InvalidAccumulator));
+
+    // received streams keep their accumulator
+    let loaded: Stream = cbor::from_slice(&cbor::to_vec(&stream), "stream").unwrap();
+    assert!(loaded.accumulator() == stream.accumulator() && loaded.accumulator().size() == 2);
+    loaded.verify_links().unwrap();
   }
 
   #[test]
@@ -316,21 +716,21 @@ mod tests {
 
     // stream-1
     let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
-    let mut stream1 = Stream::new(&keypair1, udi, r1, &vec![master], genesis, None);
+    let mut stream1 = Stream::new(&keypair1, udi, r1, &[master], genesis, None).unwrap();
 
         // add block to stream
         let record = Record { oper: OType::SET, info: b"New info!".to_vec() };
-        let block = StreamBlock::new(&keypair1, record, &stream1.sig);
+        let block = StreamBlock::new(&keypair1, record, stream1.hash()).unwrap();
         stream1.save(block).unwrap();
 
     // stream-2
     let ext_renew = ExtRenew {
-      renew: Renew::new(&m_keypair, &keypair2.public, stream1.prev(), true),
-      key: keypair1.public.clone()
+      renew: Renew::new(&m_keypair, &keypair2.public, stream1.prev(), true).unwrap(),
+      key: keypair1.public
     };
 
     let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
-    let stream2 = Stream::new(&keypair2, udi, r2, &vec![], genesis, Some(ext_renew));
+    let stream2 = Stream::new(&keypair2, udi, r2, &[], genesis, Some(ext_renew)).unwrap();
 
     // create and check chain
     let mut chain = Chain::new(stream1);
@@ -338,5 +738,76 @@ mod tests {
 
     // check chain (verify all signatures, master groups and renew blocks)
     chain.check(&keypair2.public).unwrap();
+    chain.verify_links().unwrap();
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn chain_api() {
+    let mut csprng = OsRng{};
+    let m_keypair: Keypair = Keypair::generate(&mut csprng);
+    let master = TLGroup::new(TLType::MASTER, &m_keypair.public);
+
+    // chain of 3 streams with 1, 2 and 3 blocks
+    let mut keys: Vec<Keypair> = Vec::new();
+    let mut chain: Option<Chain> = None;
+    for index in 0..3 {
+      let keypair: Keypair = Keypair::generate(&mut csprng);
+      let genesis = Record { oper: OType::SET, info: b"Not important!".to_vec() };
+      let renew = chain.as_ref().map(|ch| {
+        ExtRenew::new(Renew::new(&m_keypair, &keypair.public, ch.current().prev(), true).unwrap(), &keys[index - 1].public)
+      });
+
+      let mut stream = Stream::new(&keypair, "udi-random", &format!("r{}", index), std::slice::from_ref(&master), genesis, renew).unwrap();
+      for _ in 0..=index {
+        let block = StreamBlock::new(&keypair, Record { oper: OType::SET, info: b"New info!".to_vec() }, stream.prev()).unwrap();
+        stream.save(block).unwrap();
+      }
+
+      match chain.as_mut() {
+        None => chain = Some(Chain::new(stream)),
+        Some(ch) => ch.save(stream).unwrap()
+      }
+
+      keys.push(keypair);
+    }
+
+    let chain = chain.unwrap();
+    let key = &keys[2].public;
+    assert!(chain.len() == 3 && chain.blocks() == 6);
+    assert!(chain.iter().count() == 3 && (&chain).into_iter().map(|st| st.blocks.len()).collect::<Vec<_>>() == vec![1, 2, 3]);
+
+    let asi = chain.streams()[1].asi.clone();
+    assert!(chain.get(&asi).unwrap().blocks.len() == 2 && chain.position(&asi) == Some(1));
+    assert!(chain.get("unknown").is_none());
+
+    // serialized and re-verified
+    let loaded = Chain::from_bytes(&chain.to_bytes(), key).unwrap();
+    assert!(loaded.current().asi == chain.current().asi);
+    assert!(Chain::from_bytes(&chain.to_bytes(), &keys[1].public).is_err());
+
+    // an empty chain can't be decoded
+    let empty: BTreeMap<&str, Vec<Stream>> = vec![("chain", Vec::new())].into_iter().collect();
+    assert!(Chain::from_bytes(&cbor::to_vec(&empty), key).is_err());
+    assert!(bincode::deserialize::<Chain>(&bincode::serialize(&empty["chain"]).unwrap()).is_err());
+    assert!(bincode::deserialize::<Chain>(&bincode::serialize(&chain).unwrap()).unwrap().len() == 3);
+
+    // pagination
+    let pages = vec![chain.page(0, 2), chain.page(2, 2)];
+    assert!(pages[0].streams.len() == 2 && pages[1].streams.len() == 1 && pages[1].total == 3);
+    assert!(chain.page(5, 2).streams.is_empty());
+    let loaded = Chain::from_pages(pages.clone(), key).unwrap();
+    assert!(loaded.len() == 3);
+
+    assert!(Chain::from_pages(vec![pages[0].clone()], key).err() == Some(Error::InvalidPage { offset: 2 }));
+    assert!(Chain::from_pages(vec![pages[1].clone(), pages[0].clone()], key).err() == Some(Error::InvalidPage { offset: 2 }));
+    assert!(Chain::from_pages(Vec::new(), key).err() == Some(Error::ChainInvalidEnd));
+
+    // a page with a huge total is rejected without allocating it
+    let mut huge = pages.clone();
+    huge.iter_mut().for_each(|pg| pg.total = usize::MAX);
+    assert!(Chain::from_pages(huge, key).err() == Some(Error::InvalidPage { offset: 3 }));
+    let mut short = pages;
+    short.iter_mut().for_each(|pg| pg.total = 2);
+    assert!(Chain::from_pages(short, key).err() == Some(Error::InvalidPage { offset: 2 }));
+  }
+}